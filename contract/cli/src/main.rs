@@ -1,3 +1,25 @@
+use solana_pam_shared::error::describe_error_code;
+use std::{env, process};
+
+fn usage() -> ! {
+    eprintln!("Usage: cli decode-error <code>");
+    process::exit(1);
+}
+
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["decode-error", code] => {
+            // Accept both `112` and the `0x70` form printed in transaction logs
+            let code = match code.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => code.parse(),
+            };
+            match code {
+                Ok(code) => println!("{}", describe_error_code(code)),
+                Err(_) => usage(),
+            }
+        }
+        _ => usage(),
+    }
 }
@@ -2,8 +2,10 @@
 
 #![cfg(not(feature = "no-entrypoint"))]
 
+use solana_pam_shared::error::SolanaPamError;
 use solana_program::{
-    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, pubkey::Pubkey,
+    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult,
+    program_error::PrintProgramError, pubkey::Pubkey,
 };
 
 entrypoint!(process_instruction);
@@ -12,5 +14,12 @@ fn process_instruction(
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    crate::processor::process_instruction(program_id, accounts, instruction_data)
+    if let Err(error) =
+        crate::processor::process_instruction(program_id, accounts, instruction_data)
+    {
+        // Log the readable form of the error before returning its code
+        error.print::<SolanaPamError>();
+        return Err(error);
+    }
+    Ok(())
 }
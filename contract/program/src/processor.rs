@@ -1,231 +1,1790 @@
 //! Program state processor
+use solana_pam_shared::error::SolanaPamError;
+use solana_pam_shared::events::Event;
 use solana_pam_shared::instructions::{
-    pack_user_access_list, unpack_user_access_list, user_access_list_add_pk,
-    user_access_list_remove_pk, ProgInstruction, ProgramData, UserAccessList,
+    find_access_list_address, find_account_policy_address, find_delegate_address,
+    find_group_address, find_host_address, find_loader_data_address, find_login_log_address,
+    find_multisig_address, find_proposal_address, find_revocation_address, find_username_address,
+    read_upgrade_authority, ProgInstruction, ProgramData, ACCESS_LIST_SEED, ACCOUNT_POLICY_SEED,
+    DELEGATE_SEED, GROUP_SEED, HOST_SEED, LOGIN_LOG_SEED, MULTISIG_SEED, PROPOSAL_SEED,
+    REVOCATION_SEED, USERNAME_SEED,
 };
-use std::ops::{Deref, DerefMut};
-use std::rc::Rc;
+use solana_pam_shared::state::{
+    validate_bulk_keys, AccessList, AccountPolicy, Delegate, Group, GroupMember, HostAccount,
+    LoginLog, LoginRecord, Multisig, Proposal, ProposalAction, Revocation, Role, UsernameAccount,
+    ACCESS_LIST_SIZE, ACCOUNT_POLICY_SIZE, DELEGATE_ADD, DELEGATE_ALL_RIGHTS,
+    DELEGATE_MANAGE_EXPIRY, DELEGATE_MANAGE_ROLES, DELEGATE_REMOVE, DELEGATE_SIZE, GROUP_SIZE,
+    HOST_ACCOUNT_SIZE, LOGIN_LOG_SIZE, MAX_SERVICE_NAME_LEN, MULTISIG_SIZE, PROPOSAL_SIZE,
+    REVOCATION_SIZE, USERNAME_ACCOUNT_SIZE,
+};
+use std::ops::DerefMut;
 
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable,
+    clock::Clock,
     entrypoint::ProgramResult,
     msg,
+    program::invoke_signed,
     program_error::ProgramError,
     pubkey::Pubkey,
+    rent::Rent,
+    system_instruction, system_program,
+    sysvar::Sysvar,
 };
 
-fn process_change_access_list_account(
-    program_account: &AccountInfo,
+/// Apply `change` to an access list on behalf of `signer` and write it back
+///
+/// The signer is the list's owner, a key with an admin entry on the list, or a
+/// delegate whose account is passed as `delegate_account`. Every signer, the
+/// owner included, also passes their `revocation_account`, and is turned away
+/// once revoked, so a stolen owner key cannot grant the thief another key.
+/// `change` gets the delegate, if any, to check its rights; an admin counts as
+/// a delegate with every right.
+fn process_change_access_list_account<F>(
+    program_id: &Pubkey,
+    signer: &Pubkey,
     access_list_account: &AccountInfo,
-    pk: Pubkey,
-    add: bool,
+    delegate_account: Option<&AccountInfo>,
+    revocation_account: Option<&AccountInfo>,
+    change: F,
+) -> ProgramResult
+where
+    F: FnOnce(&mut AccessList, Option<&Delegate>) -> ProgramResult,
+{
+    if access_list_account.owner != program_id {
+        return Err(SolanaPamError::NotAccessListOwner.into());
+    }
+    let mut access_list_data = access_list_account.try_borrow_mut_data()?;
+    let mut access_list = AccessList::unpack(&access_list_data)?;
+    let delegate = if access_list.owner == *signer {
+        None
+    } else if access_list.is_admin(signer, Clock::get()?.unix_timestamp) {
+        Some(Delegate::new(
+            *access_list_account.key,
+            *signer,
+            DELEGATE_ALL_RIGHTS,
+        )?)
+    } else {
+        let delegate_account = delegate_account.ok_or(SolanaPamError::NotAccessListOwner)?;
+        Some(load_delegate(
+            program_id,
+            access_list_account.key,
+            signer,
+            delegate_account,
+        )?)
+    };
+    let revocation_account = revocation_account.ok_or(ProgramError::NotEnoughAccountKeys)?;
+    check_not_revoked(program_id, signer, revocation_account)?;
+    if access_list.multisig.is_some() {
+        return Err(SolanaPamError::GovernedByMultisig.into());
+    }
+    change(&mut access_list, delegate.as_ref())?;
+    if delegate.is_some() {
+        msg!(
+            "Access list {} changed by {} on its owner's behalf",
+            access_list_account.key,
+            signer
+        );
+    }
+    access_list.write(access_list_data.deref_mut())
+}
+
+/// Load `delegate`'s rights over the list at `access_list`
+fn load_delegate(
+    program_id: &Pubkey,
+    access_list: &Pubkey,
+    delegate: &Pubkey,
+    delegate_account: &AccountInfo,
+) -> Result<Delegate, ProgramError> {
+    if *delegate_account.key != find_delegate_address(program_id, access_list, delegate).0 {
+        return Err(SolanaPamError::WrongDelegateAddress.into());
+    }
+    // A revoked delegate's account is gone, leaving the signer a stranger
+    if delegate_account.owner != program_id {
+        return Err(SolanaPamError::NotAccessListOwner.into());
+    }
+    let record = Delegate::unpack(&delegate_account.try_borrow_data()?)?;
+    if record.access_list != *access_list || record.delegate != *delegate {
+        return Err(SolanaPamError::WrongDelegateAddress.into());
+    }
+    Ok(record)
+}
+
+/// Whether `pubkey` has been revoked, going by `revocation_account`, which
+/// must be at the key's revocation address
+fn is_revoked(
+    program_id: &Pubkey,
+    pubkey: &Pubkey,
+    revocation_account: &AccountInfo,
+) -> Result<bool, ProgramError> {
+    if *revocation_account.key != find_revocation_address(program_id, pubkey).0 {
+        return Err(SolanaPamError::WrongRevocationAddress.into());
+    }
+    // Only `RevokeKey` creates accounts at the address
+    Ok(revocation_account.owner == program_id)
+}
+
+/// Fail if `signer` has been revoked, going by `revocation_account`
+fn check_not_revoked(
+    program_id: &Pubkey,
+    signer: &Pubkey,
+    revocation_account: &AccountInfo,
 ) -> ProgramResult {
-    let mut access_list_data = access_list_account.data.borrow_mut();
-    let access_list = unpack_user_access_list(access_list_data.deref_mut()).unwrap();
-    if add {
-        user_access_list_add_pk(access_list, pk)
+    if is_revoked(program_id, signer, revocation_account)? {
+        return Err(SolanaPamError::SignerRevoked.into());
+    }
+    Ok(())
+}
+
+/// Fail unless `delegate`, when the change is made by one, holds `rights`
+fn check_delegate_rights(delegate: Option<&Delegate>, rights: u8) -> ProgramResult {
+    delegate.map_or(Ok(()), |delegate| delegate.check_rights(rights))
+}
+
+/// Whether changing a role from `from` to `to` makes or unmakes an admin,
+/// which acts with every delegate right and so needs all of them to appoint
+fn touches_admin(from: Role, to: Role) -> bool {
+    from != to && (from == Role::Admin || to == Role::Admin)
+}
+
+/// The rights a delegate needs to remove `keys` from `list`, which include
+/// `DELEGATE_ADD` when dropping a deny entry lets one of them back in
+fn removal_rights(list: &AccessList, keys: &[Pubkey]) -> u8 {
+    if keys.iter().any(|key| list.find(key, true).is_some()) {
+        DELEGATE_ADD | DELEGATE_REMOVE
     } else {
-        user_access_list_remove_pk(access_list, pk)
+        DELEGATE_REMOVE
     }
 }
 
-fn process_init_access_list(
-    mut program_data: ProgramData,
+/// Check that `access_list_account` is `user`'s list and not run by a multisig
+fn check_list_owner(
+    program_id: &Pubkey,
+    user: &Pubkey,
     access_list_account: &AccountInfo,
-    signer: &Pubkey,
 ) -> ProgramResult {
-    let mut access_list_data = access_list_account.data.borrow_mut();
-    let data = access_list_data.deref_mut();
-    data.clone_from_slice(pack_user_access_list(Vec::new()).as_slice());
-    // let access_list = unpack_user_access_list(access_list_data.deref_mut()).unwrap();
-    // msg!("New Access List: {:?}", access_list);
-    program_data
-        .user_access_map
-        .insert(signer.to_bytes(), access_list_account.key.to_bytes());
+    if access_list_account.owner != program_id {
+        return Err(SolanaPamError::NotAccessListOwner.into());
+    }
+    let access_list = AccessList::unpack(&access_list_account.try_borrow_data()?)?;
+    if access_list.owner != *user {
+        return Err(SolanaPamError::NotAccessListOwner.into());
+    }
+    if access_list.multisig.is_some() {
+        return Err(SolanaPamError::GovernedByMultisig.into());
+    }
     Ok(())
 }
 
-fn process_init(mut program_account: &AccountInfo) -> ProgramResult {
-    msg!("PROCESSING INIT");
-    let mut new_state = ProgramData::new();
-    msg!("PROCESSING INIT 2");
-    let new_state_vec = &new_state.try_to_vec().map_err(|e| {
-        msg!("{}", e.to_string());
-        e
-    })?;
-    msg!("PROCESSING INIT 2.5");
-    program_account.try_borrow_mut_data()?.copy_from_slice(new_state_vec);
-    msg!("PROCESSING INIT 3");
+fn process_set_delegate<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    user: &AccountInfo<'a>,
+    access_list_account: &AccountInfo<'a>,
+    delegate_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    delegate_state: Delegate,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    check_list_owner(program_id, user.key, access_list_account)?;
+    let delegate = delegate_state.delegate;
+    let (expected, bump) = find_delegate_address(program_id, access_list_account.key, &delegate);
+    if *delegate_account.key != expected {
+        return Err(SolanaPamError::WrongDelegateAddress.into());
+    }
+
+    if delegate_account.owner == program_id {
+        let mut data = delegate_account.try_borrow_mut_data()?;
+        let mut record = Delegate::unpack(&data)?;
+        record.set_rights(delegate_state.rights)?;
+        record.write(data.deref_mut())?;
+        emit_delegate_set(&record);
+        return Ok(());
+    }
+    create_program_account(
+        program_id,
+        user,
+        delegate_account,
+        system_program_account,
+        DELEGATE_SIZE,
+        &[
+            DELEGATE_SEED,
+            access_list_account.key.as_ref(),
+            delegate.as_ref(),
+            &[bump],
+        ],
+    )?;
+    delegate_state.write(delegate_account.try_borrow_mut_data()?.deref_mut())?;
+    emit_delegate_set(&delegate_state);
     Ok(())
 }
 
-fn get_user_access_list_pk<'a>(
-    address: &Pubkey,
-    program_data: &'a ProgramData,
-) -> Option<&'a [u8; 32]> {
-    program_data.user_access_map.get(&address.to_bytes())
+fn emit_delegate_set(delegate: &Delegate) {
+    Event::DelegateSet {
+        access_list: delegate.access_list,
+        delegate: delegate.delegate,
+        rights: delegate.rights,
+    }
+    .emit()
 }
 
-fn user_matches_access_list<'a>(
-    access_list: &Pubkey,
+fn process_revoke_delegate(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    user: &AccountInfo,
+    access_list_account: &AccountInfo,
+    delegate_account: &AccountInfo,
+    delegate: &Pubkey,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    check_list_owner(program_id, user.key, access_list_account)?;
+    load_delegate(
+        program_id,
+        access_list_account.key,
+        delegate,
+        delegate_account,
+    )?;
+    close_program_account(delegate_account, user)?;
+    Event::DelegateRevoked {
+        access_list: *access_list_account.key,
+        delegate: *delegate,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_close_access_list(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    user: &Pubkey,
+    access_list_account: &AccountInfo,
+    recipient: &AccountInfo,
+) -> ProgramResult {
+    let mut program_data = load_initialized_program_data(program_id, program_account)?;
+    check_list_owner(program_id, user, access_list_account)?;
+    // Paying the list to itself would keep it alive
+    if recipient.key == access_list_account.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    close_program_account(access_list_account, recipient)?;
+    AccessList::close(access_list_account.try_borrow_mut_data()?.deref_mut())?;
+    // A list created before lists moved to derived addresses would otherwise
+    // leave the map pointing at a deleted account
+    if program_data.remove(user, access_list_account.key) {
+        store_program_data(&program_data, program_account)?;
+    }
+    Event::AccessListClosed {
+        owner: *user,
+        access_list: *access_list_account.key,
+    }
+    .emit();
+    Ok(())
+}
+
+/// Empty a program account and move its lamports to `recipient`
+fn close_program_account(account: &AccountInfo, recipient: &AccountInfo) -> ProgramResult {
+    // An account left with no lamports is removed once the transaction ends
+    let lamports = account.lamports();
+    **account.try_borrow_mut_lamports()? = 0;
+    **recipient.try_borrow_mut_lamports()? += lamports;
+    for byte in account.try_borrow_mut_data()?.iter_mut() {
+        *byte = 0;
+    }
+    Ok(())
+}
+
+fn process_migrate_access_list(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
     user: &Pubkey,
-    program_data: ProgramData,
-) -> bool {
-    if let Some(access_list_from_prog) = get_user_access_list_pk(user, &program_data) {
-        if access_list
-            .to_bytes()
-            .iter()
-            .zip(access_list_from_prog)
-            .all(|(a, b)| a == b)
-        {
-            return true;
+    access_list_account: &AccountInfo,
+) -> ProgramResult {
+    if access_list_account.owner != program_id {
+        return Err(SolanaPamError::NotAccessListOwner.into());
+    }
+    if AccessList::has_header(&access_list_account.try_borrow_data()?) {
+        return Ok(());
+    }
+    // Headerless lists do not record their owner, so the address has to
+    // vouch for it
+    let at_derived_address =
+        *access_list_account.key == find_access_list_address(program_id, user).0;
+    if !at_derived_address {
+        let recorded = load_initialized_program_data(program_id, program_account)?
+            .user_access_map
+            .get(&user.to_bytes())
+            .map(|k| Pubkey::new_from_array(*k));
+        if recorded != Some(*access_list_account.key) {
+            return Err(SolanaPamError::WrongAccessListAddress.into());
+        }
+    }
+    let mut access_list_data = access_list_account.try_borrow_mut_data()?;
+    AccessList::migrate(&access_list_data, *user)?.write(access_list_data.deref_mut())?;
+    Event::AccessListMigrated {
+        owner: *user,
+        access_list: *access_list_account.key,
+    }
+    .emit();
+    Ok(())
+}
+
+/// Read the program state, which may be followed by unused space in the account
+fn load_program_data(program_account: &AccountInfo) -> Result<ProgramData, ProgramError> {
+    let data = program_account.try_borrow_data()?;
+    ProgramData::deserialize(&mut data.as_ref())
+        .map_err(|_| SolanaPamError::MalformedProgramData.into())
+}
+
+/// Check that the program state account is ours and will not be purged for rent
+fn check_program_account(program_id: &Pubkey, program_account: &AccountInfo) -> ProgramResult {
+    if program_account.owner != program_id {
+        return Err(SolanaPamError::WrongProgramDataOwner.into());
+    }
+    if !Rent::get()?.is_exempt(program_account.lamports(), program_account.data_len()) {
+        return Err(SolanaPamError::NotRentExempt.into());
+    }
+    Ok(())
+}
+
+fn load_initialized_program_data(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+) -> Result<ProgramData, ProgramError> {
+    check_program_account(program_id, program_account)?;
+    let program_data = load_program_data(program_account)?;
+    if !program_data.is_initialized {
+        return Err(SolanaPamError::UninitializedProgramData.into());
+    }
+    Ok(program_data)
+}
+
+fn store_program_data(program_data: &ProgramData, program_account: &AccountInfo) -> ProgramResult {
+    let mut data = program_account.try_borrow_mut_data()?;
+    let mut output: &mut [u8] = data.deref_mut();
+    program_data
+        .serialize(&mut output)
+        .map_err(|_| SolanaPamError::ProgramDataFull.into())
+}
+
+/// Create a rent-exempt account owned by the program at a program-derived address
+fn create_program_account<'a>(
+    program_id: &Pubkey,
+    payer: &AccountInfo<'a>,
+    new_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    size: usize,
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    if *system_program_account.key != system_program::id() {
+        return Err(SolanaPamError::WrongSystemProgram.into());
+    }
+    let lamports = Rent::get()?.minimum_balance(size);
+    invoke_signed(
+        &system_instruction::create_account(
+            payer.key,
+            new_account.key,
+            lamports,
+            size as u64,
+            program_id,
+        ),
+        &[
+            payer.clone(),
+            new_account.clone(),
+            system_program_account.clone(),
+        ],
+        &[seeds],
+    )
+}
+
+fn process_init_access_list<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    user: &AccountInfo<'a>,
+    access_list_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+) -> ProgramResult {
+    let (expected, bump) = find_access_list_address(program_id, user.key);
+    if *access_list_account.key != expected {
+        return Err(SolanaPamError::WrongAccessListAddress.into());
+    }
+    // The list's address is all that records it, the program state's map is
+    // only read for lists created before lists moved to derived addresses
+    load_initialized_program_data(program_id, program_account)?;
+
+    create_program_account(
+        program_id,
+        user,
+        access_list_account,
+        system_program_account,
+        ACCESS_LIST_SIZE,
+        &[ACCESS_LIST_SEED, user.key.as_ref(), &[bump]],
+    )?;
+    AccessList::new(*user.key).write(access_list_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::AccessListCreated {
+        owner: *user.key,
+        access_list: *access_list_account.key,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_register_host<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    host_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    host_state: HostAccount,
+) -> ProgramResult {
+    let program_data = load_initialized_program_data(program_id, program_account)?;
+    if program_data.authority != *authority.key {
+        return Err(SolanaPamError::NotAuthority.into());
+    }
+    let host = host_state.host;
+    let (expected, bump) = find_host_address(program_id, &host);
+    if *host_account.key != expected {
+        return Err(SolanaPamError::WrongHostAddress.into());
+    }
+
+    create_program_account(
+        program_id,
+        authority,
+        host_account,
+        system_program_account,
+        HOST_ACCOUNT_SIZE,
+        &[HOST_SEED, host.as_ref(), &[bump]],
+    )?;
+    host_state.write(host_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::HostRegistered {
+        host,
+        label: host_state.label,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_revoke_key<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    revocation_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    revocation: Revocation,
+) -> ProgramResult {
+    let program_data = load_initialized_program_data(program_id, program_account)?;
+    if revocation.revoked_by != program_data.authority && revocation.revoked_by != revocation.pubkey
+    {
+        return Err(SolanaPamError::NotRevoker.into());
+    }
+    let pubkey = revocation.pubkey;
+    let (expected, bump) = find_revocation_address(program_id, &pubkey);
+    if *revocation_account.key != expected {
+        return Err(SolanaPamError::WrongRevocationAddress.into());
+    }
+    if revocation_account.owner == program_id {
+        return Ok(());
+    }
+
+    create_program_account(
+        program_id,
+        payer,
+        revocation_account,
+        system_program_account,
+        REVOCATION_SIZE,
+        &[REVOCATION_SEED, pubkey.as_ref(), &[bump]],
+    )?;
+    revocation.write(revocation_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::KeyRevoked {
+        pubkey,
+        revoked_by: revocation.revoked_by,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_record_login<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    host: &AccountInfo<'a>,
+    host_account: &AccountInfo<'a>,
+    login_log_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    record: LoginRecord,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    check_registered_host(program_id, host.key, host_account)?;
+    let (expected, bump) = find_login_log_address(program_id, host.key);
+    if *login_log_account.key != expected {
+        return Err(SolanaPamError::WrongLoginLogAddress.into());
+    }
+
+    let mut login_log = if login_log_account.owner == program_id {
+        let login_log = LoginLog::unpack(&login_log_account.try_borrow_data()?)?;
+        if login_log.host != *host.key {
+            return Err(SolanaPamError::MalformedLoginLog.into());
+        }
+        login_log
+    } else {
+        create_program_account(
+            program_id,
+            host,
+            login_log_account,
+            system_program_account,
+            LOGIN_LOG_SIZE,
+            &[LOGIN_LOG_SEED, host.key.as_ref(), &[bump]],
+        )?;
+        LoginLog::new(*host.key)
+    };
+    let event = Event::LoginRecorded {
+        host: *host.key,
+        user: record.user,
+        service: record.service.clone(),
+    };
+    login_log.record(record);
+    login_log.write(login_log_account.try_borrow_mut_data()?.deref_mut())?;
+    event.emit();
+    Ok(())
+}
+
+/// Check that `host_account` is the registration of the host key `host`
+fn check_registered_host(
+    program_id: &Pubkey,
+    host: &Pubkey,
+    host_account: &AccountInfo,
+) -> ProgramResult {
+    if *host_account.key != find_host_address(program_id, host).0 {
+        return Err(SolanaPamError::WrongHostAddress.into());
+    }
+    if host_account.owner != program_id {
+        return Err(SolanaPamError::HostNotRegistered.into());
+    }
+    HostAccount::unpack(&host_account.try_borrow_data()?)?;
+    Ok(())
+}
+
+fn process_record_session(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    host: &Pubkey,
+    host_account: &AccountInfo,
+    user: Pubkey,
+    service: String,
+    opened_at: Option<i64>,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    check_registered_host(program_id, host, host_account)?;
+    if service.len() > MAX_SERVICE_NAME_LEN {
+        return Err(SolanaPamError::InvalidLoginRecord.into());
+    }
+    match opened_at {
+        None => Event::SessionOpened {
+            host: *host,
+            user,
+            service,
+        },
+        Some(opened_at) => Event::SessionClosed {
+            host: *host,
+            user,
+            service,
+            opened_at,
+            closed_at: Clock::get()?.unix_timestamp,
+        },
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_claim_username<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    user: &AccountInfo<'a>,
+    username_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    username_state: UsernameAccount,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    let username = username_state.username.as_bytes();
+    let (expected, bump) = find_username_address(program_id, &username_state.username);
+    if *username_account.key != expected {
+        return Err(SolanaPamError::WrongUsernameAddress.into());
+    }
+    if username_account.owner == program_id {
+        return Err(SolanaPamError::UsernameTaken.into());
+    }
+
+    create_program_account(
+        program_id,
+        user,
+        username_account,
+        system_program_account,
+        USERNAME_ACCOUNT_SIZE,
+        &[USERNAME_SEED, username, &[bump]],
+    )?;
+    username_state.write(username_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::UsernameClaimed {
+        username: username_state.username,
+        owner: username_state.owner,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_release_username(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    user: &AccountInfo,
+    username_account: &AccountInfo,
+    username: &str,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    if *username_account.key != find_username_address(program_id, username).0 {
+        return Err(SolanaPamError::WrongUsernameAddress.into());
+    }
+    if username_account.owner != program_id {
+        return Err(SolanaPamError::NotUsernameOwner.into());
+    }
+    let record = UsernameAccount::unpack(&username_account.try_borrow_data()?)?;
+    if record.owner != *user.key {
+        return Err(SolanaPamError::NotUsernameOwner.into());
+    }
+    close_program_account(username_account, user)?;
+    Event::UsernameReleased {
+        username: record.username,
+        owner: record.owner,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_init_account_policy<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    policy_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    policy: AccountPolicy,
+) -> ProgramResult {
+    let program_data = load_initialized_program_data(program_id, program_account)?;
+    if program_data.authority != *authority.key {
+        return Err(SolanaPamError::NotAuthority.into());
+    }
+    let (expected, bump) = find_account_policy_address(program_id, &policy.account);
+    if *policy_account.key != expected {
+        return Err(SolanaPamError::WrongAccountPolicyAddress.into());
+    }
+
+    create_program_account(
+        program_id,
+        authority,
+        policy_account,
+        system_program_account,
+        ACCOUNT_POLICY_SIZE,
+        &[ACCOUNT_POLICY_SEED, policy.account.as_bytes(), &[bump]],
+    )?;
+    policy.write(policy_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::AccountPolicyCreated {
+        account: policy.account,
+        admin: policy.admin,
+    }
+    .emit();
+    Ok(())
+}
+
+/// Apply `change` to the policy for unix account `account` and write it back,
+/// unless its admin has been revoked
+fn process_change_account_policy<F>(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    policy_account: &AccountInfo,
+    revocation_account: &AccountInfo,
+    account: &str,
+    change: F,
+) -> ProgramResult
+where
+    F: FnOnce(&mut AccountPolicy) -> ProgramResult,
+{
+    if *policy_account.key != find_account_policy_address(program_id, account).0 {
+        return Err(SolanaPamError::WrongAccountPolicyAddress.into());
+    }
+    if policy_account.owner != program_id {
+        return Err(SolanaPamError::MalformedAccountPolicy.into());
+    }
+    let mut policy_data = policy_account.try_borrow_mut_data()?;
+    let mut policy = AccountPolicy::unpack(&policy_data)?;
+    if policy.admin != *admin {
+        return Err(SolanaPamError::NotPolicyAdmin.into());
+    }
+    check_not_revoked(program_id, admin, revocation_account)?;
+    change(&mut policy)?;
+    policy.write(policy_data.deref_mut())
+}
+
+fn process_create_group<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    creator: &AccountInfo<'a>,
+    group_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    group: Group,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    let (expected, bump) = find_group_address(program_id, &group.name);
+    if *group_account.key != expected {
+        return Err(SolanaPamError::WrongGroupAddress.into());
+    }
+
+    create_program_account(
+        program_id,
+        creator,
+        group_account,
+        system_program_account,
+        GROUP_SIZE,
+        &[GROUP_SEED, group.name.as_bytes(), &[bump]],
+    )?;
+    group.write(group_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::GroupCreated {
+        group: *group_account.key,
+        name: group.name,
+    }
+    .emit();
+    Ok(())
+}
+
+/// Apply `change` to the group called `name` and write it back
+fn process_change_group<F>(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    group_account: &AccountInfo,
+    revocation_account: &AccountInfo,
+    name: &str,
+    change: F,
+) -> ProgramResult
+where
+    F: FnOnce(&mut Group) -> ProgramResult,
+{
+    if *group_account.key != find_group_address(program_id, name).0 {
+        return Err(SolanaPamError::WrongGroupAddress.into());
+    }
+    if group_account.owner != program_id {
+        return Err(SolanaPamError::MalformedGroup.into());
+    }
+    let mut group_data = group_account.try_borrow_mut_data()?;
+    let mut group = Group::unpack(&group_data)?;
+    if !group.is_admin(admin) {
+        return Err(SolanaPamError::NotGroupAdmin.into());
+    }
+    check_not_revoked(program_id, admin, revocation_account)?;
+    change(&mut group)?;
+    group.write(group_data.deref_mut())
+}
+
+fn load_multisig(
+    program_id: &Pubkey,
+    multisig_account: &AccountInfo,
+) -> Result<Multisig, ProgramError> {
+    if multisig_account.owner != program_id {
+        return Err(SolanaPamError::MalformedMultisig.into());
+    }
+    Multisig::unpack(&multisig_account.try_borrow_data()?)
+}
+
+/// Load a proposal of `multisig` that is still open, along with the index of
+/// `admin` among the multisig's admins
+fn load_open_proposal(
+    program_id: &Pubkey,
+    multisig_account: &AccountInfo,
+    multisig: &Multisig,
+    proposal_account: &AccountInfo,
+    admin: &Pubkey,
+) -> Result<(Proposal, usize), ProgramError> {
+    if proposal_account.owner != program_id {
+        return Err(SolanaPamError::MalformedProposal.into());
+    }
+    let proposal = Proposal::unpack(&proposal_account.try_borrow_data()?)?;
+    if proposal.multisig != *multisig_account.key {
+        return Err(SolanaPamError::WrongMultisigAddress.into());
+    }
+    if proposal.executed {
+        return Err(SolanaPamError::ProposalExecuted.into());
+    }
+    if proposal.generation != multisig.generation {
+        return Err(SolanaPamError::StaleProposal.into());
+    }
+    let admin_index = multisig
+        .admin_index(admin)
+        .ok_or(SolanaPamError::NotMultisigAdmin)?;
+    Ok((proposal, admin_index))
+}
+
+fn process_create_multisig<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    user: &AccountInfo<'a>,
+    access_list_account: &AccountInfo<'a>,
+    multisig_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    multisig: Multisig,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    if access_list_account.owner != program_id {
+        return Err(SolanaPamError::NotAccessListOwner.into());
+    }
+    let mut access_list = AccessList::unpack(&access_list_account.try_borrow_data()?)?;
+    if access_list.owner != *user.key {
+        return Err(SolanaPamError::NotAccessListOwner.into());
+    }
+    if access_list.multisig.is_some() {
+        return Err(SolanaPamError::GovernedByMultisig.into());
+    }
+    let (expected, bump) = find_multisig_address(program_id, access_list_account.key);
+    if *multisig_account.key != expected {
+        return Err(SolanaPamError::WrongMultisigAddress.into());
+    }
+
+    create_program_account(
+        program_id,
+        user,
+        multisig_account,
+        system_program_account,
+        MULTISIG_SIZE,
+        &[MULTISIG_SEED, access_list_account.key.as_ref(), &[bump]],
+    )?;
+    multisig.write(multisig_account.try_borrow_mut_data()?.deref_mut())?;
+    access_list.multisig = Some(*multisig_account.key);
+    access_list.write(access_list_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::MultisigCreated {
+        access_list: *access_list_account.key,
+        multisig: *multisig_account.key,
+        admins: multisig.admins,
+        threshold: multisig.threshold,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_propose<'a>(
+    program_id: &Pubkey,
+    program_account: &AccountInfo<'a>,
+    proposer: &AccountInfo<'a>,
+    multisig_account: &AccountInfo<'a>,
+    proposal_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    action: ProposalAction,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    let mut multisig = load_multisig(program_id, multisig_account)?;
+    let admin_index = multisig
+        .admin_index(proposer.key)
+        .ok_or(SolanaPamError::NotMultisigAdmin)?;
+    let index = multisig.proposal_count;
+    let (expected, bump) = find_proposal_address(program_id, multisig_account.key, index);
+    if *proposal_account.key != expected {
+        return Err(SolanaPamError::WrongProposalAddress.into());
+    }
+
+    create_program_account(
+        program_id,
+        proposer,
+        proposal_account,
+        system_program_account,
+        PROPOSAL_SIZE,
+        &[
+            PROPOSAL_SEED,
+            multisig_account.key.as_ref(),
+            &index.to_le_bytes(),
+            &[bump],
+        ],
+    )?;
+    let mut proposal = Proposal::new(*multisig_account.key, index, multisig.generation, action);
+    proposal.approve(admin_index);
+    proposal.write(proposal_account.try_borrow_mut_data()?.deref_mut())?;
+    multisig.proposal_count += 1;
+    multisig.write(multisig_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::ProposalCreated {
+        multisig: *multisig_account.key,
+        proposal: *proposal_account.key,
+        proposer: *proposer.key,
+        action: proposal.action,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_approve_proposal(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    admin: &Pubkey,
+    multisig_account: &AccountInfo,
+    proposal_account: &AccountInfo,
+    revocation_account: &AccountInfo,
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    let multisig = load_multisig(program_id, multisig_account)?;
+    let (mut proposal, admin_index) = load_open_proposal(
+        program_id,
+        multisig_account,
+        &multisig,
+        proposal_account,
+        admin,
+    )?;
+    check_not_revoked(program_id, admin, revocation_account)?;
+    proposal.approve(admin_index);
+    proposal.write(proposal_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::ProposalApproved {
+        proposal: *proposal_account.key,
+        admin: *admin,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_execute_proposal(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    admin: &Pubkey,
+    multisig_account: &AccountInfo,
+    proposal_account: &AccountInfo,
+    access_list_account: &AccountInfo,
+    revocation_accounts: &[AccountInfo],
+) -> ProgramResult {
+    load_initialized_program_data(program_id, program_account)?;
+    let mut multisig = load_multisig(program_id, multisig_account)?;
+    let (mut proposal, admin_index) = load_open_proposal(
+        program_id,
+        multisig_account,
+        &multisig,
+        proposal_account,
+        admin,
+    )?;
+    if revocation_accounts.len() < multisig.admins.len() {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    // Approvals from admins revoked since they gave them no longer count
+    let mut approvals = 0;
+    for (index, (key, revocation_account)) in
+        multisig.admins.iter().zip(revocation_accounts).enumerate()
+    {
+        if is_revoked(program_id, key, revocation_account)? {
+            if index == admin_index {
+                return Err(SolanaPamError::SignerRevoked.into());
+            }
+        } else if proposal.is_approved_by(index) {
+            approvals += 1;
         }
     }
-    false
+    if approvals < multisig.threshold as u32 {
+        return Err(SolanaPamError::ThresholdNotMet.into());
+    }
+    if *access_list_account.key != multisig.access_list || access_list_account.owner != program_id {
+        return Err(SolanaPamError::WrongAccessListAddress.into());
+    }
+    let mut access_list_data = access_list_account.try_borrow_mut_data()?;
+    let mut access_list = AccessList::unpack(&access_list_data)?;
+
+    match proposal.action.clone() {
+        ProposalAction::AddEntry(entry) => {
+            access_list.remove_expired(Clock::get()?.unix_timestamp);
+            access_list.add(entry);
+        }
+        ProposalAction::RemoveEntry(remove) => access_list.remove(&remove),
+        ProposalAction::SetMultisig { admins, threshold } => {
+            multisig.set_admins(admins, threshold)?;
+            multisig.write(multisig_account.try_borrow_mut_data()?.deref_mut())?;
+        }
+    }
+    access_list.write(access_list_data.deref_mut())?;
+    proposal.executed = true;
+    proposal.write(proposal_account.try_borrow_mut_data()?.deref_mut())?;
+    Event::ProposalExecuted {
+        proposal: *proposal_account.key,
+        action: proposal.action,
+    }
+    .emit();
+    Ok(())
+}
+
+/// Check that `authority` is the upgrade authority named by `loader_data`,
+/// the upgradeable loader's data account for the program
+fn check_upgrade_authority(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    loader_data: Option<&AccountInfo>,
+) -> ProgramResult {
+    let loader_data = loader_data.ok_or(SolanaPamError::NotUpgradeAuthority)?;
+    if *loader_data.key != find_loader_data_address(program_id).0
+        || *loader_data.owner != bpf_loader_upgradeable::id()
+    {
+        return Err(SolanaPamError::NotUpgradeAuthority.into());
+    }
+    if read_upgrade_authority(&loader_data.try_borrow_data()?) != Some(*authority) {
+        return Err(SolanaPamError::NotUpgradeAuthority.into());
+    }
+    Ok(())
+}
+
+fn process_init(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    authority: &Pubkey,
+    loader_data: Option<&AccountInfo>,
+) -> ProgramResult {
+    check_program_account(program_id, program_account)?;
+    // Accounts from before the authority existed load as uninitialized, so
+    // their map is kept
+    let mut program_data = load_program_data(program_account)?;
+    if program_data.is_initialized {
+        return Err(SolanaPamError::AlreadyInitialized.into());
+    }
+    // Whoever initializes the state gets to run the program, and for a state
+    // account holding lists, the lists, so only the one who deployed it may
+    check_upgrade_authority(program_id, authority, loader_data)?;
+    program_data.is_initialized = true;
+    program_data.authority = *authority;
+    store_program_data(&program_data, program_account)?;
+    Event::Initialized {
+        authority: *authority,
+    }
+    .emit();
+    Ok(())
+}
+
+fn process_set_authority(
+    program_id: &Pubkey,
+    program_account: &AccountInfo,
+    authority: &Pubkey,
+    new_authority: Pubkey,
+) -> ProgramResult {
+    let mut program_data = load_initialized_program_data(program_id, program_account)?;
+    if program_data.authority != *authority {
+        return Err(SolanaPamError::NotAuthority.into());
+    }
+    program_data.authority = new_authority;
+    store_program_data(&program_data, program_account)?;
+    Event::AuthorityChanged {
+        authority: new_authority,
+    }
+    .emit();
+    Ok(())
 }
 
 /// Instruction processor
 pub fn process_instruction(
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     accounts: &[AccountInfo],
     input: &[u8],
 ) -> ProgramResult {
+    // Decode before touching accounts so malformed data is reported as such
+    let instr = ProgInstruction::unpack(input)?;
     let account_info_iter = &mut accounts.iter();
-    let mut missing_required_signature = false;
     let program_account = next_account_info(account_info_iter)?;
-    // for account_info in account_info_iter {
-    //     if let Some(address) = account_info.signer_key() {
-    //         msg!("Signed by {:?}", address);
-    //     } else {
-    //         missing_required_signature = true;
-    //     }
-    // }
-    if missing_required_signature {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
-
-    let instr = ProgInstruction::unpack(input)?;
-    if instr == ProgInstruction::Init {
-        return process_init(program_account);
-    }
-    let program_data =
-        ProgramData::try_from_slice(program_account.try_borrow_data()?.as_ref()).unwrap();
-
     match instr {
+        ProgInstruction::Init => {
+            let authority = next_account_info(account_info_iter)?;
+            let loader_data = next_account_info(account_info_iter).ok();
+            if let Some(address) = authority.signer_key() {
+                process_init(program_id, program_account, address, loader_data)
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
         ProgInstruction::InitAccessList => {
-            let update = next_account_info(account_info_iter)?;
-            if let Some(address) = &update.signer_key() {
-                process_init_access_list(program_data, update, address)
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            if user.is_signer {
+                process_init_access_list(
+                    program_id,
+                    program_account,
+                    user,
+                    access_list,
+                    system_program_account,
+                )
             } else {
-                Err(ProgramError::Custom(111))
+                Err(SolanaPamError::MissingSigner.into())
             }
         }
-        ProgInstruction::Init => panic!("SHOULD NOT GET HERE"),
-        ProgInstruction::AddPKToAccessListAccount(add) => {
-            let update = next_account_info(account_info_iter)?;
-            if let Some(address) = update.signer_key() {
-                if user_matches_access_list(&update.key, address, program_data) {
-                    process_change_access_list_account(program_account, update, add, true)
-                } else {
-                    Err(ProgramError::Custom(112))
-                }
+        ProgInstruction::AddPKToAccessListAccount(entry) => {
+            entry.validate()?;
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate = next_account_info(account_info_iter).ok();
+            let revocation = next_account_info(account_info_iter).ok();
+            let event = Event::KeyAdded {
+                access_list: *access_list.key,
+                entry: entry.clone(),
+            };
+            if let Some(address) = user.signer_key() {
+                process_change_access_list_account(
+                    program_id,
+                    address,
+                    access_list,
+                    delegate,
+                    revocation,
+                    |list, delegate| {
+                        let listed = list.find(&entry.pubkey, entry.deny);
+                        let mut rights = match listed {
+                            // A new deny takes access away rather than granting it
+                            None if entry.deny => DELEGATE_REMOVE,
+                            None => DELEGATE_ADD,
+                            Some(listed) if listed.same_grant(&entry) => DELEGATE_MANAGE_EXPIRY,
+                            // Replacing the entry with a different one, such
+                            // as a deny with an allow, is a removal and an add
+                            Some(_) => DELEGATE_ADD | DELEGATE_REMOVE,
+                        };
+                        let role = listed.map_or(Role::Login, |e| e.role);
+                        if role != entry.role {
+                            rights |= DELEGATE_MANAGE_ROLES;
+                        }
+                        if touches_admin(role, entry.role) {
+                            rights = DELEGATE_ALL_RIGHTS;
+                        }
+                        check_delegate_rights(delegate, rights)?;
+                        // Make room by dropping grants that can never be used again
+                        list.remove_expired(Clock::get()?.unix_timestamp);
+                        list.add(entry);
+                        Ok(())
+                    },
+                )
+                .map(|()| event.emit())
             } else {
-                Err(ProgramError::Custom(111))
+                Err(SolanaPamError::MissingSigner.into())
             }
         }
         ProgInstruction::RemovePKToAccessListAccount(remove) => {
-            let update = next_account_info(account_info_iter)?;
-            if let Some(address) = update.signer_key() {
-                if user_matches_access_list(&update.key, address, program_data) {
-                    process_change_access_list_account(program_account, update, remove, false)
-                } else {
-                    Err(ProgramError::Custom(112))
-                }
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate = next_account_info(account_info_iter).ok();
+            let revocation = next_account_info(account_info_iter).ok();
+            if let Some(address) = user.signer_key() {
+                process_change_access_list_account(
+                    program_id,
+                    address,
+                    access_list,
+                    delegate,
+                    revocation,
+                    |list, delegate| {
+                        check_delegate_rights(delegate, removal_rights(list, &[remove]))?;
+                        list.remove(&remove);
+                        Ok(())
+                    },
+                )
+                .map(|()| {
+                    Event::KeyRemoved {
+                        access_list: *access_list.key,
+                        pubkey: remove,
+                    }
+                    .emit()
+                })
             } else {
-                Err(ProgramError::Custom(111))
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::AddManyToAccessList(keys) => {
+            validate_bulk_keys(&keys)?;
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate = next_account_info(account_info_iter).ok();
+            let revocation = next_account_info(account_info_iter).ok();
+            if let Some(address) = user.signer_key() {
+                process_change_access_list_account(
+                    program_id,
+                    address,
+                    access_list,
+                    delegate,
+                    revocation,
+                    |list, delegate| {
+                        check_delegate_rights(delegate, DELEGATE_ADD)?;
+                        list.remove_expired(Clock::get()?.unix_timestamp);
+                        list.add_many(&keys);
+                        Ok(())
+                    },
+                )
+                .map(|()| {
+                    Event::KeysAdded {
+                        access_list: *access_list.key,
+                        keys,
+                    }
+                    .emit()
+                })
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::RemoveManyFromAccessList(keys) => {
+            validate_bulk_keys(&keys)?;
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate = next_account_info(account_info_iter).ok();
+            let revocation = next_account_info(account_info_iter).ok();
+            if let Some(address) = user.signer_key() {
+                process_change_access_list_account(
+                    program_id,
+                    address,
+                    access_list,
+                    delegate,
+                    revocation,
+                    |list, delegate| {
+                        check_delegate_rights(delegate, removal_rights(list, &keys))?;
+                        list.remove_many(&keys);
+                        Ok(())
+                    },
+                )
+                .map(|()| {
+                    Event::KeysRemoved {
+                        access_list: *access_list.key,
+                        keys,
+                    }
+                    .emit()
+                })
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::ReplaceAccessList(keys) => {
+            validate_bulk_keys(&keys)?;
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate = next_account_info(account_info_iter).ok();
+            let revocation = next_account_info(account_info_iter).ok();
+            if let Some(address) = user.signer_key() {
+                process_change_access_list_account(
+                    program_id,
+                    address,
+                    access_list,
+                    delegate,
+                    revocation,
+                    |list, delegate| {
+                        check_delegate_rights(delegate, DELEGATE_ADD | DELEGATE_REMOVE)?;
+                        list.replace(&keys);
+                        Ok(())
+                    },
+                )
+                .map(|()| {
+                    Event::AccessListReplaced {
+                        access_list: *access_list.key,
+                        keys,
+                    }
+                    .emit()
+                })
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::SetEntryRole { pubkey, role } => {
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate = next_account_info(account_info_iter).ok();
+            let revocation = next_account_info(account_info_iter).ok();
+            if let Some(address) = user.signer_key() {
+                process_change_access_list_account(
+                    program_id,
+                    address,
+                    access_list,
+                    delegate,
+                    revocation,
+                    |list, delegate| {
+                        let listed = list.find(&pubkey, false).map_or(Role::Login, |e| e.role);
+                        check_delegate_rights(
+                            delegate,
+                            if touches_admin(listed, role) {
+                                DELEGATE_ALL_RIGHTS
+                            } else {
+                                DELEGATE_MANAGE_ROLES
+                            },
+                        )?;
+                        list.set_role(&pubkey, role)
+                    },
+                )
+                .map(|()| {
+                    Event::RoleChanged {
+                        access_list: *access_list.key,
+                        pubkey,
+                        role,
+                    }
+                    .emit()
+                })
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::MigrateAccessList => {
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            if let Some(address) = user.signer_key() {
+                process_migrate_access_list(program_id, program_account, address, access_list)
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::SetAuthority(new_authority) => {
+            let authority = next_account_info(account_info_iter)?;
+            if let Some(address) = authority.signer_key() {
+                process_set_authority(program_id, program_account, address, new_authority)
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::RegisterHost { label, tags } => {
+            let authority = next_account_info(account_info_iter)?;
+            let host = next_account_info(account_info_iter)?;
+            let host_account = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            match (authority.is_signer, host.signer_key()) {
+                (true, Some(host)) => process_register_host(
+                    program_id,
+                    program_account,
+                    authority,
+                    host_account,
+                    system_program_account,
+                    HostAccount::new(*host, label, tags)?,
+                ),
+                _ => Err(SolanaPamError::MissingSigner.into()),
+            }
+        }
+        ProgInstruction::ClaimUsername(username) => {
+            let user = next_account_info(account_info_iter)?;
+            let username_account = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            if let Some(address) = user.signer_key() {
+                process_claim_username(
+                    program_id,
+                    program_account,
+                    user,
+                    username_account,
+                    system_program_account,
+                    UsernameAccount::new(*address, username)?,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::ReleaseUsername(username) => {
+            let user = next_account_info(account_info_iter)?;
+            let username_account = next_account_info(account_info_iter)?;
+            if user.is_signer {
+                process_release_username(
+                    program_id,
+                    program_account,
+                    user,
+                    username_account,
+                    &username,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::InitAccountPolicy { account, admin } => {
+            let authority = next_account_info(account_info_iter)?;
+            let policy_account = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            if authority.is_signer {
+                process_init_account_policy(
+                    program_id,
+                    program_account,
+                    authority,
+                    policy_account,
+                    system_program_account,
+                    AccountPolicy::new(admin, account)?,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::AddToAccountPolicy { account, entry } => {
+            entry.validate()?;
+            let admin = next_account_info(account_info_iter)?;
+            let policy_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            let added = entry.clone();
+            if let Some(address) = admin.signer_key() {
+                process_change_account_policy(
+                    program_id,
+                    address,
+                    policy_account,
+                    revocation,
+                    &account,
+                    |policy| {
+                        policy.remove_expired(Clock::get()?.unix_timestamp);
+                        policy.add(entry);
+                        Ok(())
+                    },
+                )
+                .map(|()| {
+                    Event::AccountPolicyKeyAdded {
+                        account,
+                        entry: added,
+                    }
+                    .emit()
+                })
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::RemoveFromAccountPolicy { account, pubkey } => {
+            let admin = next_account_info(account_info_iter)?;
+            let policy_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            if let Some(address) = admin.signer_key() {
+                process_change_account_policy(
+                    program_id,
+                    address,
+                    policy_account,
+                    revocation,
+                    &account,
+                    |policy| {
+                        policy.remove(&pubkey);
+                        Ok(())
+                    },
+                )
+                .map(|()| Event::AccountPolicyKeyRemoved { account, pubkey }.emit())
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::CreateMultisig { admins, threshold } => {
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let multisig = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            if user.is_signer {
+                // A stolen owner key must not hand the list to the thief's keys
+                check_not_revoked(program_id, user.key, revocation)?;
+                process_create_multisig(
+                    program_id,
+                    program_account,
+                    user,
+                    access_list,
+                    multisig,
+                    system_program_account,
+                    Multisig::new(*access_list.key, admins, threshold)?,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::Propose(action) => {
+            action.validate()?;
+            let proposer = next_account_info(account_info_iter)?;
+            let multisig = next_account_info(account_info_iter)?;
+            let proposal = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            if proposer.is_signer {
+                check_not_revoked(program_id, proposer.key, revocation)?;
+                process_propose(
+                    program_id,
+                    program_account,
+                    proposer,
+                    multisig,
+                    proposal,
+                    system_program_account,
+                    action,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::ApproveProposal => {
+            let admin = next_account_info(account_info_iter)?;
+            let multisig = next_account_info(account_info_iter)?;
+            let proposal = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            if let Some(address) = admin.signer_key() {
+                process_approve_proposal(
+                    program_id,
+                    program_account,
+                    address,
+                    multisig,
+                    proposal,
+                    revocation,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::ExecuteProposal => {
+            let admin = next_account_info(account_info_iter)?;
+            let multisig = next_account_info(account_info_iter)?;
+            let proposal = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let revocations = account_info_iter.as_slice();
+            if let Some(address) = admin.signer_key() {
+                process_execute_proposal(
+                    program_id,
+                    program_account,
+                    address,
+                    multisig,
+                    proposal,
+                    access_list,
+                    revocations,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::SetDelegate { delegate, rights } => {
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate_account = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            if user.is_signer {
+                check_not_revoked(program_id, user.key, revocation)?;
+                let delegate_state = Delegate::new(*access_list.key, delegate, rights)?;
+                process_set_delegate(
+                    program_id,
+                    program_account,
+                    user,
+                    access_list,
+                    delegate_account,
+                    system_program_account,
+                    delegate_state,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::RevokeDelegate(delegate) => {
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let delegate_account = next_account_info(account_info_iter)?;
+            if user.is_signer {
+                process_revoke_delegate(
+                    program_id,
+                    program_account,
+                    user,
+                    access_list,
+                    delegate_account,
+                    &delegate,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::CloseAccessList => {
+            let user = next_account_info(account_info_iter)?;
+            let access_list = next_account_info(account_info_iter)?;
+            let recipient = next_account_info(account_info_iter)?;
+            if let Some(address) = user.signer_key() {
+                process_close_access_list(
+                    program_id,
+                    program_account,
+                    address,
+                    access_list,
+                    recipient,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::CreateGroup { name } => {
+            let creator = next_account_info(account_info_iter)?;
+            let group_account = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            if let Some(address) = creator.signer_key() {
+                process_create_group(
+                    program_id,
+                    program_account,
+                    creator,
+                    group_account,
+                    system_program_account,
+                    Group::new(name, vec![*address])?,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::AddGroupMember { name, member } => {
+            let admin = next_account_info(account_info_iter)?;
+            let group_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            if member == GroupMember::Group(*group_account.key) {
+                return Err(SolanaPamError::InvalidGroup.into());
+            }
+            if let Some(address) = admin.signer_key() {
+                process_change_group(
+                    program_id,
+                    address,
+                    group_account,
+                    revocation,
+                    &name,
+                    |group| {
+                        group.add(member);
+                        Ok(())
+                    },
+                )
+                .map(|()| {
+                    Event::GroupMemberAdded {
+                        group: *group_account.key,
+                        member,
+                    }
+                    .emit()
+                })
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::RemoveGroupMember { name, member } => {
+            let admin = next_account_info(account_info_iter)?;
+            let group_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            if let Some(address) = admin.signer_key() {
+                process_change_group(
+                    program_id,
+                    address,
+                    group_account,
+                    revocation,
+                    &name,
+                    |group| {
+                        group.remove(&member);
+                        Ok(())
+                    },
+                )
+                .map(|()| {
+                    Event::GroupMemberRemoved {
+                        group: *group_account.key,
+                        member,
+                    }
+                    .emit()
+                })
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::SetGroupAdmins { name, admins } => {
+            let admin = next_account_info(account_info_iter)?;
+            let group_account = next_account_info(account_info_iter)?;
+            let revocation = next_account_info(account_info_iter)?;
+            let event = Event::GroupAdminsSet {
+                group: *group_account.key,
+                admins: admins.clone(),
+            };
+            if let Some(address) = admin.signer_key() {
+                process_change_group(
+                    program_id,
+                    address,
+                    group_account,
+                    revocation,
+                    &name,
+                    |group| group.set_admins(admins),
+                )
+                .map(|()| event.emit())
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::RecordLogin {
+            user,
+            service,
+            remote_host_hash,
+        } => {
+            let host = next_account_info(account_info_iter)?;
+            let host_account = next_account_info(account_info_iter)?;
+            let login_log_account = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            if !host.is_signer {
+                return Err(SolanaPamError::MissingSigner.into());
+            }
+            let record = LoginRecord::new(
+                user,
+                service,
+                remote_host_hash,
+                Clock::get()?.unix_timestamp,
+            )?;
+            process_record_login(
+                program_id,
+                program_account,
+                host,
+                host_account,
+                login_log_account,
+                system_program_account,
+                record,
+            )
+        }
+        ProgInstruction::RecordSession {
+            user,
+            service,
+            opened_at,
+        } => {
+            let host = next_account_info(account_info_iter)?;
+            let host_account = next_account_info(account_info_iter)?;
+            if let Some(address) = host.signer_key() {
+                process_record_session(
+                    program_id,
+                    program_account,
+                    address,
+                    host_account,
+                    user,
+                    service,
+                    opened_at,
+                )
+            } else {
+                Err(SolanaPamError::MissingSigner.into())
+            }
+        }
+        ProgInstruction::RevokeKey(pubkey) => {
+            let payer = next_account_info(account_info_iter)?;
+            let revoker = next_account_info(account_info_iter)?;
+            let revocation_account = next_account_info(account_info_iter)?;
+            let system_program_account = next_account_info(account_info_iter)?;
+            match (payer.is_signer, revoker.signer_key()) {
+                (true, Some(revoked_by)) => {
+                    let revocation =
+                        Revocation::new(pubkey, *revoked_by, Clock::get()?.unix_timestamp);
+                    process_revoke_key(
+                        program_id,
+                        program_account,
+                        payer,
+                        revocation_account,
+                        system_program_account,
+                        revocation,
+                    )
+                }
+                _ => Err(SolanaPamError::MissingSigner.into()),
             }
         }
     }
-    // let memo = from_utf8(input).map_err(|err| {
-    //     msg!("Invalid UTF-8, from byte {}", err.valid_up_to());
-    //     ProgramError::InvalidInstructionData
-    // })?;
-    // msg!("Memo (len {}): {:?}", memo.len(), memo);
-
-    // Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use solana_program::{
-        account_info::IntoAccountInfo, program_error::ProgramError, pubkey::Pubkey,
-    };
-    use solana_sdk::account::Account;
+    use solana_pam_shared::instructions::INSTRUCTION_VERSION;
+    use solana_pam_shared::state::AccessEntry;
 
     #[test]
-    fn test_utf8_memo() {
-        let program_id = Pubkey::new(&[0; 32]);
-
-        let string = b"letters and such";
-        assert_eq!(Ok(()), process_instruction(&program_id, &[], string));
-
-        let emoji = "🐆".as_bytes();
-        let bytes = [0xF0, 0x9F, 0x90, 0x86];
-        assert_eq!(emoji, bytes);
-        assert_eq!(Ok(()), process_instruction(&program_id, &[], &emoji));
-
-        let mut bad_utf8 = bytes;
-        bad_utf8[3] = 0xFF; // Invalid UTF-8 byte
+    fn test_malformed_instruction_data() {
+        let program_id = Pubkey::new_unique();
+        for input in &[
+            &[][..],
+            &[INSTRUCTION_VERSION],
+            &[INSTRUCTION_VERSION, 2, 0, 0],
+            &[INSTRUCTION_VERSION, 0, 0],
+            &[INSTRUCTION_VERSION, 9],
+        ] {
+            assert_eq!(
+                process_instruction(&program_id, &[], input),
+                Err(ProgramError::InvalidInstructionData)
+            );
+        }
         assert_eq!(
-            Err(ProgramError::InvalidInstructionData),
-            process_instruction(&program_id, &[], &bad_utf8)
+            process_instruction(&program_id, &[], &[INSTRUCTION_VERSION + 1, 0]),
+            Err(SolanaPamError::UnsupportedInstructionVersion.into())
         );
     }
 
     #[test]
-    fn test_signers() {
-        let program_id = Pubkey::new(&[0; 32]);
-        let memo = "🐆".as_bytes();
-
-        let pubkey0 = Pubkey::new_unique();
-        let pubkey1 = Pubkey::new_unique();
-        let pubkey2 = Pubkey::new_unique();
-        let mut account0 = Account::default();
-        let mut account1 = Account::default();
-        let mut account2 = Account::default();
-
-        let signed_account_infos = vec![
-            (&pubkey0, true, &mut account0).into_account_info(),
-            (&pubkey1, true, &mut account1).into_account_info(),
-            (&pubkey2, true, &mut account2).into_account_info(),
-        ];
-        assert_eq!(
-            Ok(()),
-            process_instruction(&program_id, &signed_account_infos, memo)
-        );
-
-        assert_eq!(Ok(()), process_instruction(&program_id, &[], memo));
-
-        let unsigned_account_infos = vec![
-            (&pubkey0, false, &mut account0).into_account_info(),
-            (&pubkey1, false, &mut account1).into_account_info(),
-            (&pubkey2, false, &mut account2).into_account_info(),
-        ];
-        assert_eq!(
-            Err(ProgramError::MissingRequiredSignature),
-            process_instruction(&program_id, &unsigned_account_infos, memo)
-        );
-
-        let partially_signed_account_infos = vec![
-            (&pubkey0, true, &mut account0).into_account_info(),
-            (&pubkey1, false, &mut account1).into_account_info(),
-            (&pubkey2, true, &mut account2).into_account_info(),
-        ];
+    fn test_well_formed_instruction_needs_accounts() {
+        let program_id = Pubkey::new_unique();
+        let input =
+            ProgInstruction::AddPKToAccessListAccount(AccessEntry::new(Pubkey::new_unique()))
+                .pack();
         assert_eq!(
-            Err(ProgramError::MissingRequiredSignature),
-            process_instruction(&program_id, &partially_signed_account_infos, memo)
+            process_instruction(&program_id, &[], &input),
+            Err(ProgramError::NotEnoughAccountKeys)
         );
     }
 }
@@ -1,110 +1,577 @@
 #![cfg(feature = "test-bpf")]
 
+use borsh::{BorshDeserialize, BorshSerialize};
+use contract_pam_2::processor::process_instruction;
+use solana_pam_shared::{
+    client,
+    error::SolanaPamError,
+    instructions::{
+        find_access_list_address, find_account_policy_address, find_delegate_address,
+        find_group_address, find_host_address, find_loader_data_address, find_login_log_address,
+        find_multisig_address, find_revocation_address, find_username_address, ProgramData,
+    },
+    state::{
+        AccessEntry, AccessList, AccountPolicy, Delegate, EntryKind, Group, GroupMember,
+        HostAccount, LoginLog, Multisig, ProposalAction, Revocation, Role, UsernameAccount,
+        ACCESS_LIST_SIZE, DELEGATE_ADD, DELEGATE_MANAGE_EXPIRY, DELEGATE_MANAGE_ROLES,
+        DELEGATE_REMOVE, MAX_BULK_KEYS,
+    },
+};
 use solana_program::{
-    instruction::{AccountMeta, Instruction, InstructionError},
+    bpf_loader_upgradeable,
+    instruction::{Instruction, InstructionError},
     pubkey::Pubkey,
 };
 use solana_program_test::*;
 use solana_sdk::{
+    account::Account,
+    hash::Hash,
     signature::{Keypair, Signer},
     transaction::{Transaction, TransactionError},
 };
-use spl_memo::*;
 
-fn program_test() -> ProgramTest {
-    ProgramTest::new("spl_memo", id(), processor!(processor::process_instruction))
+const PROGRAM_DATA_SIZE: usize = 1024;
+
+/// A test with an initialized program state account at `program_data`
+fn program_test(program_id: Pubkey, program_data: Pubkey) -> ProgramTest {
+    program_test_with_authority(program_id, program_data, &Pubkey::new_unique())
 }
 
-#[tokio::test]
-async fn test_memo_signing() {
-    let memo = "🐆".as_bytes();
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+/// A test whose program state has `authority` as its authority, funded so it
+/// can pay for the accounts it creates
+fn program_test_with_authority(
+    program_id: Pubkey,
+    program_data: Pubkey,
+    authority: &Pubkey,
+) -> ProgramTest {
+    let state = ProgramData {
+        is_initialized: true,
+        authority: *authority,
+        ..ProgramData::default()
+    };
+    let mut program_test = ProgramTest::new(
+        "contract_pam_2",
+        program_id,
+        processor!(process_instruction),
+    );
+    program_test.add_account(program_data, program_data_account(program_id, &state));
+    program_test.add_account(*authority, funded_account());
+    program_test
+}
 
-    let keypairs = vec![Keypair::new(), Keypair::new(), Keypair::new()];
-    let pubkeys: Vec<Pubkey> = keypairs.iter().map(|keypair| keypair.pubkey()).collect();
+fn funded_account() -> Account {
+    Account {
+        lamports: 1_000_000_000,
+        ..Account::default()
+    }
+}
 
-    // Test complete signing
-    let signer_key_refs: Vec<&Pubkey> = pubkeys.iter().collect();
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(memo, &signer_key_refs)], Some(&payer.pubkey()));
-    let mut signers = vec![&payer];
-    for keypair in keypairs.iter() {
-        signers.push(keypair);
+fn program_data_account(program_id: Pubkey, state: &ProgramData) -> Account {
+    let mut data = vec![0; PROGRAM_DATA_SIZE];
+    state.serialize(&mut data.as_mut_slice()).unwrap();
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: program_id,
+        ..Account::default()
     }
-    transaction.sign(&signers, recent_blockhash);
+}
+
+/// The upgradeable loader's data account for a program `authority` may upgrade
+fn loader_data_account(authority: &Pubkey) -> Account {
+    let mut data = vec![3, 0, 0, 0];
+    data.extend_from_slice(&1u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(authority.as_ref());
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: bpf_loader_upgradeable::id(),
+        ..Account::default()
+    }
+}
+
+async fn get_program_data(banks_client: &mut BanksClient, program_data: Pubkey) -> ProgramData {
+    let account = banks_client
+        .get_account(program_data)
+        .await
+        .unwrap()
+        .unwrap();
+    ProgramData::deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+fn custom_error(error: SolanaPamError) -> TransactionError {
+    TransactionError::InstructionError(0, InstructionError::Custom(error as u32))
+}
+
+/// Send `instructions` signed by `signers`, the first of which pays
+async fn process(
+    banks_client: &mut BanksClient,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+    recent_blockhash: Hash,
+) -> Result<(), TransactionError> {
+    let mut transaction = Transaction::new_with_payer(instructions, Some(&signers[0].pubkey()));
+    transaction.sign(signers, recent_blockhash);
+    banks_client
+        .process_transaction(transaction)
+        .await
+        .map_err(|e| e.unwrap())
+}
+
+async fn get_access_list(
+    banks_client: &mut BanksClient,
+    program_id: &Pubkey,
+    user: &Pubkey,
+) -> Vec<Pubkey> {
+    let (address, _) = find_access_list_address(program_id, user);
+    let account = banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .expect("access list account");
+    let access_list = AccessList::unpack(&account.data).unwrap();
+    assert_eq!(access_list.owner, *user);
+    access_list.entries.iter().map(|e| e.pubkey).collect()
+}
+
+async fn get_revocation(
+    banks_client: &mut BanksClient,
+    program_id: &Pubkey,
+    pubkey: &Pubkey,
+) -> Option<Revocation> {
+    let (address, _) = find_revocation_address(program_id, pubkey);
+    banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .map(|account| Revocation::unpack(&account.data).unwrap())
+}
+
+#[tokio::test]
+async fn test_access_list_changes_persist() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let (mut banks_client, payer, recent_blockhash) =
+        program_test(program_id, program_data).start().await;
+    let user = &payer;
+    let (first, second) = (Keypair::new().pubkey(), Keypair::new().pubkey());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            client::init_access_list(&program_id, &program_data, &user.pubkey()),
+            client::add_to_access_list(&program_id, &program_data, &user.pubkey(), &first),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
     banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user.pubkey()).await,
+        vec![first]
+    );
 
-    // Test unsigned memo
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(memo, &[])], Some(&payer.pubkey()));
+    // A later transaction sees the first key and appends after it
+    let mut transaction = Transaction::new_with_payer(
+        &[client::add_to_access_list(
+            &program_id,
+            &program_data,
+            &user.pubkey(),
+            &second,
+        )],
+        Some(&payer.pubkey()),
+    );
     transaction.sign(&[&payer], recent_blockhash);
     banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user.pubkey()).await,
+        vec![first, second]
+    );
 
-    // Demonstrate success on signature provided, regardless of specific memo AccountMeta
     let mut transaction = Transaction::new_with_payer(
-        &[Instruction {
-            program_id: id(),
-            accounts: vec![
-                AccountMeta::new_readonly(keypairs[0].pubkey(), true),
-                AccountMeta::new_readonly(keypairs[1].pubkey(), true),
-                AccountMeta::new_readonly(payer.pubkey(), false),
-            ],
-            data: memo.to_vec(),
-        }],
+        &[client::remove_from_access_list(
+            &program_id,
+            &program_data,
+            &user.pubkey(),
+            &first,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user.pubkey()).await,
+        vec![second]
+    );
+}
+
+#[tokio::test]
+async fn test_init_access_list_leaves_program_data() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    // A state account whose map of legacy lists has no room for another
+    let mut state = ProgramData {
+        is_initialized: true,
+        ..ProgramData::default()
+    };
+    for _ in 0..15 {
+        state
+            .update(&Pubkey::new_unique(), &Pubkey::new_unique())
+            .unwrap();
+    }
+    let mut program_test = ProgramTest::new(
+        "contract_pam_2",
+        program_id,
+        processor!(process_instruction),
+    );
+    program_test.add_account(program_data, program_data_account(program_id, &state));
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let before = banks_client
+        .get_account(program_data)
+        .await
+        .unwrap()
+        .unwrap();
+
+    process(
+        &mut banks_client,
+        &[client::init_access_list(
+            &program_id,
+            &program_data,
+            &payer.pubkey(),
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &payer.pubkey()).await,
+        vec![]
+    );
+    assert_eq!(
+        banks_client
+            .get_account(program_data)
+            .await
+            .unwrap()
+            .unwrap()
+            .data,
+        before.data
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_headerless_access_list() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let user = Keypair::new();
+    let allowed = Pubkey::new_unique();
+    let (list, _) = find_access_list_address(&program_id, &user.pubkey());
+    let mut legacy = vec![0; ACCESS_LIST_SIZE];
+    legacy[32..64].copy_from_slice(allowed.as_ref());
+
+    let mut program_test = program_test(program_id, program_data);
+    program_test.add_account(
+        list,
+        Account {
+            lamports: 1_000_000_000,
+            data: legacy,
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    // Changes are refused until the list is migrated
+    let mut transaction = Transaction::new_with_payer(
+        &[client::add_to_access_list(
+            &program_id,
+            &program_data,
+            &user.pubkey(),
+            &user.pubkey(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            client::migrate_access_list(&program_id, &program_data, &user.pubkey(), &list),
+            client::add_to_access_list(&program_id, &program_data, &user.pubkey(), &user.pubkey()),
+        ],
         Some(&payer.pubkey()),
     );
-    transaction.sign(&[&payer, &keypairs[0], &keypairs[1]], recent_blockhash);
+    transaction.sign(&[&payer, &user], recent_blockhash);
     banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user.pubkey()).await,
+        vec![allowed, user.pubkey()]
+    );
+}
 
-    // Test missing signer(s)
+#[tokio::test]
+async fn test_init_and_set_authority() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let deployer = Keypair::new();
+    let mut program_test = ProgramTest::new(
+        "contract_pam_2",
+        program_id,
+        processor!(process_instruction),
+    );
+    program_test.add_account(
+        program_data,
+        program_data_account(program_id, &ProgramData::default()),
+    );
+    program_test.add_account(
+        find_loader_data_address(&program_id).0,
+        loader_data_account(&deployer.pubkey()),
+    );
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    // Nobody can get in before the deployer and pick the authority
+    let intruder = Keypair::new();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::init(&program_id, &program_data, &intruder.pubkey())],
+            &[&payer, &intruder],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotUpgradeAuthority))
+    );
+
+    process(
+        &mut banks_client,
+        &[client::init(&program_id, &program_data, &deployer.pubkey())],
+        &[&payer, &deployer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let state = get_program_data(&mut banks_client, program_data).await;
+    assert!(state.is_initialized);
+    assert_eq!(state.authority, deployer.pubkey());
+
+    // Nobody can initialize it again and take over
     let mut transaction = Transaction::new_with_payer(
-        &[Instruction {
-            program_id: id(),
-            accounts: vec![
-                AccountMeta::new_readonly(keypairs[0].pubkey(), true),
-                AccountMeta::new_readonly(keypairs[1].pubkey(), false),
-                AccountMeta::new_readonly(keypairs[2].pubkey(), true),
-            ],
-            data: memo.to_vec(),
-        }],
+        &[client::init(&program_id, &program_data, &intruder.pubkey())],
         Some(&payer.pubkey()),
     );
-    transaction.sign(&[&payer, &keypairs[0], &keypairs[2]], recent_blockhash);
+    transaction.sign(&[&payer, &intruder], recent_blockhash);
     assert_eq!(
         banks_client
             .process_transaction(transaction)
             .await
             .unwrap_err()
             .unwrap(),
-        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
+        custom_error(SolanaPamError::AlreadyInitialized)
     );
 
     let mut transaction = Transaction::new_with_payer(
-        &[Instruction {
-            program_id: id(),
-            accounts: vec![
-                AccountMeta::new_readonly(keypairs[0].pubkey(), false),
-                AccountMeta::new_readonly(keypairs[1].pubkey(), false),
-                AccountMeta::new_readonly(keypairs[2].pubkey(), false),
-            ],
-            data: memo.to_vec(),
-        }],
+        &[client::set_authority(
+            &program_id,
+            &program_data,
+            &intruder.pubkey(),
+            &intruder.pubkey(),
+        )],
         Some(&payer.pubkey()),
     );
-    transaction.sign(&[&payer], recent_blockhash);
+    transaction.sign(&[&payer, &intruder], recent_blockhash);
     assert_eq!(
         banks_client
             .process_transaction(transaction)
             .await
             .unwrap_err()
             .unwrap(),
-        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
+        custom_error(SolanaPamError::NotAuthority)
     );
 
-    // Test invalid utf-8; demonstrate log
-    let invalid_utf8 = [0xF0, 0x9F, 0x90, 0x86, 0xF0, 0x9F, 0xFF, 0x86];
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(&invalid_utf8, &[])], Some(&payer.pubkey()));
+    let successor = Pubkey::new_unique();
+    let mut transaction = Transaction::new_with_payer(
+        &[client::set_authority(
+            &program_id,
+            &program_data,
+            &deployer.pubkey(),
+            &successor,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &deployer], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        get_program_data(&mut banks_client, program_data)
+            .await
+            .authority,
+        successor
+    );
+}
+
+#[tokio::test]
+async fn test_init_legacy_state_needs_upgrade_authority() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let deployer = Keypair::new();
+    let (user, list) = (Pubkey::new_unique(), Pubkey::new_unique());
+    // State written before the authority existed, with a user's list in it
+    let mut state = ProgramData::default();
+    state.update(&user, &list).unwrap();
+    let mut program_test = ProgramTest::new(
+        "contract_pam_2",
+        program_id,
+        processor!(process_instruction),
+    );
+    program_test.add_account(program_data, program_data_account(program_id, &state));
+    program_test.add_account(
+        find_loader_data_address(&program_id).0,
+        loader_data_account(&deployer.pubkey()),
+    );
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    // A stranger cannot make itself the authority over the user's list
+    let stranger = Keypair::new();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::init(&program_id, &program_data, &stranger.pubkey())],
+            &[&payer, &stranger],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotUpgradeAuthority))
+    );
+    let mut no_loader_data = client::init(&program_id, &program_data, &stranger.pubkey());
+    no_loader_data.accounts.pop();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[no_loader_data],
+            &[&payer, &stranger],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotUpgradeAuthority))
+    );
+    assert!(
+        !get_program_data(&mut banks_client, program_data)
+            .await
+            .is_initialized
+    );
+
+    process(
+        &mut banks_client,
+        &[client::init(&program_id, &program_data, &deployer.pubkey())],
+        &[&payer, &deployer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let state = get_program_data(&mut banks_client, program_data).await;
+    assert!(state.is_initialized);
+    assert_eq!(state.authority, deployer.pubkey());
+    assert_eq!(
+        state.user_access_map.get(&user.to_bytes()),
+        Some(&list.to_bytes())
+    );
+}
+
+#[tokio::test]
+async fn test_init_checks_state_account() {
+    let program_id = Pubkey::new_unique();
+    let (not_exempt, foreign) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut program_test = ProgramTest::new(
+        "contract_pam_2",
+        program_id,
+        processor!(process_instruction),
+    );
+    let mut account = program_data_account(program_id, &ProgramData::default());
+    account.lamports = 1;
+    program_test.add_account(not_exempt, account);
+    let account = program_data_account(Pubkey::new_unique(), &ProgramData::default());
+    program_test.add_account(foreign, account);
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    for (program_data, error) in &[
+        (not_exempt, SolanaPamError::NotRentExempt),
+        (foreign, SolanaPamError::WrongProgramDataOwner),
+    ] {
+        let mut transaction = Transaction::new_with_payer(
+            &[client::init(&program_id, program_data, &payer.pubkey())],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            custom_error(*error)
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_expired_grants_are_dropped() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let (mut banks_client, payer, recent_blockhash) =
+        program_test(program_id, program_data).start().await;
+    let (lapsed, current) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            client::init_access_list(&program_id, &program_data, &payer.pubkey()),
+            client::add_entry_to_access_list(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                AccessEntry {
+                    expires_at: Some(1),
+                    ..AccessEntry::new(lapsed)
+                },
+            ),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &payer.pubkey()).await,
+        vec![lapsed]
+    );
+
+    // The next add drops the grant that expired in 1970
+    let mut transaction = Transaction::new_with_payer(
+        &[client::add_to_access_list(
+            &program_id,
+            &program_data,
+            &payer.pubkey(),
+            &current,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &payer.pubkey()).await,
+        vec![current]
+    );
+
+    let mut transaction = Transaction::new_with_payer(
+        &[client::add_entry_to_access_list(
+            &program_id,
+            &program_data,
+            &payer.pubkey(),
+            AccessEntry {
+                not_before: Some(200),
+                expires_at: Some(100),
+                ..AccessEntry::new(lapsed)
+            },
+        )],
+        Some(&payer.pubkey()),
+    );
     transaction.sign(&[&payer], recent_blockhash);
     assert_eq!(
         banks_client
@@ -112,95 +579,2295 @@ async fn test_memo_signing() {
             .await
             .unwrap_err()
             .unwrap(),
-        TransactionError::InstructionError(0, InstructionError::InvalidInstructionData)
+        custom_error(SolanaPamError::InvalidGrantWindow)
     );
 }
 
 #[tokio::test]
-#[ignore]
-async fn test_memo_compute_limits() {
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
+async fn test_register_host() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let (mut banks_client, payer, recent_blockhash) =
+        program_test_with_authority(program_id, program_data, &authority.pubkey())
+            .start()
+            .await;
+    let host = Keypair::new();
+    let register = |authority: &Pubkey, label: &str| {
+        client::register_host(
+            &program_id,
+            &program_data,
+            authority,
+            &host.pubkey(),
+            label.to_string(),
+            vec!["prod".to_string()],
+        )
+    };
 
-    // Test memo length
-    let mut memo = vec![];
-    for _ in 0..1000 {
-        let mut vec = vec![0x53, 0x4F, 0x4C];
-        memo.append(&mut vec);
-    }
+    // Only the authority registers hosts
+    let mut transaction =
+        Transaction::new_with_payer(&[register(&payer.pubkey(), "web-1")], Some(&payer.pubkey()));
+    transaction.sign(&[&payer, &host], recent_blockhash);
+    assert_eq!(
+        banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err()
+            .unwrap(),
+        custom_error(SolanaPamError::NotAuthority)
+    );
 
     let mut transaction =
-        Transaction::new_with_payer(&[build_memo(&memo[..450], &[])], Some(&payer.pubkey()));
-    transaction.sign(&[&payer], recent_blockhash);
+        Transaction::new_with_payer(&[register(&authority.pubkey(), "")], Some(&payer.pubkey()));
+    transaction.sign(&[&payer, &authority, &host], recent_blockhash);
+    assert_eq!(
+        banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err()
+            .unwrap(),
+        custom_error(SolanaPamError::InvalidHostName)
+    );
+
+    let mut transaction = Transaction::new_with_payer(
+        &[register(&authority.pubkey(), "web-1")],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &authority, &host], recent_blockhash);
     banks_client.process_transaction(transaction).await.unwrap();
+    let (address, _) = find_host_address(&program_id, &host.pubkey());
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    assert_eq!(account.owner, program_id);
+    let host_account = HostAccount::unpack(&account.data).unwrap();
+    assert_eq!(host_account.host, host.pubkey());
+    assert_eq!(host_account.label, "web-1");
+    assert!(host_account.has_tag("prod"));
+}
 
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(&memo[..600], &[])], Some(&payer.pubkey()));
+#[tokio::test]
+async fn test_claim_and_release_username() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let rival = Keypair::new();
+    program_test.add_account(rival.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let (address, _) = find_username_address(&program_id, "alice");
+
+    let mut transaction = Transaction::new_with_payer(
+        &[client::claim_username(
+            &program_id,
+            &program_data,
+            &payer.pubkey(),
+            "alice".to_string(),
+        )],
+        Some(&payer.pubkey()),
+    );
     transaction.sign(&[&payer], recent_blockhash);
-    let err = banks_client
-        .process_transaction(transaction)
-        .await
-        .unwrap_err()
-        .unwrap();
-    let failed_to_complete =
-        TransactionError::InstructionError(0, InstructionError::ProgramFailedToComplete);
-    let computational_budget_exceeded =
-        TransactionError::InstructionError(0, InstructionError::ComputationalBudgetExceeded);
-    assert!(err == failed_to_complete || err == computational_budget_exceeded);
+    banks_client.process_transaction(transaction).await.unwrap();
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let record = UsernameAccount::unpack(&account.data).unwrap();
+    assert_eq!(record.owner, payer.pubkey());
+    assert_eq!(record.username, "alice");
 
-    let mut memo = vec![];
-    for _ in 0..100 {
-        let mut vec = vec![0xE2, 0x97, 0x8E];
-        memo.append(&mut vec);
+    // The name is taken, and only its owner can give it up
+    for (instruction, error) in vec![
+        (
+            client::claim_username(
+                &program_id,
+                &program_data,
+                &rival.pubkey(),
+                "alice".to_string(),
+            ),
+            SolanaPamError::UsernameTaken,
+        ),
+        (
+            client::release_username(
+                &program_id,
+                &program_data,
+                &rival.pubkey(),
+                "alice".to_string(),
+            ),
+            SolanaPamError::NotUsernameOwner,
+        ),
+    ] {
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(&payer.pubkey()));
+        transaction.sign(&[&payer, &rival], recent_blockhash);
+        assert_eq!(
+            banks_client
+                .process_transaction(transaction)
+                .await
+                .unwrap_err()
+                .unwrap(),
+            custom_error(error)
+        );
     }
 
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(&memo[..60], &[])], Some(&payer.pubkey()));
+    let mut transaction = Transaction::new_with_payer(
+        &[client::claim_username(
+            &program_id,
+            &program_data,
+            &rival.pubkey(),
+            "Alice".to_string(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &rival], recent_blockhash);
+    assert_eq!(
+        banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err()
+            .unwrap(),
+        custom_error(SolanaPamError::InvalidUsername)
+    );
+
+    // Once released the name is free for anyone
+    let mut transaction = Transaction::new_with_payer(
+        &[client::release_username(
+            &program_id,
+            &program_data,
+            &payer.pubkey(),
+            "alice".to_string(),
+        )],
+        Some(&payer.pubkey()),
+    );
     transaction.sign(&[&payer], recent_blockhash);
     banks_client.process_transaction(transaction).await.unwrap();
+    assert!(banks_client.get_account(address).await.unwrap().is_none());
 
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(&memo[..63], &[])], Some(&payer.pubkey()));
-    transaction.sign(&[&payer], recent_blockhash);
-    let err = banks_client
-        .process_transaction(transaction)
-        .await
-        .unwrap_err()
-        .unwrap();
-    assert!(err == failed_to_complete || err == computational_budget_exceeded);
+    let mut transaction = Transaction::new_with_payer(
+        &[client::claim_username(
+            &program_id,
+            &program_data,
+            &rival.pubkey(),
+            "alice".to_string(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &rival], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+}
 
-    // Test num signers with 32-byte memo
-    let memo = Pubkey::new_unique().to_bytes();
-    let mut keypairs = vec![];
-    for _ in 0..20 {
-        keypairs.push(Keypair::new());
-    }
-    let pubkeys: Vec<Pubkey> = keypairs.iter().map(|keypair| keypair.pubkey()).collect();
-    let signer_key_refs: Vec<&Pubkey> = pubkeys.iter().collect();
+#[tokio::test]
+async fn test_account_policy() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let (mut banks_client, payer, recent_blockhash) =
+        program_test_with_authority(program_id, program_data, &authority.pubkey())
+            .start()
+            .await;
+    let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let deploy = || "deploy".to_string();
 
-    let mut signers = vec![&payer];
-    for keypair in keypairs[..12].iter() {
-        signers.push(keypair);
-    }
+    // The authority creates the policy and hands it to the payer
     let mut transaction = Transaction::new_with_payer(
-        &[build_memo(&memo, &signer_key_refs[..12])],
+        &[
+            client::init_account_policy(
+                &program_id,
+                &program_data,
+                &authority.pubkey(),
+                deploy(),
+                &payer.pubkey(),
+            ),
+            client::add_to_account_policy(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                deploy(),
+                AccessEntry::new(alice),
+            ),
+            client::add_to_account_policy(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                deploy(),
+                AccessEntry::new(bob),
+            ),
+            client::remove_from_account_policy(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                deploy(),
+                &alice,
+            ),
+        ],
         Some(&payer.pubkey()),
     );
-    transaction.sign(&signers, recent_blockhash);
+    transaction.sign(&[&payer, &authority], recent_blockhash);
     banks_client.process_transaction(transaction).await.unwrap();
+    let (address, _) = find_account_policy_address(&program_id, "deploy");
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let policy = AccountPolicy::unpack(&account.data).unwrap();
+    assert_eq!(policy.admin, payer.pubkey());
+    assert_eq!(policy.account, "deploy");
+    assert_eq!(policy.entries, vec![AccessEntry::new(bob)]);
 
-    let mut signers = vec![&payer];
-    for keypair in keypairs[..15].iter() {
-        signers.push(keypair);
-    }
+    // Only the admin changes it, not even the authority
     let mut transaction = Transaction::new_with_payer(
-        &[build_memo(&memo, &signer_key_refs[..15])],
+        &[client::add_to_account_policy(
+            &program_id,
+            &program_data,
+            &authority.pubkey(),
+            deploy(),
+            AccessEntry::new(alice),
+        )],
         Some(&payer.pubkey()),
     );
-    transaction.sign(&signers, recent_blockhash);
-    let err = banks_client
-        .process_transaction(transaction)
+    transaction.sign(&[&payer, &authority], recent_blockhash);
+    assert_eq!(
+        banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err()
+            .unwrap(),
+        custom_error(SolanaPamError::NotPolicyAdmin)
+    );
+}
+
+#[tokio::test]
+async fn test_multisig_threshold() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let admins = vec![Keypair::new(), Keypair::new(), Keypair::new()];
+    for admin in &admins {
+        program_test.add_account(admin.pubkey(), funded_account());
+    }
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let (a, b, c) = (&admins[0], &admins[1], &admins[2]);
+    let admin_keys: Vec<Pubkey> = admins.iter().map(|k| k.pubkey()).collect();
+    let (access_list, _) = find_access_list_address(&program_id, &payer.pubkey());
+    let (multisig, _) = find_multisig_address(&program_id, &access_list);
+    let granted = Pubkey::new_unique();
+
+    // A threshold of zero, or above the number of admins, can never work
+    for threshold in &[0, 4] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[
+                    client::init_access_list(&program_id, &program_data, &payer.pubkey()),
+                    client::create_multisig(
+                        &program_id,
+                        &program_data,
+                        &payer.pubkey(),
+                        admin_keys.clone(),
+                        *threshold,
+                    ),
+                ],
+                &[&payer],
+                recent_blockhash,
+            )
+            .await,
+            Err(TransactionError::InstructionError(
+                1,
+                InstructionError::Custom(SolanaPamError::InvalidMultisig as u32)
+            ))
+        );
+    }
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &payer.pubkey()),
+            client::create_multisig(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                admin_keys.clone(),
+                2,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // The owner alone can no longer change the list
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_to_access_list(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &granted,
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::GovernedByMultisig))
+    );
+
+    let add = ProposalAction::AddEntry(AccessEntry::new(granted));
+    let propose = client::propose(&program_id, &program_data, &a.pubkey(), &multisig, 0, add);
+    process(&mut banks_client, &[propose], &[a], recent_blockhash)
+        .await
+        .unwrap();
+    let execute = |admin: &Keypair| {
+        client::execute_proposal(
+            &program_id,
+            &program_data,
+            &admin.pubkey(),
+            &multisig,
+            0,
+            &access_list,
+            &admin_keys,
+        )
+    };
+    let approve = |admin: &Keypair| {
+        client::approve_proposal(&program_id, &program_data, &admin.pubkey(), &multisig, 0)
+    };
+
+    // One of two: the proposer's approval alone is not enough, and approving
+    // twice counts once
+    assert_eq!(
+        process(&mut banks_client, &[execute(a)], &[a], recent_blockhash).await,
+        Err(custom_error(SolanaPamError::ThresholdNotMet))
+    );
+    process(&mut banks_client, &[approve(a)], &[a], recent_blockhash)
+        .await
+        .unwrap();
+    assert_eq!(
+        process(&mut banks_client, &[execute(b)], &[b], recent_blockhash).await,
+        Err(custom_error(SolanaPamError::ThresholdNotMet))
+    );
+    let outsider = Keypair::new();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[approve(&outsider)],
+            &[&payer, &outsider],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotMultisigAdmin))
+    );
+
+    // Exactly the threshold
+    process(&mut banks_client, &[approve(b)], &[b], recent_blockhash)
+        .await
+        .unwrap();
+    process(&mut banks_client, &[execute(c)], &[c], recent_blockhash)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &payer.pubkey()).await,
+        vec![granted]
+    );
+    // The payer makes this transaction differ from the failed attempt above
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[execute(a)],
+            &[&payer, a],
+            recent_blockhash
+        )
+        .await,
+        Err(custom_error(SolanaPamError::ProposalExecuted))
+    );
+    assert_eq!(
+        process(&mut banks_client, &[approve(c)], &[c], recent_blockhash).await,
+        Err(custom_error(SolanaPamError::ProposalExecuted))
+    );
+}
+
+#[tokio::test]
+async fn test_multisig_changes_its_rules() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let (a, b) = (Keypair::new(), Keypair::new());
+    program_test.add_account(a.pubkey(), funded_account());
+    program_test.add_account(b.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let (access_list, _) = find_access_list_address(&program_id, &payer.pubkey());
+    let (multisig, _) = find_multisig_address(&program_id, &access_list);
+    let (kept, removed) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let propose = |admin: &Keypair, index, action| {
+        client::propose(
+            &program_id,
+            &program_data,
+            &admin.pubkey(),
+            &multisig,
+            index,
+            action,
+        )
+    };
+    let (first_rules, second_rules) = (vec![a.pubkey()], vec![a.pubkey(), b.pubkey()]);
+    let execute = |admin: &Keypair, index, admins: &[Pubkey]| {
+        client::execute_proposal(
+            &program_id,
+            &program_data,
+            &admin.pubkey(),
+            &multisig,
+            index,
+            &access_list,
+            admins,
+        )
+    };
+
+    // With a threshold of one, the proposer alone can carry a change
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &payer.pubkey()),
+            client::add_to_access_list(&program_id, &program_data, &payer.pubkey(), &kept),
+            client::add_to_access_list(&program_id, &program_data, &payer.pubkey(), &removed),
+            client::create_multisig(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                vec![a.pubkey()],
+                1,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    process(
+        &mut banks_client,
+        &[
+            propose(&a, 0, ProposalAction::RemoveEntry(removed)),
+            execute(&a, 0, &first_rules),
+        ],
+        &[&a],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &payer.pubkey()).await,
+        vec![kept]
+    );
+
+    // Proposal 1 is left open while proposal 2 makes it two of two
+    let set_rules = ProposalAction::SetMultisig {
+        admins: vec![a.pubkey(), b.pubkey()],
+        threshold: 2,
+    };
+    process(
+        &mut banks_client,
+        &[
+            propose(&a, 1, ProposalAction::RemoveEntry(kept)),
+            propose(&a, 2, set_rules),
+            execute(&a, 2, &first_rules),
+        ],
+        &[&a],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let account = banks_client.get_account(multisig).await.unwrap().unwrap();
+    let rules = Multisig::unpack(&account.data).unwrap();
+    assert_eq!(rules.admins, vec![a.pubkey(), b.pubkey()]);
+    assert_eq!(rules.threshold, 2);
+    assert_eq!(rules.proposal_count, 3);
+
+    // Approvals given under the old rule no longer count
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[execute(&a, 1, &second_rules)],
+            &[&a],
+            recent_blockhash
+        )
+        .await,
+        Err(custom_error(SolanaPamError::StaleProposal))
+    );
+
+    // N of N needs every admin
+    process(
+        &mut banks_client,
+        &[propose(&b, 3, ProposalAction::RemoveEntry(kept))],
+        &[&b],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[execute(&b, 3, &second_rules)],
+            &[&b],
+            recent_blockhash
+        )
+        .await,
+        Err(custom_error(SolanaPamError::ThresholdNotMet))
+    );
+    process(
+        &mut banks_client,
+        &[
+            client::approve_proposal(&program_id, &program_data, &a.pubkey(), &multisig, 3),
+            execute(&a, 3, &second_rules),
+        ],
+        &[&a],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert!(
+        get_access_list(&mut banks_client, &program_id, &payer.pubkey())
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_delegate_rights() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let lead = Keypair::new();
+    program_test.add_account(lead.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let owner = payer.pubkey();
+    let (member, newcomer, late) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner),
+            client::add_to_access_list(&program_id, &program_data, &owner, &member),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &lead.pubkey(),
+                DELEGATE_ADD,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let (access_list, _) = find_access_list_address(&program_id, &owner);
+    let (delegate_address, _) = find_delegate_address(&program_id, &access_list, &lead.pubkey());
+    let account = banks_client
+        .get_account(delegate_address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        Delegate::unpack(&account.data).unwrap().rights,
+        DELEGATE_ADD
+    );
+
+    // An add-only delegate adds new keys but cannot touch listed ones
+    process(
+        &mut banks_client,
+        &[client::add_entry_as_delegate(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            &owner,
+            AccessEntry::new(newcomer),
+        )],
+        &[&lead],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &owner).await,
+        vec![member, newcomer]
+    );
+    let extend = AccessEntry {
+        expires_at: Some(1),
+        ..AccessEntry::new(member)
+    };
+    for instruction in vec![
+        client::add_entry_as_delegate(&program_id, &program_data, &lead.pubkey(), &owner, extend),
+        client::remove_as_delegate(&program_id, &program_data, &lead.pubkey(), &owner, &member),
+    ] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[instruction],
+                &[&lead],
+                recent_blockhash
+            )
+            .await,
+            Err(custom_error(SolanaPamError::MissingDelegateRight))
+        );
+    }
+
+    // Rights must be some of the known bits
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &lead.pubkey(),
+                0
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::InvalidDelegateRights))
+    );
+
+    // The owner swaps the delegate's rights, then revokes them
+    process(
+        &mut banks_client,
+        &[client::set_delegate(
+            &program_id,
+            &program_data,
+            &owner,
+            &lead.pubkey(),
+            DELEGATE_REMOVE | DELEGATE_MANAGE_EXPIRY,
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    process(
+        &mut banks_client,
+        &[client::remove_as_delegate(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            &owner,
+            &member,
+        )],
+        &[&lead, &payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &owner).await,
+        vec![newcomer]
+    );
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_entry_as_delegate(
+                &program_id,
+                &program_data,
+                &lead.pubkey(),
+                &owner,
+                AccessEntry::new(late),
+            )],
+            &[&lead],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::MissingDelegateRight))
+    );
+
+    process(
+        &mut banks_client,
+        &[client::revoke_delegate(
+            &program_id,
+            &program_data,
+            &owner,
+            &lead.pubkey(),
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        banks_client.get_account(delegate_address).await.unwrap(),
+        None
+    );
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::remove_as_delegate(
+                &program_id,
+                &program_data,
+                &lead.pubkey(),
+                &owner,
+                &newcomer,
+            )],
+            &[&lead],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotAccessListOwner))
+    );
+}
+
+#[tokio::test]
+async fn test_expiry_delegate_cannot_change_grant() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let lead = Keypair::new();
+    program_test.add_account(lead.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let owner = payer.pubkey();
+    let blocked = Pubkey::new_unique();
+    let deny = AccessEntry {
+        deny: true,
+        ..AccessEntry::new(blocked)
+    };
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner),
+            client::add_entry_to_access_list(&program_id, &program_data, &owner, deny.clone()),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &lead.pubkey(),
+                DELEGATE_MANAGE_EXPIRY,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // Turning the deny into an allow, or the key entry into a group entry,
+    // is more than a change of expiry
+    for entry in vec![
+        AccessEntry::new(blocked),
+        AccessEntry {
+            kind: EntryKind::Group,
+            ..deny.clone()
+        },
+    ] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[client::add_entry_as_delegate(
+                    &program_id,
+                    &program_data,
+                    &lead.pubkey(),
+                    &owner,
+                    entry,
+                )],
+                &[&lead],
+                recent_blockhash,
+            )
+            .await,
+            Err(custom_error(SolanaPamError::MissingDelegateRight))
+        );
+    }
+
+    let extended = AccessEntry {
+        expires_at: Some(i64::MAX),
+        ..deny
+    };
+    process(
+        &mut banks_client,
+        &[client::add_entry_as_delegate(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            &owner,
+            extended.clone(),
+        )],
+        &[&lead],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let (address, _) = find_access_list_address(&program_id, &owner);
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let list = AccessList::unpack(&account.data).unwrap();
+    assert_eq!(list.entries, vec![extended]);
+}
+
+#[tokio::test]
+async fn test_delegate_rights_for_deny_entries() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let (adder, remover, lead) = (Keypair::new(), Keypair::new(), Keypair::new());
+    for delegate in &[&adder, &remover, &lead] {
+        program_test.add_account(delegate.pubkey(), funded_account());
+    }
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let owner = payer.pubkey();
+    let (member, blocked) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let deny = |pubkey| AccessEntry {
+        deny: true,
+        ..AccessEntry::new(pubkey)
+    };
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner),
+            client::add_to_access_list(&program_id, &program_data, &owner, &member),
+            client::add_entry_to_access_list(&program_id, &program_data, &owner, deny(blocked)),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &adder.pubkey(),
+                DELEGATE_ADD,
+            ),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &remover.pubkey(),
+                DELEGATE_REMOVE,
+            ),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &lead.pubkey(),
+                DELEGATE_ADD | DELEGATE_REMOVE,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // Denying a listed key takes its access away, which adding cannot do,
+    // and dropping a deny lets the key back in, which removing cannot do
+    for (delegate, instruction) in vec![
+        (
+            &adder,
+            client::add_entry_as_delegate(
+                &program_id,
+                &program_data,
+                &adder.pubkey(),
+                &owner,
+                deny(member),
+            ),
+        ),
+        (
+            &adder,
+            client::remove_as_delegate(
+                &program_id,
+                &program_data,
+                &adder.pubkey(),
+                &owner,
+                &blocked,
+            ),
+        ),
+        (
+            &remover,
+            client::remove_as_delegate(
+                &program_id,
+                &program_data,
+                &remover.pubkey(),
+                &owner,
+                &blocked,
+            ),
+        ),
+    ] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[instruction],
+                &[delegate],
+                recent_blockhash
+            )
+            .await,
+            Err(custom_error(SolanaPamError::MissingDelegateRight))
+        );
+    }
+
+    process(
+        &mut banks_client,
+        &[
+            client::add_entry_as_delegate(
+                &program_id,
+                &program_data,
+                &remover.pubkey(),
+                &owner,
+                deny(member),
+            ),
+            client::remove_as_delegate(
+                &program_id,
+                &program_data,
+                &lead.pubkey(),
+                &owner,
+                &blocked,
+            ),
+        ],
+        &[&remover, &lead],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let (address, _) = find_access_list_address(&program_id, &owner);
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let list = AccessList::unpack(&account.data).unwrap();
+    assert_eq!(list.entries, vec![AccessEntry::new(member), deny(member)]);
+}
+
+#[tokio::test]
+async fn test_bulk_changes() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let (mut banks_client, payer, recent_blockhash) =
+        program_test(program_id, program_data).start().await;
+    let user = payer.pubkey();
+    let team: Vec<Pubkey> = (0..MAX_BULK_KEYS).map(|_| Pubkey::new_unique()).collect();
+
+    // A whole team joins in one transaction, repeats and all
+    let mut onboard = team[..4].to_vec();
+    onboard.push(team[0]);
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &user),
+            client::add_many_to_access_list(&program_id, &program_data, &user, onboard),
+            client::remove_many_from_access_list(
+                &program_id,
+                &program_data,
+                &user,
+                vec![team[1], team[2]],
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user).await,
+        vec![team[0], team[3]]
+    );
+
+    process(
+        &mut banks_client,
+        &[client::replace_access_list(
+            &program_id,
+            &program_data,
+            &user,
+            team.clone(),
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user).await,
+        team
+    );
+
+    let mut too_many = team.clone();
+    too_many.push(Pubkey::new_unique());
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_many_to_access_list(
+                &program_id,
+                &program_data,
+                &user,
+                too_many,
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::TooManyKeys))
+    );
+
+    // Past the space left in the account, the change fails as a whole
+    let extra: Vec<Pubkey> = (0..MAX_BULK_KEYS).map(|_| Pubkey::new_unique()).collect();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_many_to_access_list(
+                &program_id,
+                &program_data,
+                &user,
+                extra,
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::AccessListFull))
+    );
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user).await,
+        team
+    );
+}
+
+#[tokio::test]
+async fn test_close_access_list() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let stranger = Keypair::new();
+    program_test.add_account(stranger.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let user = payer.pubkey();
+    let recipient = Pubkey::new_unique();
+    let (list, _) = find_access_list_address(&program_id, &user);
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &user),
+            client::add_to_access_list(&program_id, &program_data, &user, &recipient),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let rent = banks_client
+        .get_account(list)
+        .await
+        .unwrap()
+        .unwrap()
+        .lamports;
+
+    // Only the owner closes it, and a closed list cannot be changed afterwards
+    // in the same transaction
+    let close = client::close_access_list(&program_id, &program_data, &user, &recipient);
+    let mut not_owner = close.clone();
+    not_owner.accounts[1].pubkey = stranger.pubkey();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[not_owner],
+            &[&stranger],
+            recent_blockhash
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotAccessListOwner))
+    );
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[
+                close.clone(),
+                client::add_to_access_list(&program_id, &program_data, &user, &user),
+            ],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(TransactionError::InstructionError(
+            1,
+            InstructionError::Custom(SolanaPamError::AccessListClosed as u32)
+        ))
+    );
+
+    process(&mut banks_client, &[close], &[&payer], recent_blockhash)
+        .await
+        .unwrap();
+    assert_eq!(banks_client.get_account(list).await.unwrap(), None);
+    assert_eq!(banks_client.get_balance(recipient).await.unwrap(), rent);
+    let state = get_program_data(&mut banks_client, program_data).await;
+    assert_eq!(state.user_access_map.get(&user.to_bytes()), None);
+
+    // The user can start over with an empty list
+    process(
+        &mut banks_client,
+        &[client::init_access_list(&program_id, &program_data, &user)],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &user).await,
+        vec![]
+    );
+}
+
+#[tokio::test]
+async fn test_close_legacy_access_list() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let user = Keypair::new();
+    let (list, other, recipient) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    // A headerless list recorded only in the state's map, next to another
+    // user's list
+    let mut state = ProgramData {
+        is_initialized: true,
+        ..ProgramData::default()
+    };
+    state.update(&user.pubkey(), &list).unwrap();
+    state.update(&other, &Pubkey::new_unique()).unwrap();
+    let mut program_test = ProgramTest::new(
+        "contract_pam_2",
+        program_id,
+        processor!(process_instruction),
+    );
+    program_test.add_account(program_data, program_data_account(program_id, &state));
+    program_test.add_account(
+        list,
+        Account {
+            lamports: 1_000_000_000,
+            data: vec![0; ACCESS_LIST_SIZE],
+            owner: program_id,
+            ..Account::default()
+        },
+    );
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    process(
+        &mut banks_client,
+        &[
+            client::migrate_access_list(&program_id, &program_data, &user.pubkey(), &list),
+            client::close_legacy_access_list(
+                &program_id,
+                &program_data,
+                &user.pubkey(),
+                &list,
+                &recipient,
+            ),
+        ],
+        &[&payer, &user],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(banks_client.get_account(list).await.unwrap(), None);
+    let state = get_program_data(&mut banks_client, program_data).await;
+    assert_eq!(state.user_access_map.get(&user.pubkey().to_bytes()), None);
+    assert!(state.user_access_map.get(&other.to_bytes()).is_some());
+}
+
+#[tokio::test]
+async fn test_revoke_key() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let (mut banks_client, payer, recent_blockhash) =
+        program_test_with_authority(program_id, program_data, &authority.pubkey())
+            .start()
+            .await;
+    let (stolen, lost, stranger) = (Keypair::new(), Keypair::new(), Keypair::new());
+
+    // Anyone other than the authority or the key itself is turned away
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::revoke_key(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &stranger.pubkey(),
+                &stolen.pubkey(),
+            )],
+            &[&payer, &stranger],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotRevoker))
+    );
+
+    // The authority revokes one key and another key revokes itself
+    process(
+        &mut banks_client,
+        &[
+            client::revoke_key(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &authority.pubkey(),
+                &stolen.pubkey(),
+            ),
+            client::revoke_key(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &lost.pubkey(),
+                &lost.pubkey(),
+            ),
+        ],
+        &[&payer, &authority, &lost],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let record = get_revocation(&mut banks_client, &program_id, &stolen.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(record.pubkey, stolen.pubkey());
+    assert_eq!(record.revoked_by, authority.pubkey());
+    let record = get_revocation(&mut banks_client, &program_id, &lost.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(record.revoked_by, lost.pubkey());
+    assert_eq!(
+        get_revocation(&mut banks_client, &program_id, &stranger.pubkey()).await,
+        None
+    );
+
+    // Revoking again leaves the first record in place
+    process(
+        &mut banks_client,
+        &[client::revoke_key(
+            &program_id,
+            &program_data,
+            &payer.pubkey(),
+            &stolen.pubkey(),
+            &stolen.pubkey(),
+        )],
+        &[&payer, &stolen],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let record = get_revocation(&mut banks_client, &program_id, &stolen.pubkey())
         .await
-        .unwrap_err()
         .unwrap();
-    assert!(err == failed_to_complete || err == computational_budget_exceeded);
+    assert_eq!(record.revoked_by, authority.pubkey());
+}
+
+#[tokio::test]
+async fn test_revoked_keys_cannot_change_lists() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let (admin, lead, team) = (Keypair::new(), Keypair::new(), Keypair::new());
+    for key in &[&admin, &lead, &team] {
+        program_test.add_account(key.pubkey(), funded_account());
+    }
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let owner = payer.pubkey();
+    let (granted, other_admin) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner),
+            client::add_entry_to_access_list(
+                &program_id,
+                &program_data,
+                &owner,
+                AccessEntry {
+                    role: Role::Admin,
+                    ..AccessEntry::new(admin.pubkey())
+                },
+            ),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &lead.pubkey(),
+                DELEGATE_ADD,
+            ),
+            client::init_access_list(&program_id, &program_data, &team.pubkey()),
+            client::create_multisig(
+                &program_id,
+                &program_data,
+                &team.pubkey(),
+                vec![admin.pubkey(), other_admin],
+                1,
+            ),
+        ],
+        &[&payer, &team],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // The admin and the delegate lose their keys and revoke them
+    process(
+        &mut banks_client,
+        &[
+            client::revoke_key(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &admin.pubkey(),
+                &admin.pubkey(),
+            ),
+            client::revoke_key(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &lead.pubkey(),
+                &lead.pubkey(),
+            ),
+        ],
+        &[&payer, &admin, &lead],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    for signer in &[&admin, &lead] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[client::add_entry_as_delegate(
+                    &program_id,
+                    &program_data,
+                    &signer.pubkey(),
+                    &owner,
+                    AccessEntry::new(granted),
+                )],
+                &[signer],
+                recent_blockhash,
+            )
+            .await,
+            Err(custom_error(SolanaPamError::SignerRevoked))
+        );
+    }
+    // Passing someone else's revocation address does not get around it
+    let mut elsewhere = client::add_entry_as_delegate(
+        &program_id,
+        &program_data,
+        &admin.pubkey(),
+        &owner,
+        AccessEntry::new(granted),
+    );
+    elsewhere.accounts[4].pubkey = find_revocation_address(&program_id, &granted).0;
+    assert_eq!(
+        process(&mut banks_client, &[elsewhere], &[&admin], recent_blockhash).await,
+        Err(custom_error(SolanaPamError::WrongRevocationAddress))
+    );
+    let (team_list, _) = find_access_list_address(&program_id, &team.pubkey());
+    let (multisig, _) = find_multisig_address(&program_id, &team_list);
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::propose(
+                &program_id,
+                &program_data,
+                &admin.pubkey(),
+                &multisig,
+                0,
+                ProposalAction::AddEntry(AccessEntry::new(granted)),
+            )],
+            &[&admin],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::SignerRevoked))
+    );
+
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &owner).await,
+        vec![admin.pubkey()]
+    );
+}
+
+#[tokio::test]
+async fn test_revoked_owners_and_policy_admins() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let owner = Keypair::new();
+    let mut program_test =
+        program_test_with_authority(program_id, program_data, &authority.pubkey());
+    program_test.add_account(owner.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let thief = Pubkey::new_unique();
+    let deploy = || "deploy".to_string();
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner.pubkey()),
+            client::init_account_policy(
+                &program_id,
+                &program_data,
+                &authority.pubkey(),
+                deploy(),
+                &owner.pubkey(),
+            ),
+            client::revoke_key(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &owner.pubkey(),
+                &owner.pubkey(),
+            ),
+        ],
+        &[&payer, &owner, &authority],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // Whoever holds the revoked key cannot grant another key, directly,
+    // through a delegate or a multisig, or on the policy it administers
+    for instruction in vec![
+        client::add_to_access_list(&program_id, &program_data, &owner.pubkey(), &thief),
+        client::set_delegate(
+            &program_id,
+            &program_data,
+            &owner.pubkey(),
+            &thief,
+            DELEGATE_ADD,
+        ),
+        client::create_multisig(&program_id, &program_data, &owner.pubkey(), vec![thief], 1),
+        client::add_to_account_policy(
+            &program_id,
+            &program_data,
+            &owner.pubkey(),
+            deploy(),
+            AccessEntry::new(thief),
+        ),
+    ] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[instruction],
+                &[&payer, &owner],
+                recent_blockhash
+            )
+            .await,
+            Err(custom_error(SolanaPamError::SignerRevoked))
+        );
+    }
+
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &owner.pubkey()).await,
+        vec![]
+    );
+    let (address, _) = find_account_policy_address(&program_id, "deploy");
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    assert_eq!(
+        AccountPolicy::unpack(&account.data).unwrap().entries,
+        vec![]
+    );
+}
+
+#[tokio::test]
+async fn test_revoked_approvals_do_not_count() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let (a, b, c) = (Keypair::new(), Keypair::new(), Keypair::new());
+    for admin in &[&a, &b, &c] {
+        program_test.add_account(admin.pubkey(), funded_account());
+    }
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let admin_keys = vec![a.pubkey(), b.pubkey(), c.pubkey()];
+    let (access_list, _) = find_access_list_address(&program_id, &payer.pubkey());
+    let (multisig, _) = find_multisig_address(&program_id, &access_list);
+    let granted = Pubkey::new_unique();
+    let approve = |admin: &Keypair| {
+        client::approve_proposal(&program_id, &program_data, &admin.pubkey(), &multisig, 0)
+    };
+    let execute = |admin: &Keypair| {
+        client::execute_proposal(
+            &program_id,
+            &program_data,
+            &admin.pubkey(),
+            &multisig,
+            0,
+            &access_list,
+            &admin_keys,
+        )
+    };
+
+    // Two of three approve, then one of them is revoked
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &payer.pubkey()),
+            client::create_multisig(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                admin_keys.clone(),
+                2,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    process(
+        &mut banks_client,
+        &[
+            client::propose(
+                &program_id,
+                &program_data,
+                &a.pubkey(),
+                &multisig,
+                0,
+                ProposalAction::AddEntry(AccessEntry::new(granted)),
+            ),
+            approve(&b),
+            client::revoke_key(
+                &program_id,
+                &program_data,
+                &a.pubkey(),
+                &b.pubkey(),
+                &b.pubkey(),
+            ),
+        ],
+        &[&a, &b],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        process(&mut banks_client, &[execute(&b)], &[&b], recent_blockhash).await,
+        Err(custom_error(SolanaPamError::SignerRevoked))
+    );
+    assert_eq!(
+        process(&mut banks_client, &[execute(&c)], &[&c], recent_blockhash).await,
+        Err(custom_error(SolanaPamError::ThresholdNotMet))
+    );
+    // Leaving out the revoked admin's revocation does not get around it
+    let mut dodged = execute(&c);
+    dodged.accounts[6].pubkey = find_revocation_address(&program_id, &granted).0;
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[dodged],
+            &[&payer, &c],
+            recent_blockhash
+        )
+        .await,
+        Err(custom_error(SolanaPamError::WrongRevocationAddress))
+    );
+
+    process(
+        &mut banks_client,
+        &[approve(&c), execute(&c)],
+        &[&c],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &payer.pubkey()).await,
+        vec![granted]
+    );
+}
+
+#[tokio::test]
+async fn test_entry_roles() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let (admin, lead) = (Keypair::new(), Keypair::new());
+    program_test.add_account(admin.pubkey(), funded_account());
+    program_test.add_account(lead.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let owner = payer.pubkey();
+    let (member, newcomer, late) = (
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    );
+    let with_role = |pubkey, role| AccessEntry {
+        role,
+        ..AccessEntry::new(pubkey)
+    };
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner),
+            client::add_entry_to_access_list(
+                &program_id,
+                &program_data,
+                &owner,
+                with_role(admin.pubkey(), Role::Admin),
+            ),
+            client::add_to_access_list(&program_id, &program_data, &owner, &member),
+            client::set_entry_role(
+                &program_id,
+                &program_data,
+                &owner,
+                &owner,
+                &member,
+                Role::Sudo,
+            ),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &lead.pubkey(),
+                DELEGATE_ADD,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let (address, _) = find_access_list_address(&program_id, &owner);
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let list = AccessList::unpack(&account.data).unwrap();
+    assert_eq!(list.get(&member).unwrap().role, Role::Sudo);
+
+    // Only the roles right lets a delegate hand out more than logins
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_entry_as_delegate(
+                &program_id,
+                &program_data,
+                &lead.pubkey(),
+                &owner,
+                with_role(late, Role::Sudo),
+            )],
+            &[&lead],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::MissingDelegateRight))
+    );
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::set_entry_role(
+                &program_id,
+                &program_data,
+                &owner,
+                &owner,
+                &late,
+                Role::Sudo,
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::EntryNotFound))
+    );
+
+    // An admin changes the list without a delegate account
+    process(
+        &mut banks_client,
+        &[
+            client::add_entry_as_delegate(
+                &program_id,
+                &program_data,
+                &admin.pubkey(),
+                &owner,
+                AccessEntry::new(newcomer),
+            ),
+            client::set_entry_role(
+                &program_id,
+                &program_data,
+                &admin.pubkey(),
+                &owner,
+                &member,
+                Role::Login,
+            ),
+        ],
+        &[&admin],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let list = AccessList::unpack(&account.data).unwrap();
+    assert_eq!(list.get(&member).unwrap().role, Role::Login);
+    assert!(list.get(&newcomer).is_some());
+
+    // Until the owner takes the role away
+    process(
+        &mut banks_client,
+        &[client::set_entry_role(
+            &program_id,
+            &program_data,
+            &owner,
+            &owner,
+            &admin.pubkey(),
+            Role::Sudo,
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::remove_as_delegate(
+                &program_id,
+                &program_data,
+                &admin.pubkey(),
+                &owner,
+                &newcomer,
+            )],
+            &[&admin],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotAccessListOwner))
+    );
+}
+
+#[tokio::test]
+async fn test_roles_delegate_cannot_appoint_admins() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let lead = Keypair::new();
+    program_test.add_account(lead.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let owner = payer.pubkey();
+    let admin = Pubkey::new_unique();
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner),
+            client::add_entry_to_access_list(
+                &program_id,
+                &program_data,
+                &owner,
+                AccessEntry {
+                    role: Role::Admin,
+                    ..AccessEntry::new(admin)
+                },
+            ),
+            client::add_to_access_list(&program_id, &program_data, &owner, &lead.pubkey()),
+            client::set_delegate(
+                &program_id,
+                &program_data,
+                &owner,
+                &lead.pubkey(),
+                DELEGATE_MANAGE_ROLES,
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // An admin acts with every right, so a delegate with only some of them
+    // can neither make itself one nor demote the admins it has
+    for instruction in vec![
+        client::set_entry_role(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            &owner,
+            &lead.pubkey(),
+            Role::Admin,
+        ),
+        client::add_entry_as_delegate(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            &owner,
+            AccessEntry {
+                role: Role::Admin,
+                ..AccessEntry::new(lead.pubkey())
+            },
+        ),
+        client::set_entry_role(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            &owner,
+            &admin,
+            Role::Login,
+        ),
+    ] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[instruction],
+                &[&lead],
+                recent_blockhash
+            )
+            .await,
+            Err(custom_error(SolanaPamError::MissingDelegateRight))
+        );
+    }
+
+    // Roles short of admin are still its to hand out
+    process(
+        &mut banks_client,
+        &[client::set_entry_role(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            &owner,
+            &lead.pubkey(),
+            Role::Sudo,
+        )],
+        &[&lead],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let (address, _) = find_access_list_address(&program_id, &owner);
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let list = AccessList::unpack(&account.data).unwrap();
+    assert_eq!(list.get(&lead.pubkey()).unwrap().role, Role::Sudo);
+    assert_eq!(list.get(&admin).unwrap().role, Role::Admin);
+}
+
+async fn get_group(banks_client: &mut BanksClient, program_id: &Pubkey, name: &str) -> Group {
+    let (address, _) = find_group_address(program_id, name);
+    let account = banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .expect("group account");
+    assert_eq!(account.owner, *program_id);
+    Group::unpack(&account.data).unwrap()
+}
+
+#[tokio::test]
+async fn test_groups() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let (lead, stranger) = (Keypair::new(), Keypair::new());
+    program_test.add_account(lead.pubkey(), funded_account());
+    program_test.add_account(stranger.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let creator = payer.pubkey();
+    let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let (oncall, _) = find_group_address(&program_id, "oncall");
+    let (ops, _) = find_group_address(&program_id, "ops");
+
+    process(
+        &mut banks_client,
+        &[
+            client::create_group(&program_id, &program_data, &creator, "ops".to_string()),
+            client::create_group(&program_id, &program_data, &creator, "oncall".to_string()),
+            client::add_group_member(
+                &program_id,
+                &program_data,
+                &creator,
+                "ops".to_string(),
+                GroupMember::Key(alice),
+            ),
+            client::add_group_member(
+                &program_id,
+                &program_data,
+                &creator,
+                "ops".to_string(),
+                GroupMember::Group(oncall),
+            ),
+            client::add_group_member(
+                &program_id,
+                &program_data,
+                &creator,
+                "oncall".to_string(),
+                GroupMember::Key(bob),
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let group = get_group(&mut banks_client, &program_id, "ops").await;
+    assert_eq!(group.admins, vec![creator]);
+    assert_eq!(
+        group.members,
+        vec![GroupMember::Key(alice), GroupMember::Group(oncall)]
+    );
+
+    // Only an admin changes a group, and a group cannot contain itself
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_group_member(
+                &program_id,
+                &program_data,
+                &stranger.pubkey(),
+                "ops".to_string(),
+                GroupMember::Key(stranger.pubkey()),
+            )],
+            &[&stranger],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotGroupAdmin))
+    );
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_group_member(
+                &program_id,
+                &program_data,
+                &creator,
+                "ops".to_string(),
+                GroupMember::Group(ops),
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::InvalidGroup))
+    );
+
+    // Handing the group to another admin takes it away from the creator
+    process(
+        &mut banks_client,
+        &[client::set_group_admins(
+            &program_id,
+            &program_data,
+            &creator,
+            "ops".to_string(),
+            vec![lead.pubkey()],
+        )],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    process(
+        &mut banks_client,
+        &[client::remove_group_member(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            "ops".to_string(),
+            GroupMember::Key(alice),
+        )],
+        &[&lead],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let group = get_group(&mut banks_client, &program_id, "ops").await;
+    assert_eq!(group.admins, vec![lead.pubkey()]);
+    assert_eq!(group.members, vec![GroupMember::Group(oncall)]);
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::set_group_admins(
+                &program_id,
+                &program_data,
+                &creator,
+                "ops".to_string(),
+                vec![creator],
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::NotGroupAdmin))
+    );
+
+    // An access list names the group as an entry
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &creator),
+            client::add_entry_to_access_list(
+                &program_id,
+                &program_data,
+                &creator,
+                AccessEntry::group(ops),
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_access_list(&mut banks_client, &program_id, &creator).await,
+        vec![ops]
+    );
+}
+
+#[tokio::test]
+async fn test_revoked_group_admins() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let mut program_test = program_test(program_id, program_data);
+    let lead = Keypair::new();
+    program_test.add_account(lead.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let thief = Pubkey::new_unique();
+
+    process(
+        &mut banks_client,
+        &[
+            client::create_group(
+                &program_id,
+                &program_data,
+                &lead.pubkey(),
+                "ops".to_string(),
+            ),
+            client::revoke_key(
+                &program_id,
+                &program_data,
+                &payer.pubkey(),
+                &lead.pubkey(),
+                &lead.pubkey(),
+            ),
+        ],
+        &[&payer, &lead],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // A stolen admin key, once revoked, can neither let its holder into the
+    // group nor keep control of it
+    for instruction in vec![
+        client::add_group_member(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            "ops".to_string(),
+            GroupMember::Key(thief),
+        ),
+        client::set_group_admins(
+            &program_id,
+            &program_data,
+            &lead.pubkey(),
+            "ops".to_string(),
+            vec![thief],
+        ),
+    ] {
+        assert_eq!(
+            process(
+                &mut banks_client,
+                &[instruction],
+                &[&lead],
+                recent_blockhash
+            )
+            .await,
+            Err(custom_error(SolanaPamError::SignerRevoked))
+        );
+    }
+    let group = get_group(&mut banks_client, &program_id, "ops").await;
+    assert_eq!(group.admins, vec![lead.pubkey()]);
+    assert_eq!(group.members, vec![]);
+}
+
+#[tokio::test]
+async fn test_token_entries() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let (mut banks_client, payer, recent_blockhash) =
+        program_test(program_id, program_data).start().await;
+    let owner = payer.pubkey();
+    let badge = Pubkey::new_unique();
+
+    process(
+        &mut banks_client,
+        &[
+            client::init_access_list(&program_id, &program_data, &owner),
+            client::add_entry_to_access_list(
+                &program_id,
+                &program_data,
+                &owner,
+                AccessEntry::token(badge, 1),
+            ),
+        ],
+        &[&payer],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let (address, _) = find_access_list_address(&program_id, &owner);
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    let list = AccessList::unpack(&account.data).unwrap();
+    assert_eq!(list.get(&badge), Some(&AccessEntry::token(badge, 1)));
+
+    // An entry every key would satisfy is refused
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[client::add_entry_to_access_list(
+                &program_id,
+                &program_data,
+                &owner,
+                AccessEntry::token(Pubkey::new_unique(), 0),
+            )],
+            &[&payer],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::InvalidTokenGate))
+    );
+}
+
+#[tokio::test]
+async fn test_record_login() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let host = Keypair::new();
+    let mut program_test =
+        program_test_with_authority(program_id, program_data, &authority.pubkey());
+    program_test.add_account(host.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let record = |user: &Pubkey, service: &str| {
+        client::record_login(
+            &program_id,
+            &program_data,
+            &host.pubkey(),
+            user,
+            service.to_string(),
+            [7; 32],
+        )
+    };
+
+    // Only registered hosts keep a login log
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[record(&alice, "sshd")],
+            &[&host],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::HostNotRegistered))
+    );
+    process(
+        &mut banks_client,
+        &[client::register_host(
+            &program_id,
+            &program_data,
+            &authority.pubkey(),
+            &host.pubkey(),
+            "web-1".to_string(),
+            vec![],
+        )],
+        &[&payer, &authority, &host],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    process(
+        &mut banks_client,
+        &[record(&alice, "sshd"), record(&bob, "sudo")],
+        &[&host],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    let (address, _) = find_login_log_address(&program_id, &host.pubkey());
+    let account = banks_client.get_account(address).await.unwrap().unwrap();
+    assert_eq!(account.owner, program_id);
+    let log = LoginLog::unpack(&account.data).unwrap();
+    assert_eq!(log.host, host.pubkey());
+    assert_eq!(log.total, 2);
+    let records: Vec<_> = log.records().collect();
+    assert_eq!(records[0].user, alice);
+    assert_eq!(records[0].service, "sshd");
+    assert_eq!(records[0].remote_host_hash, [7; 32]);
+    assert_eq!(records[1].user, bob);
+    assert_eq!(records[1].service, "sudo");
+
+    // Service names longer than any PAM service are refused
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[record(&alice, &"s".repeat(33))],
+            &[&host],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::InvalidLoginRecord))
+    );
+}
+
+#[tokio::test]
+async fn test_record_session() {
+    let program_id = Pubkey::new_unique();
+    let program_data = Pubkey::new_unique();
+    let authority = Keypair::new();
+    let host = Keypair::new();
+    let mut program_test =
+        program_test_with_authority(program_id, program_data, &authority.pubkey());
+    program_test.add_account(host.pubkey(), funded_account());
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+    let alice = Pubkey::new_unique();
+    let record = |service: &str, opened_at| {
+        client::record_session(
+            &program_id,
+            &program_data,
+            &host.pubkey(),
+            &alice,
+            service.to_string(),
+            opened_at,
+        )
+    };
+
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[record("sshd", None)],
+            &[&host],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::HostNotRegistered))
+    );
+    process(
+        &mut banks_client,
+        &[client::register_host(
+            &program_id,
+            &program_data,
+            &authority.pubkey(),
+            &host.pubkey(),
+            "web-1".to_string(),
+            vec![],
+        )],
+        &[&payer, &authority, &host],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+
+    // Sessions are only attested in the transaction, no account is written
+    process(
+        &mut banks_client,
+        &[record("sshd", None), record("sshd", Some(1_000))],
+        &[&host],
+        recent_blockhash,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[record(&"s".repeat(33), Some(1_000))],
+            &[&host],
+            recent_blockhash,
+        )
+        .await,
+        Err(custom_error(SolanaPamError::InvalidLoginRecord))
+    );
+
+    // Only the host key can attest its sessions
+    let mut instruction = record("sshd", None);
+    instruction.accounts[1].is_signer = false;
+    assert_eq!(
+        process(
+            &mut banks_client,
+            &[instruction],
+            &[&payer],
+            recent_blockhash
+        )
+        .await,
+        Err(custom_error(SolanaPamError::MissingSigner))
+    );
 }
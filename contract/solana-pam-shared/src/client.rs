@@ -0,0 +1,759 @@
+//! Instruction builders for clients of the program
+use crate::instructions::{
+    find_access_list_address, find_account_policy_address, find_delegate_address,
+    find_group_address, find_host_address, find_loader_data_address, find_login_log_address,
+    find_multisig_address, find_proposal_address, find_revocation_address, find_username_address,
+    ProgInstruction,
+};
+use crate::state::{AccessEntry, GroupMember, ProposalAction, Role};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+
+/// Initialize the program state with `authority` as its administrator
+pub fn init(program_id: &Pubkey, program_data: &Pubkey, authority: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*program_data, false),
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new_readonly(find_loader_data_address(program_id).0, false),
+        ],
+        data: ProgInstruction::Init.pack(),
+    }
+}
+
+/// Hand administration of the program from `authority` to `new_authority`
+pub fn set_authority(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    authority: &Pubkey,
+    new_authority: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*program_data, false),
+            AccountMeta::new_readonly(*authority, true),
+        ],
+        data: ProgInstruction::SetAuthority(*new_authority).pack(),
+    }
+}
+
+/// Create `user`'s access list at its program-derived address
+pub fn init_access_list(program_id: &Pubkey, program_data: &Pubkey, user: &Pubkey) -> Instruction {
+    let (access_list, _) = find_access_list_address(program_id, user);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*user, true),
+            AccountMeta::new(access_list, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: ProgInstruction::InitAccessList.pack(),
+    }
+}
+
+/// Change `owner`'s access list, signed by the owner, one of its delegates or
+/// a key with an admin entry on it
+fn change_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    signer: &Pubkey,
+    owner: &Pubkey,
+    instruction: ProgInstruction,
+) -> Instruction {
+    let (access_list, _) = find_access_list_address(program_id, owner);
+    let (delegate_account, _) = find_delegate_address(program_id, &access_list, signer);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(*signer, true),
+            AccountMeta::new(access_list, false),
+            AccountMeta::new_readonly(delegate_account, false),
+            AccountMeta::new_readonly(find_revocation_address(program_id, signer).0, false),
+        ],
+        data: instruction.pack(),
+    }
+}
+
+/// Add `add` to `user`'s access list with no time bounds
+pub fn add_to_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    add: &Pubkey,
+) -> Instruction {
+    add_entry_to_access_list(program_id, program_data, user, AccessEntry::new(*add))
+}
+
+/// Add `entry` to `user`'s access list, or update the bounds of its key
+pub fn add_entry_to_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    entry: AccessEntry,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        user,
+        user,
+        ProgInstruction::AddPKToAccessListAccount(entry),
+    )
+}
+
+/// Remove `remove` from `user`'s access list
+pub fn remove_from_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    remove: &Pubkey,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        user,
+        user,
+        ProgInstruction::RemovePKToAccessListAccount(*remove),
+    )
+}
+
+/// Add each of `keys` to `user`'s access list with no time bounds
+pub fn add_many_to_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    keys: Vec<Pubkey>,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        user,
+        user,
+        ProgInstruction::AddManyToAccessList(keys),
+    )
+}
+
+/// Remove each of `keys` from `user`'s access list
+pub fn remove_many_from_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    keys: Vec<Pubkey>,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        user,
+        user,
+        ProgInstruction::RemoveManyFromAccessList(keys),
+    )
+}
+
+/// Make `user`'s access list hold exactly `keys`
+pub fn replace_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    keys: Vec<Pubkey>,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        user,
+        user,
+        ProgInstruction::ReplaceAccessList(keys),
+    )
+}
+
+/// Close `user`'s access list, sending its lamports to `recipient`
+pub fn close_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    recipient: &Pubkey,
+) -> Instruction {
+    let (access_list, _) = find_access_list_address(program_id, user);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(*user, true),
+            AccountMeta::new(access_list, false),
+            AccountMeta::new(*recipient, false),
+        ],
+        data: ProgInstruction::CloseAccessList.pack(),
+    }
+}
+
+/// Close `user`'s list at `access_list`, one created before lists moved to
+/// program-derived addresses that the program state's map still records
+pub fn close_legacy_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    access_list: &Pubkey,
+    recipient: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*program_data, false),
+            AccountMeta::new_readonly(*user, true),
+            AccountMeta::new(*access_list, false),
+            AccountMeta::new(*recipient, false),
+        ],
+        data: ProgInstruction::CloseAccessList.pack(),
+    }
+}
+
+/// Add `entry` to `owner`'s access list as `delegate`
+pub fn add_entry_as_delegate(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    delegate: &Pubkey,
+    owner: &Pubkey,
+    entry: AccessEntry,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        delegate,
+        owner,
+        ProgInstruction::AddPKToAccessListAccount(entry),
+    )
+}
+
+/// Remove `remove` from `owner`'s access list as `delegate`
+pub fn remove_as_delegate(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    delegate: &Pubkey,
+    owner: &Pubkey,
+    remove: &Pubkey,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        delegate,
+        owner,
+        ProgInstruction::RemovePKToAccessListAccount(*remove),
+    )
+}
+
+/// Give `pubkey` `role` on `owner`'s access list, signed by `signer`
+pub fn set_entry_role(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    signer: &Pubkey,
+    owner: &Pubkey,
+    pubkey: &Pubkey,
+    role: Role,
+) -> Instruction {
+    change_access_list(
+        program_id,
+        program_data,
+        signer,
+        owner,
+        ProgInstruction::SetEntryRole {
+            pubkey: *pubkey,
+            role,
+        },
+    )
+}
+
+/// Let `delegate` change `user`'s access list within `rights`
+pub fn set_delegate(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    delegate: &Pubkey,
+    rights: u8,
+) -> Instruction {
+    let (access_list, _) = find_access_list_address(program_id, user);
+    let (delegate_account, _) = find_delegate_address(program_id, &access_list, delegate);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*user, true),
+            AccountMeta::new_readonly(access_list, false),
+            AccountMeta::new(delegate_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_revocation_address(program_id, user).0, false),
+        ],
+        data: ProgInstruction::SetDelegate {
+            delegate: *delegate,
+            rights,
+        }
+        .pack(),
+    }
+}
+
+/// Take away all of `delegate`'s rights over `user`'s access list
+pub fn revoke_delegate(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    delegate: &Pubkey,
+) -> Instruction {
+    let (access_list, _) = find_access_list_address(program_id, user);
+    let (delegate_account, _) = find_delegate_address(program_id, &access_list, delegate);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*user, true),
+            AccountMeta::new_readonly(access_list, false),
+            AccountMeta::new(delegate_account, false),
+        ],
+        data: ProgInstruction::RevokeDelegate(*delegate).pack(),
+    }
+}
+
+/// Rewrite `user`'s list at `access_list` in the current layout
+///
+/// `access_list` is the user's program-derived address, or for lists created
+/// before those, the address recorded for them in the program state.
+pub fn migrate_access_list(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    access_list: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(*user, true),
+            AccountMeta::new(*access_list, false),
+        ],
+        data: ProgInstruction::MigrateAccessList.pack(),
+    }
+}
+
+/// Register the host holding key `host` under `label` and `tags`
+pub fn register_host(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    authority: &Pubkey,
+    host: &Pubkey,
+    label: String,
+    tags: Vec<String>,
+) -> Instruction {
+    let (host_account, _) = find_host_address(program_id, host);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new_readonly(*host, true),
+            AccountMeta::new(host_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: ProgInstruction::RegisterHost { label, tags }.pack(),
+    }
+}
+
+/// Claim `username` for `user`
+pub fn claim_username(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    username: String,
+) -> Instruction {
+    let (username_account, _) = find_username_address(program_id, &username);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*user, true),
+            AccountMeta::new(username_account, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: ProgInstruction::ClaimUsername(username).pack(),
+    }
+}
+
+/// Give up `user`'s claim on `username`
+pub fn release_username(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    username: String,
+) -> Instruction {
+    let (username_account, _) = find_username_address(program_id, &username);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*user, true),
+            AccountMeta::new(username_account, false),
+        ],
+        data: ProgInstruction::ReleaseUsername(username).pack(),
+    }
+}
+
+/// Create the policy for unix account `account`, administered by `admin`
+pub fn init_account_policy(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    authority: &Pubkey,
+    account: String,
+    admin: &Pubkey,
+) -> Instruction {
+    let (policy, _) = find_account_policy_address(program_id, &account);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(policy, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: ProgInstruction::InitAccountPolicy {
+            account,
+            admin: *admin,
+        }
+        .pack(),
+    }
+}
+
+fn change_account_policy(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    policy: &Pubkey,
+    instruction: ProgInstruction,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(*policy, false),
+            AccountMeta::new_readonly(find_revocation_address(program_id, admin).0, false),
+        ],
+        data: instruction.pack(),
+    }
+}
+
+/// Let `entry`'s key log in as `account`, or update its entry
+pub fn add_to_account_policy(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    account: String,
+    entry: AccessEntry,
+) -> Instruction {
+    let policy = find_account_policy_address(program_id, &account).0;
+    change_account_policy(
+        program_id,
+        program_data,
+        admin,
+        &policy,
+        ProgInstruction::AddToAccountPolicy { account, entry },
+    )
+}
+
+/// Stop `pubkey` logging in as `account`
+pub fn remove_from_account_policy(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    account: String,
+    pubkey: &Pubkey,
+) -> Instruction {
+    let policy = find_account_policy_address(program_id, &account).0;
+    change_account_policy(
+        program_id,
+        program_data,
+        admin,
+        &policy,
+        ProgInstruction::RemoveFromAccountPolicy {
+            account,
+            pubkey: *pubkey,
+        },
+    )
+}
+
+/// Hand `user`'s access list to a multisig of `admins`, `threshold` of whom
+/// must approve each change
+pub fn create_multisig(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+    admins: Vec<Pubkey>,
+    threshold: u8,
+) -> Instruction {
+    let (access_list, _) = find_access_list_address(program_id, user);
+    let (multisig, _) = find_multisig_address(program_id, &access_list);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*user, true),
+            AccountMeta::new(access_list, false),
+            AccountMeta::new(multisig, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_revocation_address(program_id, user).0, false),
+        ],
+        data: ProgInstruction::CreateMultisig { admins, threshold }.pack(),
+    }
+}
+
+/// Propose `action` as proposal number `index` of `multisig`, which must be
+/// the multisig's current `proposal_count`
+pub fn propose(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    proposer: &Pubkey,
+    multisig: &Pubkey,
+    index: u64,
+    action: ProposalAction,
+) -> Instruction {
+    let (proposal, _) = find_proposal_address(program_id, multisig, index);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*proposer, true),
+            AccountMeta::new(*multisig, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(find_revocation_address(program_id, proposer).0, false),
+        ],
+        data: ProgInstruction::Propose(action).pack(),
+    }
+}
+
+/// Approve proposal number `index` of `multisig` as `admin`
+pub fn approve_proposal(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    multisig: &Pubkey,
+    index: u64,
+) -> Instruction {
+    let (proposal, _) = find_proposal_address(program_id, multisig, index);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new_readonly(*multisig, false),
+            AccountMeta::new(proposal, false),
+            AccountMeta::new_readonly(find_revocation_address(program_id, admin).0, false),
+        ],
+        data: ProgInstruction::ApproveProposal.pack(),
+    }
+}
+
+/// Execute proposal number `index` of `multisig`, which governs `access_list`
+/// and is run by `admins`, in the multisig's order
+pub fn execute_proposal(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    multisig: &Pubkey,
+    index: u64,
+    access_list: &Pubkey,
+    admins: &[Pubkey],
+) -> Instruction {
+    let (proposal, _) = find_proposal_address(program_id, multisig, index);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*program_data, false),
+        AccountMeta::new_readonly(*admin, true),
+        AccountMeta::new(*multisig, false),
+        AccountMeta::new(proposal, false),
+        AccountMeta::new(*access_list, false),
+    ];
+    for admin in admins {
+        let (revocation_account, _) = find_revocation_address(program_id, admin);
+        accounts.push(AccountMeta::new_readonly(revocation_account, false));
+    }
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: ProgInstruction::ExecuteProposal.pack(),
+    }
+}
+
+/// Revoke `pubkey` everywhere, signed by `revoker`: the program authority or
+/// the key itself
+pub fn revoke_key(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    payer: &Pubkey,
+    revoker: &Pubkey,
+    pubkey: &Pubkey,
+) -> Instruction {
+    let (revocation, _) = find_revocation_address(program_id, pubkey);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(*revoker, true),
+            AccountMeta::new(revocation, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: ProgInstruction::RevokeKey(*pubkey).pack(),
+    }
+}
+
+/// Record that `user` logged in to `host` through `service`, signed by the
+/// host key
+pub fn record_login(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    host: &Pubkey,
+    user: &Pubkey,
+    service: String,
+    remote_host_hash: [u8; 32],
+) -> Instruction {
+    let (host_account, _) = find_host_address(program_id, host);
+    let (login_log, _) = find_login_log_address(program_id, host);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*host, true),
+            AccountMeta::new_readonly(host_account, false),
+            AccountMeta::new(login_log, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: ProgInstruction::RecordLogin {
+            user: *user,
+            service,
+            remote_host_hash,
+        }
+        .pack(),
+    }
+}
+
+/// Attest that a session `user` opened on `host` started, or ended when
+/// `opened_at` is given, signed by the host key
+pub fn record_session(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    host: &Pubkey,
+    user: &Pubkey,
+    service: String,
+    opened_at: Option<i64>,
+) -> Instruction {
+    let (host_account, _) = find_host_address(program_id, host);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(*host, true),
+            AccountMeta::new_readonly(host_account, false),
+        ],
+        data: ProgInstruction::RecordSession {
+            user: *user,
+            service,
+            opened_at,
+        }
+        .pack(),
+    }
+}
+
+/// Create the group called `name` with `creator` as its admin
+pub fn create_group(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    creator: &Pubkey,
+    name: String,
+) -> Instruction {
+    let (group, _) = find_group_address(program_id, &name);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new(*creator, true),
+            AccountMeta::new(group, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: ProgInstruction::CreateGroup { name }.pack(),
+    }
+}
+
+/// Change the group at `group`, signed by one of its admins
+fn change_group(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    group: &Pubkey,
+    instruction: ProgInstruction,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*program_data, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(*group, false),
+            AccountMeta::new_readonly(find_revocation_address(program_id, admin).0, false),
+        ],
+        data: instruction.pack(),
+    }
+}
+
+/// Add `member` to the group called `name`
+pub fn add_group_member(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    name: String,
+    member: GroupMember,
+) -> Instruction {
+    let group = find_group_address(program_id, &name).0;
+    change_group(
+        program_id,
+        program_data,
+        admin,
+        &group,
+        ProgInstruction::AddGroupMember { name, member },
+    )
+}
+
+/// Remove `member` from the group called `name`
+pub fn remove_group_member(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    name: String,
+    member: GroupMember,
+) -> Instruction {
+    let group = find_group_address(program_id, &name).0;
+    change_group(
+        program_id,
+        program_data,
+        admin,
+        &group,
+        ProgInstruction::RemoveGroupMember { name, member },
+    )
+}
+
+/// Replace the admins of the group called `name`
+pub fn set_group_admins(
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    admin: &Pubkey,
+    name: String,
+    admins: Vec<Pubkey>,
+) -> Instruction {
+    let group = find_group_address(program_id, &name).0;
+    change_group(
+        program_id,
+        program_data,
+        admin,
+        &group,
+        ProgInstruction::SetGroupAdmins { name, admins },
+    )
+}
@@ -0,0 +1,289 @@
+//! Error types
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
+use thiserror::Error;
+
+/// Errors returned by the program as `ProgramError::Custom`
+///
+/// The discriminants are part of the program's interface and must not change.
+#[derive(Clone, Copy, Debug, Eq, Error, FromPrimitive, PartialEq)]
+pub enum SolanaPamError {
+    /// The instruction data does not decode to a known instruction
+    ///
+    /// Only returned by versions before instructions carried a version byte,
+    /// malformed data is now `ProgramError::InvalidInstructionData`.
+    #[error("Invalid instruction")]
+    InvalidInstruction = 11,
+    /// The access list account data is not a valid access list
+    #[error("Malformed access list")]
+    MalformedAccessList = 12,
+    /// The access list has no room for another key
+    #[error("Access list is full")]
+    AccessListFull = 13,
+    /// The program state account data could not be decoded
+    #[error("Malformed program state")]
+    MalformedProgramData = 14,
+    /// The program state does not fit in its account
+    #[error("Program state account is full")]
+    ProgramDataFull = 15,
+    /// The instruction data starts with a version this program does not understand
+    #[error("Unsupported instruction version")]
+    UnsupportedInstructionVersion = 16,
+    /// The program state has not been set up with `Init`
+    #[error("Program state is not initialized")]
+    UninitializedProgramData = 17,
+    /// `Init` was sent for program state that is already initialized
+    #[error("Program state is already initialized")]
+    AlreadyInitialized = 18,
+    /// A grant's `not_before` is not earlier than its `expires_at`
+    #[error("Grant would never be valid")]
+    InvalidGrantWindow = 19,
+    /// An entry's scope is empty, names too many hosts or has an oversized name
+    #[error("Invalid host scope")]
+    InvalidHostScope = 20,
+    /// A host label or tag is empty or too long, or there are too many tags
+    #[error("Invalid host label or tags")]
+    InvalidHostName = 21,
+    /// The host account data is not a valid host account
+    #[error("Malformed host account")]
+    MalformedHostAccount = 22,
+    /// The username is not a valid POSIX login name
+    #[error("Invalid username")]
+    InvalidUsername = 23,
+    /// The username is already claimed
+    #[error("Username is already claimed")]
+    UsernameTaken = 24,
+    /// The username account data is not a valid username account
+    #[error("Malformed username account")]
+    MalformedUsernameAccount = 25,
+    /// The account policy data is not a valid account policy
+    #[error("Malformed account policy")]
+    MalformedAccountPolicy = 26,
+    /// The account policy has no room for another key
+    #[error("Account policy is full")]
+    AccountPolicyFull = 27,
+    /// A multisig has no admins, too many, repeated ones, or a threshold it
+    /// can never reach
+    #[error("Invalid multisig admins or threshold")]
+    InvalidMultisig = 28,
+    /// The multisig account data is not a valid multisig
+    #[error("Malformed multisig account")]
+    MalformedMultisig = 29,
+    /// The proposal account data is not a valid proposal
+    #[error("Malformed proposal")]
+    MalformedProposal = 30,
+    /// The proposal has already been executed
+    #[error("Proposal was already executed")]
+    ProposalExecuted = 31,
+    /// The proposal has fewer approvals than the multisig's threshold
+    #[error("Proposal does not have enough approvals")]
+    ThresholdNotMet = 32,
+    /// The multisig's admins changed since the proposal was made
+    #[error("Proposal was made under an earlier multisig")]
+    StaleProposal = 33,
+    /// Delegate rights are empty or contain unknown bits
+    #[error("Invalid delegate rights")]
+    InvalidDelegateRights = 34,
+    /// The delegate account data is not a valid delegate record
+    #[error("Malformed delegate account")]
+    MalformedDelegate = 35,
+    /// A bulk instruction carries more than `MAX_BULK_KEYS` keys
+    #[error("Too many keys for one instruction")]
+    TooManyKeys = 36,
+    /// The access list account was closed by `CloseAccessList`
+    #[error("Access list has been closed")]
+    AccessListClosed = 37,
+    /// The revocation account data is not a valid revocation record
+    #[error("Malformed revocation account")]
+    MalformedRevocation = 38,
+    /// The key has no entry on the access list
+    #[error("Key is not on the access list")]
+    EntryNotFound = 39,
+    /// The group account data is not a valid group
+    #[error("Malformed group account")]
+    MalformedGroup = 40,
+    /// The group account has no room for another member
+    #[error("Group is full")]
+    GroupFull = 41,
+    /// A group name, admin set or member that is not allowed, such as a
+    /// group nested in itself
+    #[error("Invalid group")]
+    InvalidGroup = 42,
+    /// A token entry that any key would satisfy
+    #[error("Token entry must require at least one token")]
+    InvalidTokenGate = 43,
+    /// The login log account data is not a valid login log
+    #[error("Malformed login log account")]
+    MalformedLoginLog = 44,
+    /// A login or session record with a service name too long to keep
+    #[error("Invalid login record")]
+    InvalidLoginRecord = 45,
+
+    /// A required signature is missing
+    #[error("Missing required signature")]
+    MissingSigner = 111,
+    /// The signer is not the owner of the access list
+    #[error("Signer does not own the access list")]
+    NotAccessListOwner = 112,
+    /// The access list account is not at the signer's program-derived address
+    #[error("Access list account is not at the expected address")]
+    WrongAccessListAddress = 113,
+    /// The account passed as the system program is not the system program
+    #[error("Expected the system program")]
+    WrongSystemProgram = 114,
+    /// The signer is not the program's authority
+    #[error("Signer is not the program authority")]
+    NotAuthority = 115,
+    /// The program state account is not owned by the program
+    #[error("Program state account is not owned by the program")]
+    WrongProgramDataOwner = 116,
+    /// The program state account could be purged for rent
+    #[error("Program state account is not rent exempt")]
+    NotRentExempt = 117,
+    /// The host account is not at the host key's program-derived address
+    #[error("Host account is not at the expected address")]
+    WrongHostAddress = 118,
+    /// The signer did not claim the username
+    #[error("Signer does not own the username")]
+    NotUsernameOwner = 119,
+    /// The username account is not at the username's program-derived address
+    #[error("Username account is not at the expected address")]
+    WrongUsernameAddress = 120,
+    /// The signer is not the account policy's admin
+    #[error("Signer does not administer the account policy")]
+    NotPolicyAdmin = 121,
+    /// The account policy is not at the account name's program-derived address
+    #[error("Account policy is not at the expected address")]
+    WrongAccountPolicyAddress = 122,
+    /// The access list is governed by a multisig and only changes through proposals
+    #[error("Access list is governed by a multisig")]
+    GovernedByMultisig = 123,
+    /// The signer is not one of the multisig's admins
+    #[error("Signer is not a multisig admin")]
+    NotMultisigAdmin = 124,
+    /// The multisig account is not the one governing the access list or proposal
+    #[error("Multisig account is not at the expected address")]
+    WrongMultisigAddress = 125,
+    /// The proposal account is not at the expected program-derived address
+    #[error("Proposal account is not at the expected address")]
+    WrongProposalAddress = 126,
+    /// The delegate does not hold the right the change needs
+    #[error("Delegate lacks the right for this change")]
+    MissingDelegateRight = 127,
+    /// The delegate account is not at the list and delegate's program-derived address
+    #[error("Delegate account is not at the expected address")]
+    WrongDelegateAddress = 128,
+    /// Only the program authority or the key itself can revoke a key
+    #[error("Signer can neither revoke keys nor is the key being revoked")]
+    NotRevoker = 129,
+    /// The revocation account is not at the key's program-derived address
+    #[error("Revocation account is not at the expected address")]
+    WrongRevocationAddress = 130,
+    /// The signer is not one of the group's admins
+    #[error("Signer is not a group admin")]
+    NotGroupAdmin = 131,
+    /// The group account is not at the address derived from its name
+    #[error("Group account is not at the expected address")]
+    WrongGroupAddress = 132,
+    /// The signer has no host account, so it cannot record logins
+    #[error("Host is not registered")]
+    HostNotRegistered = 133,
+    /// The login log account is not at the host's program-derived address
+    #[error("Login log account is not at the expected address")]
+    WrongLoginLogAddress = 134,
+    /// `Init` was not signed by the program's upgrade authority
+    #[error("Signer is not the program's upgrade authority")]
+    NotUpgradeAuthority = 135,
+    /// The signer changes a list, a policy, a group or a multisig's proposals
+    /// but was revoked with `RevokeKey`
+    #[error("Signer's key has been revoked")]
+    SignerRevoked = 136,
+}
+
+impl From<SolanaPamError> for ProgramError {
+    fn from(e: SolanaPamError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for SolanaPamError {
+    fn type_of() -> &'static str {
+        "SolanaPamError"
+    }
+}
+
+impl PrintProgramError for SolanaPamError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        msg!("Error: {}", self);
+    }
+}
+
+impl SolanaPamError {
+    /// Look up the error behind a `ProgramError::Custom` code
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::from_u32(code)
+    }
+}
+
+/// Describe a custom program error code returned by a failed transaction
+pub fn describe_error_code(code: u32) -> String {
+    match SolanaPamError::from_code(code) {
+        Some(e) => format!("{} (error {})", e, code),
+        None => format!("unknown program error {}", code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_codes_round_trip() {
+        for error in &[
+            SolanaPamError::InvalidInstruction,
+            SolanaPamError::MalformedAccessList,
+            SolanaPamError::AccessListFull,
+            SolanaPamError::UnsupportedInstructionVersion,
+            SolanaPamError::AlreadyInitialized,
+            SolanaPamError::MissingSigner,
+            SolanaPamError::NotAccessListOwner,
+            SolanaPamError::WrongAccessListAddress,
+            SolanaPamError::NotRentExempt,
+            SolanaPamError::UsernameTaken,
+            SolanaPamError::WrongUsernameAddress,
+            SolanaPamError::ThresholdNotMet,
+            SolanaPamError::GovernedByMultisig,
+            SolanaPamError::MissingDelegateRight,
+            SolanaPamError::TooManyKeys,
+            SolanaPamError::AccessListClosed,
+            SolanaPamError::NotRevoker,
+            SolanaPamError::EntryNotFound,
+            SolanaPamError::GroupFull,
+            SolanaPamError::InvalidTokenGate,
+            SolanaPamError::InvalidLoginRecord,
+            SolanaPamError::NotGroupAdmin,
+            SolanaPamError::HostNotRegistered,
+            SolanaPamError::NotUpgradeAuthority,
+            SolanaPamError::SignerRevoked,
+        ] {
+            let code = match ProgramError::from(*error) {
+                ProgramError::Custom(code) => code,
+                e => panic!("unexpected {:?}", e),
+            };
+            assert_eq!(SolanaPamError::from_code(code), Some(*error));
+        }
+        assert_eq!(SolanaPamError::from_code(0), None);
+        assert_eq!(
+            describe_error_code(112),
+            "Signer does not own the access list (error 112)"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Events the program logs when it changes state
+//!
+//! Each successful state change logs one line, `EVENT_LOG_PREFIX` followed by
+//! the base64 of `EVENT_VERSION` and the Borsh encoding of an `Event`, so an
+//! indexer can follow access lists from transaction logs without reading
+//! accounts. Logs of failed transactions are kept by the cluster too, so only
+//! the logs of transactions that succeeded should be parsed.
+use crate::state::{AccessEntry, GroupMember, ProposalAction, Role};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{msg, pubkey::Pubkey};
+use thiserror::Error;
+
+/// Start of the program log message carrying an event
+pub const EVENT_LOG_PREFIX: &str = "solana-pam event: ";
+/// Leads every encoded event, bumped when an existing variant's layout changes
+///
+/// New variants are only ever appended, so they do not bump the version.
+pub const EVENT_VERSION: u8 = 1;
+
+/// A change to the program's state
+///
+/// The variant order is part of the program's interface and must not change.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The program state was initialized with `authority`
+    Initialized { authority: Pubkey },
+    /// The program authority was handed to `authority`
+    AuthorityChanged { authority: Pubkey },
+    /// `owner` created the access list at `access_list`
+    AccessListCreated { owner: Pubkey, access_list: Pubkey },
+    /// The headerless list at `access_list` was given a header naming `owner`
+    AccessListMigrated { owner: Pubkey, access_list: Pubkey },
+    /// An entry was added to a list, or replaced the key's entry that allows
+    /// or denies as it does
+    KeyAdded {
+        access_list: Pubkey,
+        entry: AccessEntry,
+    },
+    /// A key's entries were removed from a list
+    KeyRemoved { access_list: Pubkey, pubkey: Pubkey },
+    /// Keys were added to a list with `AddManyToAccessList`
+    KeysAdded {
+        access_list: Pubkey,
+        keys: Vec<Pubkey>,
+    },
+    /// Keys were removed from a list with `RemoveManyFromAccessList`
+    KeysRemoved {
+        access_list: Pubkey,
+        keys: Vec<Pubkey>,
+    },
+    /// A list's entries were replaced with plain entries for `keys`
+    AccessListReplaced {
+        access_list: Pubkey,
+        keys: Vec<Pubkey>,
+    },
+    /// The role of `pubkey`'s entry changed
+    RoleChanged {
+        access_list: Pubkey,
+        pubkey: Pubkey,
+        role: Role,
+    },
+    /// `owner` closed the list at `access_list`
+    AccessListClosed { owner: Pubkey, access_list: Pubkey },
+    /// The host key `host` was registered
+    HostRegistered { host: Pubkey, label: String },
+    /// `owner` claimed `username`
+    UsernameClaimed { username: String, owner: Pubkey },
+    /// `owner` released `username`
+    UsernameReleased { username: String, owner: Pubkey },
+    /// A policy for the unix account `account` was created, run by `admin`
+    AccountPolicyCreated { account: String, admin: Pubkey },
+    /// An entry was added to the policy for `account`
+    AccountPolicyKeyAdded { account: String, entry: AccessEntry },
+    /// A key was removed from the policy for `account`
+    AccountPolicyKeyRemoved { account: String, pubkey: Pubkey },
+    /// The list at `access_list` came under the multisig at `multisig`
+    MultisigCreated {
+        access_list: Pubkey,
+        multisig: Pubkey,
+        admins: Vec<Pubkey>,
+        threshold: u8,
+    },
+    /// `proposer` opened the proposal at `proposal`
+    ProposalCreated {
+        multisig: Pubkey,
+        proposal: Pubkey,
+        proposer: Pubkey,
+        action: ProposalAction,
+    },
+    /// `admin` approved the proposal at `proposal`
+    ProposalApproved { proposal: Pubkey, admin: Pubkey },
+    /// The proposal at `proposal` was carried out
+    ProposalExecuted {
+        proposal: Pubkey,
+        action: ProposalAction,
+    },
+    /// `delegate` was given `rights` over a list, or had them changed
+    DelegateSet {
+        access_list: Pubkey,
+        delegate: Pubkey,
+        rights: u8,
+    },
+    /// `delegate` lost its rights over a list
+    DelegateRevoked {
+        access_list: Pubkey,
+        delegate: Pubkey,
+    },
+    /// `revoked_by` revoked `pubkey` everywhere
+    KeyRevoked { pubkey: Pubkey, revoked_by: Pubkey },
+    /// The group `name` was created at `group`
+    GroupCreated { group: Pubkey, name: String },
+    /// `member` was added to a group
+    GroupMemberAdded { group: Pubkey, member: GroupMember },
+    /// `member` was removed from a group
+    GroupMemberRemoved { group: Pubkey, member: GroupMember },
+    /// A group's admins were replaced with `admins`
+    GroupAdminsSet { group: Pubkey, admins: Vec<Pubkey> },
+    /// `host` recorded a login by `user`
+    LoginRecorded {
+        host: Pubkey,
+        user: Pubkey,
+        service: String,
+    },
+    /// `host` attested that a session of `user` started
+    SessionOpened {
+        host: Pubkey,
+        user: Pubkey,
+        service: String,
+    },
+    /// `host` attested that a session of `user` that started at `opened_at`,
+    /// by the host's clock, ended at `closed_at`, by the cluster's
+    SessionClosed {
+        host: Pubkey,
+        user: Pubkey,
+        service: String,
+        opened_at: i64,
+        closed_at: i64,
+    },
+}
+
+/// Why a logged event could not be read
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum EventError {
+    /// The message after the prefix is not base64
+    #[error("Event is not valid base64")]
+    InvalidEncoding,
+    /// The event was written by a newer program
+    #[error("Unsupported event version {0}")]
+    UnsupportedVersion(u8),
+    /// The event does not decode to a known variant
+    #[error("Malformed event")]
+    Malformed,
+}
+
+impl Event {
+    pub fn pack(&self) -> Vec<u8> {
+        let mut output = vec![EVENT_VERSION];
+        // Writing into a Vec cannot fail
+        self.serialize(&mut output).unwrap();
+        output
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, EventError> {
+        let (&version, rest) = input.split_first().ok_or(EventError::Malformed)?;
+        if version != EVENT_VERSION {
+            return Err(EventError::UnsupportedVersion(version));
+        }
+        Self::try_from_slice(rest).map_err(|_| EventError::Malformed)
+    }
+
+    /// The program log message carrying the event
+    pub fn to_log_message(&self) -> String {
+        format!("{}{}", EVENT_LOG_PREFIX, base64::encode(self.pack()))
+    }
+
+    /// Read an event from a program log message, `None` if it carries none
+    pub fn from_log_message(message: &str) -> Option<Result<Self, EventError>> {
+        let encoded = message.strip_prefix(EVENT_LOG_PREFIX)?;
+        Some(
+            base64::decode(encoded)
+                .map_err(|_| EventError::InvalidEncoding)
+                .and_then(|data| Self::unpack(&data)),
+        )
+    }
+
+    /// Log the event from the program
+    pub fn emit(&self) {
+        msg!("{}", self.to_log_message());
+    }
+}
+
+/// Pull the events `program_id` logged out of a transaction's log messages
+///
+/// Only messages logged while `program_id` is the running program count, so
+/// another program in the transaction cannot forge events by logging the
+/// prefix itself.
+pub fn parse_events<S: AsRef<str>>(
+    program_id: &Pubkey,
+    logs: &[S],
+) -> Result<Vec<Event>, EventError> {
+    let program_id = program_id.to_string();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+    for line in logs {
+        let line = line.as_ref();
+        if let Some(message) = line.strip_prefix("Program log: ") {
+            if stack.last() == Some(&program_id.as_str()) {
+                if let Some(event) = Event::from_log_message(message) {
+                    events.push(event?);
+                }
+            }
+            continue;
+        }
+        let mut words = line.split(' ');
+        match (words.next(), words.next(), words.next()) {
+            (Some("Program"), Some(program), Some("invoke")) => stack.push(program),
+            (Some("Program"), Some(_), Some("success"))
+            | (Some("Program"), Some(_), Some("failed:")) => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_round_trip() {
+        let access_list = Pubkey::new_unique();
+        for event in &[
+            Event::Initialized {
+                authority: Pubkey::new_unique(),
+            },
+            Event::KeyAdded {
+                access_list,
+                entry: AccessEntry::token(Pubkey::new_unique(), 5),
+            },
+            Event::RoleChanged {
+                access_list,
+                pubkey: Pubkey::new_unique(),
+                role: Role::Admin,
+            },
+            Event::LoginRecorded {
+                host: Pubkey::new_unique(),
+                user: Pubkey::new_unique(),
+                service: "sshd".to_string(),
+            },
+        ] {
+            assert_eq!(Event::unpack(&event.pack()).as_ref(), Ok(event));
+            assert_eq!(
+                Event::from_log_message(&event.to_log_message()),
+                Some(Ok(event.clone()))
+            );
+        }
+
+        let mut data = Event::AccessListClosed {
+            owner: Pubkey::new_unique(),
+            access_list,
+        }
+        .pack();
+        data[0] = EVENT_VERSION + 1;
+        assert_eq!(
+            Event::unpack(&data),
+            Err(EventError::UnsupportedVersion(EVENT_VERSION + 1))
+        );
+        assert_eq!(Event::unpack(&[]), Err(EventError::Malformed));
+        assert_eq!(
+            Event::unpack(&[EVENT_VERSION, 255]),
+            Err(EventError::Malformed)
+        );
+        assert_eq!(Event::from_log_message("Access list changed"), None);
+        assert_eq!(
+            Event::from_log_message(&format!("{}not base64!", EVENT_LOG_PREFIX)),
+            Some(Err(EventError::InvalidEncoding))
+        );
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let program_id = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let added = Event::KeyAdded {
+            access_list: Pubkey::new_unique(),
+            entry: AccessEntry::new(Pubkey::new_unique()),
+        };
+        let removed = Event::KeyRemoved {
+            access_list: Pubkey::new_unique(),
+            pubkey: Pubkey::new_unique(),
+        };
+        let forged = Event::AuthorityChanged { authority: other };
+        let logs = vec![
+            format!("Program {} invoke [1]", program_id),
+            format!("Program log: {}", added.to_log_message()),
+            format!(
+                "Program {} consumed 2000 of 200000 compute units",
+                program_id
+            ),
+            format!("Program {} success", program_id),
+            format!("Program {} invoke [1]", other),
+            format!("Program log: {}", forged.to_log_message()),
+            format!("Program {} invoke [2]", program_id),
+            format!("Program log: {}", removed.to_log_message()),
+            format!("Program {} success", program_id),
+            format!("Program log: {}", forged.to_log_message()),
+            format!("Program {} success", other),
+        ];
+        assert_eq!(parse_events(&program_id, &logs), Ok(vec![added, removed]));
+        assert_eq!(
+            parse_events(&other, &logs),
+            Ok(vec![forged.clone(), forged])
+        );
+
+        let broken = vec![
+            format!("Program {} invoke [1]", program_id),
+            format!("Program log: {}AAAA", EVENT_LOG_PREFIX),
+        ];
+        assert_eq!(
+            parse_events(&program_id, &broken),
+            Err(EventError::UnsupportedVersion(0))
+        );
+    }
+}
@@ -1,104 +1,694 @@
 //! Instruction types
+use crate::error::SolanaPamError;
+use crate::state::{AccessEntry, GroupMember, ProposalAction, Role};
 use borsh::maybestd::collections::HashMap;
 use borsh::{BorshDeserialize, BorshSerialize};
-use core::mem::transmute;
-use solana_program::{entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
-use std::{convert::TryInto, ops::Deref};
+use solana_program::{
+    bpf_loader_upgradeable, entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey,
+};
+use std::convert::TryInto;
 
-use std::str::FromStr;
+/// Seed prefix for the program-derived address holding a user's access list
+pub const ACCESS_LIST_SEED: &[u8] = b"access_list";
 
-pub type UserAccessList = Vec<Pubkey>;
-#[derive(BorshDeserialize, BorshSerialize)]
+/// Seed prefix for the program-derived address of a host account
+pub const HOST_SEED: &[u8] = b"host";
+
+/// Seed prefix for the program-derived address recording who claimed a username
+pub const USERNAME_SEED: &[u8] = b"username";
+
+/// Seed prefix for the program-derived address of a unix account's policy
+pub const ACCOUNT_POLICY_SEED: &[u8] = b"account_policy";
+
+/// Seed prefix for the program-derived address of an access list's multisig
+pub const MULTISIG_SEED: &[u8] = b"multisig";
+
+/// Seed prefix for the program-derived address of a multisig's proposals
+pub const PROPOSAL_SEED: &[u8] = b"proposal";
+
+/// Seed prefix for the program-derived address of an access list's delegate
+pub const DELEGATE_SEED: &[u8] = b"delegate";
+
+/// Seed prefix for the program-derived address of a key's revocation
+pub const REVOCATION_SEED: &[u8] = b"revoked";
+
+/// Seed prefix for the program-derived address of a group
+pub const GROUP_SEED: &[u8] = b"group";
+
+/// Seed prefix for the program-derived address of a host's login log
+pub const LOGIN_LOG_SEED: &[u8] = b"login_log";
+
+/// Find the address of `user`'s access list and its bump seed
+pub fn find_access_list_address(program_id: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ACCESS_LIST_SEED, user.as_ref()], program_id)
+}
+
+/// Find the address of the account registered for host key `host`
+pub fn find_host_address(program_id: &Pubkey, host: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[HOST_SEED, host.as_ref()], program_id)
+}
+
+/// Find the address of the login log kept for host key `host`
+pub fn find_login_log_address(program_id: &Pubkey, host: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[LOGIN_LOG_SEED, host.as_ref()], program_id)
+}
+
+/// Find the address of the account recording who claimed `username`
+pub fn find_username_address(program_id: &Pubkey, username: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[USERNAME_SEED, username.as_bytes()], program_id)
+}
+
+/// Find the address of the policy for unix account `account`
+pub fn find_account_policy_address(program_id: &Pubkey, account: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ACCOUNT_POLICY_SEED, account.as_bytes()], program_id)
+}
+
+/// Find the address of the multisig governing the list at `access_list`
+pub fn find_multisig_address(program_id: &Pubkey, access_list: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MULTISIG_SEED, access_list.as_ref()], program_id)
+}
+
+/// Find the address of proposal number `index` of `multisig`
+pub fn find_proposal_address(program_id: &Pubkey, multisig: &Pubkey, index: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[PROPOSAL_SEED, multisig.as_ref(), &index.to_le_bytes()],
+        program_id,
+    )
+}
+
+/// Find the address recording `delegate`'s rights over the list at `access_list`
+pub fn find_delegate_address(
+    program_id: &Pubkey,
+    access_list: &Pubkey,
+    delegate: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[DELEGATE_SEED, access_list.as_ref(), delegate.as_ref()],
+        program_id,
+    )
+}
+
+/// Find the address that records `pubkey` as revoked, once it is
+pub fn find_revocation_address(program_id: &Pubkey, pubkey: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[REVOCATION_SEED, pubkey.as_ref()], program_id)
+}
+
+/// Find the address of the group called `name`
+pub fn find_group_address(program_id: &Pubkey, name: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[GROUP_SEED, name.as_bytes()], program_id)
+}
+
+/// Find the upgradeable loader's data account for `program_id`, which names
+/// the program's upgrade authority
+pub fn find_loader_data_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id())
+}
+
+/// Read the upgrade authority from the data of a loader data account, `None`
+/// if the data is not a loader data account or the program is immutable
+pub fn read_upgrade_authority(data: &[u8]) -> Option<Pubkey> {
+    // Bincode encoded: a four byte `ProgramData` tag of 3, the slot of the
+    // last deploy in eight bytes, then the authority as an `Option<Pubkey>`
+    if data.get(0..4)? != 3u32.to_le_bytes() || *data.get(12)? != 1 {
+        return None;
+    }
+    Some(Pubkey::new_from_array(data.get(13..45)?.try_into().ok()?))
+}
+
+/// Program state
+///
+/// The map comes first so that state accounts written before the authority
+/// existed still decode, as uninitialized and with their map intact.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
 pub struct ProgramData {
+    /// Where lists created before lists moved to program-derived addresses
+    /// live, by owner. The program no longer adds to it.
     pub user_access_map: HashMap<[u8; 32], [u8; 32]>,
+    pub is_initialized: bool,
+    /// Administers the program, set by `Init` and changed with `SetAuthority`
+    pub authority: Pubkey,
 }
 
 impl ProgramData {
     pub fn new() -> Self {
-        ProgramData {
-            user_access_map: HashMap::new(),
-        }
+        ProgramData::default()
     }
     pub fn update(&mut self, user: &Pubkey, new_access_list_account: &Pubkey) -> ProgramResult {
         self.user_access_map
             .insert(user.to_bytes(), new_access_list_account.to_bytes());
         Ok(())
     }
+    /// Forget `user`'s list if it is the one recorded at `access_list_account`,
+    /// returning whether it was
+    pub fn remove(&mut self, user: &Pubkey, access_list_account: &Pubkey) -> bool {
+        if self.user_access_map.get(&user.to_bytes()) == Some(&access_list_account.to_bytes()) {
+            self.user_access_map.remove(&user.to_bytes());
+            return true;
+        }
+        false
+    }
 }
 
-/// Instructions supported by the token program.
-#[repr(C)]
-#[derive(Clone, Debug, PartialEq)]
+/// Version byte leading every instruction's data
+///
+/// Bumped whenever the encoding of an existing instruction changes, so an old
+/// client is rejected instead of having its bytes misread.
+pub const INSTRUCTION_VERSION: u8 = 6;
+
+/// Instructions supported by the program
+///
+/// Encoded as `INSTRUCTION_VERSION` followed by the Borsh encoding of the
+/// variant: a one byte tag in declaration order, then its fields.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
 pub enum ProgInstruction {
-    Init,
-    /// UpdateAccessList updates the access list for the caller
+    /// Init marks the program state initialized with the signer as its
+    /// authority. The state account must be owned by the program, rent exempt
+    /// and not yet initialized. Only the program's upgrade authority may
+    /// initialize it, so nobody else can get in first and take the program,
+    /// or lists created before the authority existed, over.
     ///
     /// Accounts expected
     /// program_account (W) - program state account
-    /// new_access_list - an account with the caller's updated access list
+    /// authority (S) - the new authority, the program's upgrade authority
+    /// loader_data - the address from `find_loader_data_address`
+    Init,
+    /// InitAccessList creates the caller's access list at its program-derived
+    /// address. The program state is only checked to be initialized.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S, W) - the list owner, pays for the new account
+    /// access_list (W) - the address from `find_access_list_address`
+    /// system_program - the system program
     InitAccessList,
-    // TODO: bulk operations
-    AddPKToAccessListAccount(Pubkey),
+    /// AddPKToAccessListAccount grants the entry's key access, or with the
+    /// entry's `deny` set refuses it, replacing the key's entry that allows or
+    /// denies as the new one does. A key can have one of each, so a list can
+    /// grant it access and refuse it on some hosts. Expired entries are
+    /// dropped from the list as it is rewritten. A delegate needs
+    /// `DELEGATE_ADD` for an allow entry the key does not have yet,
+    /// `DELEGATE_REMOVE` for such a deny entry,
+    /// `DELEGATE_MANAGE_EXPIRY` to change only the time bounds of one it has,
+    /// and both `DELEGATE_ADD` and `DELEGATE_REMOVE` to change anything else
+    /// about it. It also needs `DELEGATE_MANAGE_ROLES` to give the key a role
+    /// other than `Role::Login` or to change its role, and every right to
+    /// make it or stop it being a `Role::Admin`.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S) - the list owner, one of its delegates, or a key with an
+    ///   admin entry on the list
+    /// access_list (W) - the owner's access list
+    /// delegate_account - the address from `find_delegate_address`, only
+    ///   read when the signer is a delegate
+    /// revocation_account - the signer's address from
+    ///   `find_revocation_address`
+    AddPKToAccessListAccount(AccessEntry),
+    /// RemovePKToAccessListAccount removes the key's allow and deny entries.
+    /// A delegate needs `DELEGATE_REMOVE`, and also `DELEGATE_ADD` when the
+    /// key has a deny entry, since removing it can let the key back in.
+    ///
+    /// Accounts expected are the same as for `AddPKToAccessListAccount`
     RemovePKToAccessListAccount(Pubkey),
+    /// MigrateAccessList rewrites a list stored in the headerless format in the
+    /// current layout, with the signer as its owner. Lists already in the
+    /// current layout are left alone.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S) - the list owner
+    /// access_list (W) - the owner's program-derived list, or the list
+    ///   recorded for them in the program state
+    MigrateAccessList,
+    /// SetAuthority hands administration of the program to a new authority
+    ///
+    /// Accounts expected
+    /// program_account (W) - program state account
+    /// authority (S) - the current authority
+    SetAuthority(Pubkey),
+    /// RegisterHost creates the account for a host the module runs on, so
+    /// entries can be scoped to it by address or by one of its tags
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// authority (S, W) - the program authority, pays for the new account
+    /// host (S) - the host key, proving the host holds it
+    /// host_account (W) - the address from `find_host_address`
+    /// system_program - the system program
+    RegisterHost { label: String, tags: Vec<String> },
+    /// ClaimUsername records the signer as the owner of a POSIX username, so
+    /// the module can map the name to their key. Each name is claimed once.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S, W) - the new owner, pays for the new account
+    /// username_account (W) - the address from `find_username_address`
+    /// system_program - the system program
+    ClaimUsername(String),
+    /// ReleaseUsername closes a username's account so it can be claimed again,
+    /// returning its lamports to the owner
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S, W) - the owner
+    /// username_account (W) - the address from `find_username_address`
+    ReleaseUsername(String),
+    /// InitAccountPolicy creates the policy listing which keys may log in as
+    /// a unix account, usually one shared by several people
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// authority (S, W) - the program authority, pays for the new account
+    /// policy_account (W) - the address from `find_account_policy_address`
+    /// system_program - the system program
+    InitAccountPolicy { account: String, admin: Pubkey },
+    /// AddToAccountPolicy lets the entry's key log in as the account, or with
+    /// the entry's `deny` set refuses it, replacing the key's entry that
+    /// allows or denies as the new one does. Expired entries are dropped from
+    /// the policy as it is rewritten.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// admin (S) - the policy's admin
+    /// policy_account (W) - the account's policy
+    /// revocation_account - the admin's address from `find_revocation_address`
+    AddToAccountPolicy { account: String, entry: AccessEntry },
+    /// Accounts expected are the same as for `AddToAccountPolicy`
+    RemoveFromAccountPolicy { account: String, pubkey: Pubkey },
+    /// CreateMultisig hands the caller's access list to a multisig, after
+    /// which it only changes through proposals approved by `threshold` of
+    /// `admins`
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S, W) - the list owner, pays for the new account
+    /// access_list (W) - the owner's access list
+    /// multisig (W) - the address from `find_multisig_address`
+    /// system_program - the system program
+    /// revocation_account - the owner's address from `find_revocation_address`
+    CreateMultisig { admins: Vec<Pubkey>, threshold: u8 },
+    /// Propose makes the next proposal of a multisig, approved by its proposer
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// proposer (S, W) - a multisig admin, pays for the new account
+    /// multisig (W) - the multisig
+    /// proposal (W) - the address from `find_proposal_address` for the
+    ///   multisig's `proposal_count`
+    /// system_program - the system program
+    /// revocation_account - the proposer's address from `find_revocation_address`
+    Propose(ProposalAction),
+    /// ApproveProposal adds the signer's approval to a proposal
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// admin (S) - a multisig admin
+    /// multisig - the multisig
+    /// proposal (W) - the proposal
+    /// revocation_account - the admin's address from `find_revocation_address`
+    ApproveProposal,
+    /// ExecuteProposal applies a proposal with enough approvals. Approvals
+    /// from admins revoked since they gave them do not count.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// admin (S) - a multisig admin
+    /// multisig (W) - the multisig
+    /// proposal (W) - the proposal
+    /// access_list (W) - the list the multisig governs
+    /// revocation_accounts - the address from `find_revocation_address` of
+    ///   each of the multisig's admins, in the multisig's order
+    ExecuteProposal,
+    /// SetDelegate lets `delegate` change the caller's access list within
+    /// `rights`, a set of `DELEGATE_*` bits, replacing any rights it had
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S, W) - the list owner, pays for the new account
+    /// access_list - the owner's access list
+    /// delegate_account (W) - the address from `find_delegate_address`
+    /// system_program - the system program
+    /// revocation_account - the owner's address from `find_revocation_address`
+    SetDelegate { delegate: Pubkey, rights: u8 },
+    /// RevokeDelegate closes a delegate's account, returning its lamports to
+    /// the owner
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// user (S, W) - the list owner
+    /// access_list - the owner's access list
+    /// delegate_account (W) - the address from `find_delegate_address`
+    RevokeDelegate(Pubkey),
+    /// AddManyToAccessList grants each key access without time bounds. Repeated
+    /// keys are added once and keys already listed keep their entries. At most
+    /// `MAX_BULK_KEYS` keys, and a delegate needs `DELEGATE_ADD`.
+    ///
+    /// Accounts expected are the same as for `AddPKToAccessListAccount`
+    AddManyToAccessList(Vec<Pubkey>),
+    /// RemoveManyFromAccessList removes each key that is listed. At most
+    /// `MAX_BULK_KEYS` keys, and a delegate needs `DELEGATE_REMOVE`, and also
+    /// `DELEGATE_ADD` when any of the keys has a deny entry.
+    ///
+    /// Accounts expected are the same as for `AddPKToAccessListAccount`
+    RemoveManyFromAccessList(Vec<Pubkey>),
+    /// ReplaceAccessList makes the list hold exactly the given keys. Keys
+    /// already listed keep their entries and the others get none. At most
+    /// `MAX_BULK_KEYS` keys, and a delegate needs both `DELEGATE_ADD` and
+    /// `DELEGATE_REMOVE`.
+    ///
+    /// Accounts expected are the same as for `AddPKToAccessListAccount`
+    ReplaceAccessList(Vec<Pubkey>),
+    /// CloseAccessList deletes the caller's access list, sending its lamports
+    /// to `recipient`, and drops it from the program state's map if the map
+    /// still records it. The data is zeroed and marked closed, so the account
+    /// is never read as a list again. Lists governed by a multisig cannot be
+    /// closed, and delegate accounts outlive the list, so revoke them first.
+    ///
+    /// Accounts expected
+    /// program_account - program state account, writable when its map
+    ///   records the list
+    /// user (S) - the list owner
+    /// access_list (W) - the owner's access list
+    /// recipient (W) - receives the list's lamports
+    CloseAccessList,
+    /// RevokeKey locks a key out of every host, whatever its grants say, and
+    /// stops it changing lists, as their owner, an admin, a delegate or a
+    /// multisig admin, account policies and groups.
+    /// Either the program authority or the key itself signs, so the owner of a
+    /// stolen key can act at once. Revoking a revoked key does nothing.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// payer (S, W) - pays for the new account
+    /// revoker (S) - the program authority, or the key being revoked
+    /// revocation_account (W) - the address from `find_revocation_address`
+    /// system_program - the system program
+    RevokeKey(Pubkey),
+    /// SetEntryRole changes the role of a key already on the list, leaving
+    /// the rest of its entry as it is. A delegate needs `DELEGATE_MANAGE_ROLES`,
+    /// or every right to make the key or stop it being a `Role::Admin`.
+    ///
+    /// Accounts expected are the same as for `AddPKToAccessListAccount`
+    SetEntryRole { pubkey: Pubkey, role: Role },
+    /// CreateGroup creates a group with no members and the signer as its only
+    /// admin. Access entries of kind `EntryKind::Group` name the group by its
+    /// address.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// creator (S, W) - the first admin, pays for the new account
+    /// group_account (W) - the address from `find_group_address`
+    /// system_program - the system program
+    CreateGroup { name: String },
+    /// AddGroupMember adds a key or a nested group to a group. A group cannot
+    /// be added to itself; longer cycles are caught when groups are expanded.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// admin (S) - one of the group's admins
+    /// group_account (W) - the address from `find_group_address`
+    /// revocation_account - the admin's address from `find_revocation_address`
+    AddGroupMember { name: String, member: GroupMember },
+    /// Accounts expected are the same as for `AddGroupMember`
+    RemoveGroupMember { name: String, member: GroupMember },
+    /// SetGroupAdmins replaces the group's admins, of which there must be at
+    /// least one
+    ///
+    /// Accounts expected are the same as for `AddGroupMember`
+    SetGroupAdmins { name: String, admins: Vec<Pubkey> },
+    /// RecordLogin appends a login to the signing host's login log, creating
+    /// the log on the host's first login. The host must be registered. The
+    /// record is stamped with the cluster's time rather than the host's.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// host (S, W) - the host key, pays for the log when it is created
+    /// host_account - the address from `find_host_address`
+    /// login_log (W) - the address from `find_login_log_address`
+    /// system_program - the system program
+    RecordLogin {
+        user: Pubkey,
+        service: String,
+        remote_host_hash: [u8; 32],
+    },
+    /// RecordSession attests that a session `user` opened on the signing host
+    /// through `service` started, or with `opened_at` set, ended. Nothing is
+    /// stored: the attestation is the transaction and the event it logs, with
+    /// the end stamped by the cluster's time. The host must be registered.
+    ///
+    /// Accounts expected
+    /// program_account - program state account
+    /// host (S) - the host key
+    /// host_account - the address from `find_host_address`
+    RecordSession {
+        user: Pubkey,
+        service: String,
+        opened_at: Option<i64>,
+    },
 }
 
 impl ProgInstruction {
+    /// Decode instruction data, rejecting short, overlong or unknown input
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        match input[0] {
-            0 => Ok(ProgInstruction::Init),
-            1 => Ok(ProgInstruction::InitAccessList),
-            2 => {
-                let pk = &input[1..33];
-                Ok(ProgInstruction::AddPKToAccessListAccount(
-                    Pubkey::new_from_array(pk.try_into().unwrap()),
-                ))
-            }
-            3 => {
-                let pk = &input[1..33];
-                Ok(ProgInstruction::RemovePKToAccessListAccount(
-                    Pubkey::new_from_array(pk.try_into().unwrap()),
-                ))
-            }
-            _ => Err(ProgramError::Custom(11)),
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        if version != INSTRUCTION_VERSION {
+            return Err(SolanaPamError::UnsupportedInstructionVersion.into());
         }
+        // try_from_slice fails unless the whole input is consumed
+        Self::try_from_slice(rest).map_err(|_| ProgramError::InvalidInstructionData)
     }
-}
 
-pub fn pack_user_access_list(input: UserAccessList) -> Vec<u8> {
-    let keys: Vec<[u8; 32]> = input.iter().map(|pk| pk.to_bytes()).collect();
-    unsafe {
-        let ret: Vec<u8> = transmute(keys);
-        ret
+    pub fn pack(&self) -> Vec<u8> {
+        let mut data = vec![INSTRUCTION_VERSION];
+        // Writing into a Vec cannot fail
+        self.serialize(&mut data).unwrap();
+        data
     }
 }
 
-pub fn unpack_user_access_list(input: &mut [u8]) -> Result<UserAccessList, ProgramError> {
-    if input.len() % 32 != 0 {
-        Err(ProgramError::Custom(12))
-    } else {
-        // TODO: unwrap for all
-        let keys: Vec<Pubkey> = input
-            .chunks(32)
-            .into_iter()
-            .map(|k| Pubkey::new_from_array(k.try_into().unwrap()))
-            .collect();
-        Ok(keys)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{EntryKind, HostScope};
+
+    fn all_instructions() -> Vec<ProgInstruction> {
+        vec![
+            ProgInstruction::Init,
+            ProgInstruction::InitAccessList,
+            ProgInstruction::AddPKToAccessListAccount(AccessEntry::new(Pubkey::new_unique())),
+            ProgInstruction::AddPKToAccessListAccount(AccessEntry {
+                pubkey: Pubkey::new_unique(),
+                not_before: Some(1_600_000_000),
+                expires_at: Some(1_700_000_000),
+                hosts: HostScope::Hosts(vec![Pubkey::new_unique()]),
+                deny: false,
+                role: Role::Sudo,
+                kind: EntryKind::Key,
+            }),
+            ProgInstruction::AddPKToAccessListAccount(AccessEntry {
+                hosts: HostScope::Service("sshd".to_string()),
+                deny: true,
+                ..AccessEntry::new(Pubkey::new_unique())
+            }),
+            ProgInstruction::RemovePKToAccessListAccount(Pubkey::new_unique()),
+            ProgInstruction::MigrateAccessList,
+            ProgInstruction::SetAuthority(Pubkey::new_unique()),
+            ProgInstruction::RegisterHost {
+                label: "web-1".to_string(),
+                tags: vec!["prod".to_string()],
+            },
+            ProgInstruction::ClaimUsername("alice".to_string()),
+            ProgInstruction::ReleaseUsername("alice".to_string()),
+            ProgInstruction::InitAccountPolicy {
+                account: "deploy".to_string(),
+                admin: Pubkey::new_unique(),
+            },
+            ProgInstruction::AddToAccountPolicy {
+                account: "deploy".to_string(),
+                entry: AccessEntry::new(Pubkey::new_unique()),
+            },
+            ProgInstruction::RemoveFromAccountPolicy {
+                account: "deploy".to_string(),
+                pubkey: Pubkey::new_unique(),
+            },
+            ProgInstruction::CreateMultisig {
+                admins: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                threshold: 2,
+            },
+            ProgInstruction::Propose(ProposalAction::AddEntry(AccessEntry::new(
+                Pubkey::new_unique(),
+            ))),
+            ProgInstruction::Propose(ProposalAction::RemoveEntry(Pubkey::new_unique())),
+            ProgInstruction::Propose(ProposalAction::SetMultisig {
+                admins: vec![Pubkey::new_unique()],
+                threshold: 1,
+            }),
+            ProgInstruction::ApproveProposal,
+            ProgInstruction::ExecuteProposal,
+            ProgInstruction::SetDelegate {
+                delegate: Pubkey::new_unique(),
+                rights: 3,
+            },
+            ProgInstruction::RevokeDelegate(Pubkey::new_unique()),
+            ProgInstruction::AddManyToAccessList(vec![Pubkey::new_unique(); 2]),
+            ProgInstruction::RemoveManyFromAccessList(vec![Pubkey::new_unique()]),
+            ProgInstruction::ReplaceAccessList(vec![]),
+            ProgInstruction::CloseAccessList,
+            ProgInstruction::RevokeKey(Pubkey::new_unique()),
+            ProgInstruction::SetEntryRole {
+                pubkey: Pubkey::new_unique(),
+                role: Role::Sudo,
+            },
+            ProgInstruction::CreateGroup {
+                name: "ops".to_string(),
+            },
+            ProgInstruction::AddGroupMember {
+                name: "ops".to_string(),
+                member: GroupMember::Group(Pubkey::new_unique()),
+            },
+            ProgInstruction::RemoveGroupMember {
+                name: "ops".to_string(),
+                member: GroupMember::Key(Pubkey::new_unique()),
+            },
+            ProgInstruction::SetGroupAdmins {
+                name: "ops".to_string(),
+                admins: vec![Pubkey::new_unique()],
+            },
+            ProgInstruction::RecordLogin {
+                user: Pubkey::new_unique(),
+                service: "sshd".to_string(),
+                remote_host_hash: [9; 32],
+            },
+            ProgInstruction::RecordSession {
+                user: Pubkey::new_unique(),
+                service: "sshd".to_string(),
+                opened_at: Some(1_000),
+            },
+        ]
     }
-}
 
-pub fn user_access_list_add_pk(mut access_list: UserAccessList, add: Pubkey) -> ProgramResult {
-    if access_list.iter().find(|pk| pk.deref().eq(&add)) == None {
-        access_list.push(add);
+    #[test]
+    fn test_instruction_round_trip() {
+        for instruction in all_instructions() {
+            let packed = instruction.pack();
+            assert_eq!(ProgInstruction::unpack(&packed), Ok(instruction));
+        }
     }
-    Ok(())
-}
 
-pub fn user_access_list_remove_pk(
-    mut access_list: UserAccessList,
-    remove: Pubkey,
-) -> ProgramResult {
-    if let Some(idx) = access_list.iter().position(|&r| r.eq(&remove)) {
-        access_list.remove(idx);
+    #[test]
+    fn test_instruction_encoding() {
+        // pam-client's instrData produces these bytes
+        let pk = Pubkey::new_from_array([7; 32]);
+        let v = INSTRUCTION_VERSION;
+        assert_eq!(ProgInstruction::Init.pack(), vec![v, 0]);
+        assert_eq!(ProgInstruction::InitAccessList.pack(), vec![v, 1]);
+        let mut expected = vec![v, 3];
+        expected.extend_from_slice(&[7; 32]);
+        assert_eq!(
+            ProgInstruction::RemovePKToAccessListAccount(pk).pack(),
+            expected
+        );
+        // Then the entry: both time bounds unset, any host, not a deny, login
+        // role, for a key
+        expected[1] = 2;
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            ProgInstruction::AddPKToAccessListAccount(AccessEntry::new(pk)).pack(),
+            expected
+        );
+        // Each time bound is a 1 byte tag, then the timestamp as a little endian i64.
+        // A tag scope is variant 2, then the tag as a u32 length and utf-8 bytes.
+        // The deny flag, then the role and kind in declaration order, are the
+        // last bytes.
+        expected.truncate(34);
+        expected.extend_from_slice(&[0, 1, 0x10, 0x27, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[2, 4, 0, 0, 0, b'p', b'r', b'o', b'd', 1, 2, 1]);
+        let entry = AccessEntry {
+            expires_at: Some(10_000),
+            hosts: HostScope::Tag("prod".to_string()),
+            deny: true,
+            role: Role::Admin,
+            ..AccessEntry::group(pk)
+        };
+        assert_eq!(
+            ProgInstruction::AddPKToAccessListAccount(entry).pack(),
+            expected
+        );
+        let mut expected = vec![v, 23];
+        expected.extend_from_slice(&[7; 32]);
+        expected.push(1);
+        assert_eq!(
+            ProgInstruction::SetEntryRole {
+                pubkey: pk,
+                role: Role::Sudo
+            }
+            .pack(),
+            expected
+        );
+        // The group name as a u32 length and utf-8 bytes, then the member
+        let mut expected = vec![v, 25, 3, 0, 0, 0, b'o', b'p', b's', 1];
+        expected.extend_from_slice(&[7; 32]);
+        assert_eq!(
+            ProgInstruction::AddGroupMember {
+                name: "ops".to_string(),
+                member: GroupMember::Group(pk)
+            }
+            .pack(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_unpack_rejects_malformed_input() {
+        assert_eq!(
+            ProgInstruction::unpack(&[]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            ProgInstruction::unpack(&[INSTRUCTION_VERSION]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            ProgInstruction::unpack(&[INSTRUCTION_VERSION, 0xff]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(
+            ProgInstruction::unpack(&[0, 0]),
+            Err(SolanaPamError::UnsupportedInstructionVersion.into())
+        );
+        for instruction in all_instructions() {
+            let packed = instruction.pack();
+            let mut long = packed.clone();
+            long.push(0);
+            assert_eq!(
+                ProgInstruction::unpack(&long),
+                Err(ProgramError::InvalidInstructionData)
+            );
+            for len in 0..packed.len() {
+                assert!(ProgInstruction::unpack(&packed[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_upgrade_authority() {
+        let authority = Pubkey::new_unique();
+        let mut data = vec![3, 0, 0, 0];
+        data.extend_from_slice(&42u64.to_le_bytes());
+        data.push(1);
+        data.extend_from_slice(authority.as_ref());
+        // The program's code follows the header
+        data.extend_from_slice(&[0xff; 16]);
+        assert_eq!(read_upgrade_authority(&data), Some(authority));
+
+        let mut immutable = data.clone();
+        immutable[12] = 0;
+        assert_eq!(read_upgrade_authority(&immutable), None);
+        let mut program = data.clone();
+        program[0] = 2;
+        assert_eq!(read_upgrade_authority(&program), None);
+        assert_eq!(read_upgrade_authority(&data[..40]), None);
     }
-    Ok(())
 }
@@ -1 +1,5 @@
-pub mod instructions;
\ No newline at end of file
+pub mod client;
+pub mod error;
+pub mod events;
+pub mod instructions;
+pub mod state;
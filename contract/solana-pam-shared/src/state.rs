@@ -0,0 +1,2630 @@
+//! Account layouts
+use crate::error::SolanaPamError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+
+/// Most host accounts an entry can name
+pub const MAX_SCOPE_HOSTS: usize = 4;
+/// Longest host label or tag, in bytes
+pub const MAX_HOST_NAME_LEN: usize = 32;
+/// Most tags a host can carry
+pub const MAX_HOST_TAGS: usize = 8;
+/// Longest PAM service name an entry can be scoped to, in bytes
+pub const MAX_SERVICE_NAME_LEN: usize = 32;
+
+/// The machines, or the PAM service, an access entry applies to
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub enum HostScope {
+    /// Every host the module is installed on
+    Any,
+    /// Only these host accounts
+    Hosts(Vec<Pubkey>),
+    /// Hosts whose account carries this tag
+    Tag(String),
+    /// Logins through this PAM service, such as `sshd`, on any host
+    Service(String),
+}
+
+impl HostScope {
+    fn validate(&self) -> ProgramResult {
+        let valid = match self {
+            HostScope::Any => true,
+            HostScope::Hosts(hosts) => !hosts.is_empty() && hosts.len() <= MAX_SCOPE_HOSTS,
+            HostScope::Tag(tag) => is_valid_host_name(tag),
+            HostScope::Service(service) => {
+                !service.is_empty() && service.len() <= MAX_SERVICE_NAME_LEN
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(SolanaPamError::InvalidHostScope.into())
+        }
+    }
+
+    /// Whether the scope covers `login`
+    pub fn covers(&self, login: &Login) -> bool {
+        match self {
+            HostScope::Any => true,
+            HostScope::Hosts(hosts) => login.host.map_or(false, |host| hosts.contains(&host)),
+            HostScope::Tag(tag) => login.host_tags.contains(tag),
+            HostScope::Service(service) => login.service == Some(service.as_str()),
+        }
+    }
+}
+
+fn is_valid_host_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_HOST_NAME_LEN
+}
+
+/// What a granted key may do, each role including the ones before it
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Log in, but not through the `sudo` service
+    Login,
+    /// Log in and use `sudo`
+    Sudo,
+    /// Also change the list it is on, as a delegate with every right would
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Login => "login",
+            Role::Sudo => "sudo",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// Who an access entry is for
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub enum EntryKind {
+    /// The key in the entry
+    Key,
+    /// Every key in the group account in the entry, or in the groups nested
+    /// in it
+    Group,
+    /// Every key holding at least `min_amount` of the SPL token mint in the
+    /// entry, counted across all its token accounts for the mint
+    Token { min_amount: u64 },
+}
+
+/// A key or group allowed to log in as the list's owner, possibly only for a
+/// while and only on some hosts, or with `deny` set, kept out
+///
+/// Times are unix timestamps in seconds, as in the Clock sysvar.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct AccessEntry {
+    /// The key, or the address of the group account or token mint, the entry
+    /// is for
+    pub pubkey: Pubkey,
+    /// The entry grants nothing before this time
+    pub not_before: Option<i64>,
+    /// The entry grants nothing from this time on
+    pub expires_at: Option<i64>,
+    pub hosts: HostScope,
+    /// Refuse the key instead of granting it access, over any grant from
+    /// another list
+    pub deny: bool,
+    /// What the key may do once logged in; ignored on deny entries
+    pub role: Role,
+    pub kind: EntryKind,
+}
+
+/// Entry layout of version 2 access lists, before host scopes
+#[derive(BorshDeserialize)]
+struct AccessEntryV2 {
+    pubkey: Pubkey,
+    not_before: Option<i64>,
+    expires_at: Option<i64>,
+}
+
+impl From<AccessEntryV2> for AccessEntry {
+    fn from(entry: AccessEntryV2) -> Self {
+        AccessEntry {
+            not_before: entry.not_before,
+            expires_at: entry.expires_at,
+            ..AccessEntry::new(entry.pubkey)
+        }
+    }
+}
+
+/// Entry layout of version 3 and 4 access lists, before deny entries
+#[derive(BorshDeserialize)]
+struct AccessEntryV3 {
+    pubkey: Pubkey,
+    not_before: Option<i64>,
+    expires_at: Option<i64>,
+    hosts: HostScope,
+}
+
+impl From<AccessEntryV3> for AccessEntry {
+    fn from(entry: AccessEntryV3) -> Self {
+        AccessEntry {
+            not_before: entry.not_before,
+            expires_at: entry.expires_at,
+            hosts: entry.hosts,
+            ..AccessEntry::new(entry.pubkey)
+        }
+    }
+}
+
+/// Entry layout of version 5 access lists, before roles
+#[derive(BorshDeserialize)]
+struct AccessEntryV4 {
+    pubkey: Pubkey,
+    not_before: Option<i64>,
+    expires_at: Option<i64>,
+    hosts: HostScope,
+    deny: bool,
+}
+
+impl From<AccessEntryV4> for AccessEntry {
+    fn from(entry: AccessEntryV4) -> Self {
+        AccessEntry {
+            not_before: entry.not_before,
+            expires_at: entry.expires_at,
+            hosts: entry.hosts,
+            deny: entry.deny,
+            ..AccessEntry::new(entry.pubkey)
+        }
+    }
+}
+
+/// Entry layout of version 6 access lists, before group entries
+#[derive(BorshDeserialize)]
+struct AccessEntryV5 {
+    pubkey: Pubkey,
+    not_before: Option<i64>,
+    expires_at: Option<i64>,
+    hosts: HostScope,
+    deny: bool,
+    role: Role,
+}
+
+impl From<AccessEntryV5> for AccessEntry {
+    fn from(entry: AccessEntryV5) -> Self {
+        AccessEntry {
+            not_before: entry.not_before,
+            expires_at: entry.expires_at,
+            hosts: entry.hosts,
+            deny: entry.deny,
+            role: entry.role,
+            ..AccessEntry::new(entry.pubkey)
+        }
+    }
+}
+
+impl From<Pubkey> for AccessEntry {
+    fn from(pubkey: Pubkey) -> Self {
+        AccessEntry::new(pubkey)
+    }
+}
+
+impl AccessEntry {
+    /// An entry granting logins on every host with no time bounds
+    pub fn new(pubkey: Pubkey) -> Self {
+        AccessEntry {
+            pubkey,
+            not_before: None,
+            expires_at: None,
+            hosts: HostScope::Any,
+            deny: false,
+            role: Role::Login,
+            kind: EntryKind::Key,
+        }
+    }
+
+    /// An entry granting logins to the members of `group` on every host with
+    /// no time bounds
+    pub fn group(group: Pubkey) -> Self {
+        AccessEntry {
+            kind: EntryKind::Group,
+            ..AccessEntry::new(group)
+        }
+    }
+
+    /// An entry granting logins to holders of at least `min_amount` of
+    /// `mint` on every host with no time bounds
+    pub fn token(mint: Pubkey, min_amount: u64) -> Self {
+        AccessEntry {
+            kind: EntryKind::Token { min_amount },
+            ..AccessEntry::new(mint)
+        }
+    }
+
+    /// Reject windows that can never be open, oversized host scopes and
+    /// token entries every key satisfies
+    pub fn validate(&self) -> ProgramResult {
+        match (self.not_before, self.expires_at) {
+            (Some(not_before), Some(expires_at)) if not_before >= expires_at => {
+                return Err(SolanaPamError::InvalidGrantWindow.into())
+            }
+            _ => {}
+        }
+        if self.kind == (EntryKind::Token { min_amount: 0 }) {
+            return Err(SolanaPamError::InvalidTokenGate.into());
+        }
+        self.hosts.validate()
+    }
+
+    /// Whether the entry has not started granting access at `now`
+    pub fn is_pending(&self, now: i64) -> bool {
+        self.not_before.map_or(false, |not_before| now < not_before)
+    }
+
+    /// Whether the entry has stopped granting access at `now`
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| now >= expires_at)
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        !self.is_pending(now) && !self.is_expired(now)
+    }
+
+    /// Whether the entry is for `login`'s key, itself, through a group or
+    /// through the tokens it holds
+    pub fn is_for(&self, login: &Login) -> bool {
+        match self.kind {
+            EntryKind::Key => self.pubkey == login.pubkey,
+            EntryKind::Group => login.groups.contains(&self.pubkey),
+            EntryKind::Token { min_amount } => login
+                .tokens
+                .iter()
+                .any(|(mint, amount)| *mint == self.pubkey && *amount >= min_amount),
+        }
+    }
+
+    /// Whether `other` differs from this entry in no more than its time
+    /// bounds and role
+    pub fn same_grant(&self, other: &AccessEntry) -> bool {
+        self.pubkey == other.pubkey
+            && self.hosts == other.hosts
+            && self.deny == other.deny
+            && self.kind == other.kind
+    }
+
+    /// Whether the entry is for `login`'s key and covers it: active at
+    /// `login.now`, give or take `login.skew` seconds, and in scope
+    pub fn applies_to(&self, login: &Login) -> bool {
+        self.is_for(login)
+            && !self.is_pending(login.now.saturating_add(login.skew))
+            && !self.is_expired(login.now.saturating_sub(login.skew))
+            && self.hosts.covers(login)
+    }
+}
+
+pub type UserAccessList = Vec<AccessEntry>;
+
+/// A login attempt that entries are checked against
+#[derive(Clone, Debug, Default)]
+pub struct Login<'a> {
+    /// The key that signed for the login
+    pub pubkey: Pubkey,
+    pub now: i64,
+    /// Leeway in seconds at both ends of a time-bounded entry
+    pub skew: i64,
+    /// The account of the host being logged in to, if it is registered
+    pub host: Option<Pubkey>,
+    /// The tags on that host account
+    pub host_tags: &'a [String],
+    /// The PAM service handling the login
+    pub service: Option<&'a str>,
+    /// The group accounts named by the entries that have the key as a member,
+    /// directly or through nested groups
+    pub groups: &'a [Pubkey],
+    /// How much of each mint named by a token entry the key holds
+    pub tokens: &'a [(Pubkey, u64)],
+}
+
+/// What the entries from every list consulted say about a login
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    /// No entry applies
+    Unlisted,
+    /// With the highest role among the allow entries that apply
+    Allow(Role),
+    Deny,
+}
+
+/// Combine the entries for a login gathered from any number of lists
+///
+/// A deny entry that applies wins over every allow, wherever either came from.
+pub fn decide<'a, I>(entries: I, login: &Login) -> Decision
+where
+    I: IntoIterator<Item = &'a AccessEntry>,
+{
+    let mut decision = Decision::Unlisted;
+    for entry in entries.into_iter().filter(|e| e.applies_to(login)) {
+        if entry.deny {
+            return Decision::Deny;
+        }
+        decision = match decision {
+            Decision::Allow(role) if role >= entry.role => decision,
+            _ => Decision::Allow(entry.role),
+        };
+    }
+    decision
+}
+
+/// First bytes of every access list account
+pub const ACCESS_LIST_DISCRIMINATOR: [u8; 8] = *b"pam_acl\0";
+/// Layout version written by this program
+///
+/// Version 1 entries were bare pubkeys, version 2 added time bounds, version 3
+/// host scopes, version 4 the governing multisig, version 5 deny entries,
+/// version 6 roles and version 7 group entries. Token entries were added to
+/// version 7 as a new entry kind, which leaves existing entries unchanged.
+pub const ACCESS_LIST_VERSION: u8 = 7;
+/// Bytes before the first entry: discriminator, version, owner, multisig and
+/// entry count
+pub const ACCESS_LIST_HEADER_SIZE: usize = 8 + 1 + 32 + 33 + 4;
+/// Largest encoding of an entry, with both time bounds set and the most hosts
+pub const ACCESS_ENTRY_MAX_SIZE: usize = 32 + 9 + 9 + 1 + 4 + MAX_SCOPE_HOSTS * 32 + 1 + 1 + 1 + 8;
+/// Size of an access list account, as allocated by `InitAccessList`
+pub const ACCESS_LIST_SIZE: usize = 1024;
+/// Number of entries that always fit in an `ACCESS_LIST_SIZE` account
+///
+/// Entries without time bounds or host lists are smaller, so more of those fit.
+pub const ACCESS_LIST_CAPACITY: usize =
+    (ACCESS_LIST_SIZE - ACCESS_LIST_HEADER_SIZE) / ACCESS_ENTRY_MAX_SIZE;
+/// Stamped over a closed access list, so that it reads as closed rather than
+/// as an empty list in the headerless format
+pub const CLOSED_ACCESS_LIST_DISCRIMINATOR: [u8; 8] = *b"pam_clsd";
+/// Most keys one bulk instruction carries
+///
+/// 24 keys are 768 bytes of instruction data, which leaves room in a 1232 byte
+/// transaction for the signatures and accounts of a delegate or fee payer. An
+/// access list holds at most 24 entries without bounds or hosts, so a bulk
+/// change may still fail with `AccessListFull`. Compute grows with the keys
+/// times the entries, well under the default budget at these sizes.
+pub const MAX_BULK_KEYS: usize = 24;
+
+/// Check the keys of a bulk instruction fit within `MAX_BULK_KEYS`
+pub fn validate_bulk_keys(keys: &[Pubkey]) -> ProgramResult {
+    if keys.len() > MAX_BULK_KEYS {
+        return Err(SolanaPamError::TooManyKeys.into());
+    }
+    Ok(())
+}
+
+/// An access list account
+///
+/// Borsh encoded as the discriminator, the version, the owner, the multisig,
+/// a u32 entry count and the entries. The rest of the account is zero and
+/// never read.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct AccessList {
+    discriminator: [u8; 8],
+    version: u8,
+    /// The user whose list this is, the only signer allowed to change it
+    /// while it has no multisig
+    pub owner: Pubkey,
+    /// Once set, the list only changes through proposals executed by this
+    /// multisig account
+    pub multisig: Option<Pubkey>,
+    pub entries: UserAccessList,
+}
+
+/// An older version of the layout, read so those lists keep working until
+/// they are rewritten
+#[derive(BorshDeserialize)]
+struct AccessListOld<E> {
+    _discriminator: [u8; 8],
+    _version: u8,
+    owner: Pubkey,
+    entries: Vec<E>,
+}
+
+impl<E: Into<AccessEntry>> From<AccessListOld<E>> for AccessList {
+    fn from(old: AccessListOld<E>) -> Self {
+        let mut list = AccessList::new(old.owner);
+        list.entries = old.entries.into_iter().map(Into::into).collect();
+        list
+    }
+}
+
+/// Versions 4 to 6 of the layout, with a multisig but older entries
+#[derive(BorshDeserialize)]
+struct AccessListV4<E> {
+    _discriminator: [u8; 8],
+    _version: u8,
+    owner: Pubkey,
+    multisig: Option<Pubkey>,
+    entries: Vec<E>,
+}
+
+impl<E: Into<AccessEntry>> From<AccessListV4<E>> for AccessList {
+    fn from(old: AccessListV4<E>) -> Self {
+        let mut list = AccessList::new(old.owner);
+        list.multisig = old.multisig;
+        list.entries = old.entries.into_iter().map(Into::into).collect();
+        list
+    }
+}
+
+impl AccessList {
+    pub fn new(owner: Pubkey) -> Self {
+        AccessList {
+            discriminator: ACCESS_LIST_DISCRIMINATOR,
+            version: ACCESS_LIST_VERSION,
+            owner,
+            multisig: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Whether `input` starts with the header of any layout version
+    pub fn has_header(input: &[u8]) -> bool {
+        input.get(..8) == Some(&ACCESS_LIST_DISCRIMINATOR[..])
+            && matches!(input.get(8), Some(1..=ACCESS_LIST_VERSION))
+    }
+
+    /// Whether `input` is an access list closed by `close`
+    pub fn is_closed(input: &[u8]) -> bool {
+        input.get(..8) == Some(&CLOSED_ACCESS_LIST_DISCRIMINATOR[..])
+    }
+
+    /// Zero a closed access list's data and mark it closed
+    pub fn close(output: &mut [u8]) -> ProgramResult {
+        write_padded(
+            &CLOSED_ACCESS_LIST_DISCRIMINATOR,
+            output,
+            SolanaPamError::MalformedAccessList,
+        )
+    }
+
+    /// Decode an access list account, ignoring the padding after the entries
+    ///
+    /// Older layout versions are converted, and are written back in the
+    /// current one.
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if Self::is_closed(input) {
+            return Err(SolanaPamError::AccessListClosed.into());
+        }
+        if !Self::has_header(input) {
+            return Err(SolanaPamError::MalformedAccessList.into());
+        }
+        let malformed = |_| SolanaPamError::MalformedAccessList.into();
+        let input = &mut &input[..];
+        match input[8] {
+            1 => AccessListOld::<Pubkey>::deserialize(input).map(Self::from),
+            2 => AccessListOld::<AccessEntryV2>::deserialize(input).map(Self::from),
+            3 => AccessListOld::<AccessEntryV3>::deserialize(input).map(Self::from),
+            4 => AccessListV4::<AccessEntryV3>::deserialize(input).map(Self::from),
+            5 => AccessListV4::<AccessEntryV4>::deserialize(input).map(Self::from),
+            6 => AccessListV4::<AccessEntryV5>::deserialize(input).map(Self::from),
+            _ => Self::deserialize(input),
+        }
+        .map_err(malformed)
+    }
+
+    /// Decode an account written before access lists had a header
+    ///
+    /// The old format is a run of 32 byte slots where an all-zero slot is
+    /// unused. It does not record who owns the list, so the caller supplies the
+    /// owner. Accounts that have a header are unpacked as they are.
+    pub fn migrate(input: &[u8], owner: Pubkey) -> Result<Self, ProgramError> {
+        if Self::has_header(input) || Self::is_closed(input) {
+            return Self::unpack(input);
+        }
+        if input.len() % 32 != 0 {
+            return Err(SolanaPamError::MalformedAccessList.into());
+        }
+        let mut list = AccessList::new(owner);
+        for slot in input.chunks_exact(32) {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(slot);
+            if key != [0u8; 32] {
+                list.add(AccessEntry::new(Pubkey::new_from_array(key)));
+            }
+        }
+        Ok(list)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    /// Write the list over an account's data, zeroing everything after it
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::AccessListFull)
+    }
+
+    /// Add an entry, replacing the key's entry that allows or denies as it
+    /// does, if there is one
+    pub fn add(&mut self, add: AccessEntry) {
+        add_entry(&mut self.entries, add)
+    }
+
+    pub fn remove(&mut self, remove: &Pubkey) {
+        self.entries.retain(|e| e.pubkey != *remove);
+    }
+
+    /// Add each key without time bounds, leaving keys already listed as they are
+    pub fn add_many(&mut self, keys: &[Pubkey]) {
+        for key in keys {
+            if self.get(key).is_none() {
+                self.entries.push(AccessEntry::new(*key));
+            }
+        }
+    }
+
+    pub fn remove_many(&mut self, keys: &[Pubkey]) {
+        self.entries.retain(|e| !keys.contains(&e.pubkey));
+    }
+
+    /// List exactly `keys`, in order and once each
+    ///
+    /// Keys already listed keep their entries; the others are added without
+    /// time bounds.
+    pub fn replace(&mut self, keys: &[Pubkey]) {
+        self.entries = replace_entries(&self.entries, keys);
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&AccessEntry> {
+        self.entries.iter().find(|e| e.pubkey == *pubkey)
+    }
+
+    /// The key's entry that denies, or with `deny` unset, that allows
+    pub fn find(&self, pubkey: &Pubkey, deny: bool) -> Option<&AccessEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.pubkey == *pubkey && e.deny == deny)
+    }
+
+    /// Drop entries that will never grant access again
+    pub fn remove_expired(&mut self, now: i64) {
+        self.entries.retain(|e| !e.is_expired(now));
+    }
+
+    /// Change the role of a listed key's allow entry
+    pub fn set_role(&mut self, pubkey: &Pubkey, role: Role) -> ProgramResult {
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.pubkey == *pubkey && !e.deny)
+        {
+            Some(entry) => {
+                entry.role = role;
+                Ok(())
+            }
+            None => Err(SolanaPamError::EntryNotFound.into()),
+        }
+    }
+
+    /// Whether `pubkey` has an admin entry in force at `now`, letting it
+    /// change the list without a delegate account
+    ///
+    /// A deny entry for the key in force at `now`, whatever its scope, takes
+    /// the admin rights away.
+    pub fn is_admin(&self, pubkey: &Pubkey, now: i64) -> bool {
+        let admin = self.find(pubkey, false).map_or(false, |e| {
+            e.kind == EntryKind::Key && e.role == Role::Admin && e.is_active(now)
+        });
+        admin && !self.find(pubkey, true).map_or(false, |e| e.is_active(now))
+    }
+}
+
+/// Add `add` to `entries`, replacing the entry for its key that allows or
+/// denies as it does if there is one
+///
+/// A key has at most one allow and one deny entry, so a list can grant a key
+/// access and refuse it on some hosts.
+fn add_entry(entries: &mut UserAccessList, add: AccessEntry) {
+    match entries
+        .iter_mut()
+        .find(|e| e.pubkey == add.pubkey && e.deny == add.deny)
+    {
+        Some(entry) => *entry = add,
+        None => entries.push(add),
+    }
+}
+
+/// The entries for exactly `keys`, as `AccessList::replace` lists them
+fn replace_entries(entries: &[AccessEntry], keys: &[Pubkey]) -> UserAccessList {
+    let mut replaced = UserAccessList::new();
+    for key in keys {
+        if replaced.iter().any(|e| e.pubkey == *key) {
+            continue;
+        }
+        let len = replaced.len();
+        replaced.extend(entries.iter().filter(|e| e.pubkey == *key).cloned());
+        if replaced.len() == len {
+            replaced.push(AccessEntry::new(*key));
+        }
+    }
+    replaced
+}
+
+/// Copy `packed` over the start of `output` and zero the rest
+fn write_padded(packed: &[u8], output: &mut [u8], too_long: SolanaPamError) -> ProgramResult {
+    if packed.len() > output.len() {
+        return Err(too_long.into());
+    }
+    let (used, unused) = output.split_at_mut(packed.len());
+    used.copy_from_slice(packed);
+    for byte in unused.iter_mut() {
+        *byte = 0;
+    }
+    Ok(())
+}
+
+/// First bytes of every host account
+pub const HOST_DISCRIMINATOR: [u8; 8] = *b"pam_host";
+pub const HOST_VERSION: u8 = 1;
+/// Size of a host account, enough for a full label and tag set
+pub const HOST_ACCOUNT_SIZE: usize =
+    8 + 1 + 32 + (4 + MAX_HOST_NAME_LEN) + 4 + MAX_HOST_TAGS * (4 + MAX_HOST_NAME_LEN);
+
+/// A machine the PAM module runs on, registered by the program authority
+///
+/// Lives at the address from `find_host_address` for its host key, which the
+/// module loads from a local key file to find out which host it is.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct HostAccount {
+    discriminator: [u8; 8],
+    version: u8,
+    pub host: Pubkey,
+    pub label: String,
+    pub tags: Vec<String>,
+}
+
+impl HostAccount {
+    pub fn new(host: Pubkey, label: String, tags: Vec<String>) -> Result<Self, ProgramError> {
+        let valid = is_valid_host_name(&label)
+            && tags.len() <= MAX_HOST_TAGS
+            && tags.iter().all(|tag| is_valid_host_name(tag));
+        if !valid {
+            return Err(SolanaPamError::InvalidHostName.into());
+        }
+        Ok(HostAccount {
+            discriminator: HOST_DISCRIMINATOR,
+            version: HOST_VERSION,
+            host,
+            label,
+            tags,
+        })
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&HOST_DISCRIMINATOR[..]) || input.get(8) != Some(&HOST_VERSION) {
+            return Err(SolanaPamError::MalformedHostAccount.into());
+        }
+        Self::deserialize(&mut &input[..]).map_err(|_| SolanaPamError::MalformedHostAccount.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::InvalidHostName)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// Longest username, the usual limit for POSIX login names
+pub const MAX_USERNAME_LEN: usize = 32;
+
+/// Whether `name` is a portable POSIX login name: a lowercase letter or
+/// underscore, then lowercase letters, digits, underscores or dashes
+pub fn is_valid_username(name: &str) -> bool {
+    let mut chars = name.chars();
+    let first_valid = matches!(chars.next(), Some('a'..='z') | Some('_'));
+    first_valid
+        && name.len() <= MAX_USERNAME_LEN
+        && chars.all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_' | '-'))
+}
+
+/// First bytes of every username account
+pub const USERNAME_DISCRIMINATOR: [u8; 8] = *b"pam_name";
+pub const USERNAME_VERSION: u8 = 1;
+/// Size of a username account, enough for the longest name
+pub const USERNAME_ACCOUNT_SIZE: usize = 8 + 1 + 32 + 4 + MAX_USERNAME_LEN;
+
+/// Records which key claimed a username
+///
+/// Lives at the address from `find_username_address` for the name, so a name
+/// can only be claimed by one key at a time.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct UsernameAccount {
+    discriminator: [u8; 8],
+    version: u8,
+    pub owner: Pubkey,
+    pub username: String,
+}
+
+impl UsernameAccount {
+    pub fn new(owner: Pubkey, username: String) -> Result<Self, ProgramError> {
+        if !is_valid_username(&username) {
+            return Err(SolanaPamError::InvalidUsername.into());
+        }
+        Ok(UsernameAccount {
+            discriminator: USERNAME_DISCRIMINATOR,
+            version: USERNAME_VERSION,
+            owner,
+            username,
+        })
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&USERNAME_DISCRIMINATOR[..])
+            || input.get(8) != Some(&USERNAME_VERSION)
+        {
+            return Err(SolanaPamError::MalformedUsernameAccount.into());
+        }
+        Self::deserialize(&mut &input[..])
+            .map_err(|_| SolanaPamError::MalformedUsernameAccount.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::InvalidUsername)
+    }
+}
+
+/// First bytes of every account policy
+pub const ACCOUNT_POLICY_DISCRIMINATOR: [u8; 8] = *b"pam_acct";
+/// Layout version written by this program; version 1 entries had no deny flag,
+/// version 2 entries no role and version 3 entries no kind
+pub const ACCOUNT_POLICY_VERSION: u8 = 4;
+/// Bytes before the first entry: discriminator, version, admin, the longest
+/// account name and the entry count
+pub const ACCOUNT_POLICY_HEADER_SIZE: usize = 8 + 1 + 32 + 4 + MAX_USERNAME_LEN + 4;
+/// Size of an account policy, as allocated by `InitAccountPolicy`
+pub const ACCOUNT_POLICY_SIZE: usize = 4096;
+/// Number of entries that always fit in an account policy
+pub const ACCOUNT_POLICY_CAPACITY: usize =
+    (ACCOUNT_POLICY_SIZE - ACCOUNT_POLICY_HEADER_SIZE) / ACCESS_ENTRY_MAX_SIZE;
+
+/// The keys that may log in as a unix account, such as a shared `deploy`
+/// account, each with its own time bounds and hosts
+///
+/// Lives at the address from `find_account_policy_address` for the account
+/// name. Unlike an access list, the keys are not tied to one user.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct AccountPolicy {
+    discriminator: [u8; 8],
+    version: u8,
+    /// The only signer allowed to change the policy
+    pub admin: Pubkey,
+    /// The unix account the policy is for
+    pub account: String,
+    pub entries: UserAccessList,
+}
+
+/// Versions 1 to 3 of the layout, read so those policies keep working
+#[derive(BorshDeserialize)]
+struct AccountPolicyOld<E> {
+    _discriminator: [u8; 8],
+    _version: u8,
+    admin: Pubkey,
+    account: String,
+    entries: Vec<E>,
+}
+
+impl<E: Into<AccessEntry>> From<AccountPolicyOld<E>> for AccountPolicy {
+    fn from(old: AccountPolicyOld<E>) -> Self {
+        AccountPolicy {
+            discriminator: ACCOUNT_POLICY_DISCRIMINATOR,
+            version: ACCOUNT_POLICY_VERSION,
+            admin: old.admin,
+            account: old.account,
+            entries: old.entries.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl AccountPolicy {
+    pub fn new(admin: Pubkey, account: String) -> Result<Self, ProgramError> {
+        if !is_valid_username(&account) {
+            return Err(SolanaPamError::InvalidUsername.into());
+        }
+        Ok(AccountPolicy {
+            discriminator: ACCOUNT_POLICY_DISCRIMINATOR,
+            version: ACCOUNT_POLICY_VERSION,
+            admin,
+            account,
+            entries: Vec::new(),
+        })
+    }
+
+    /// Decode an account policy, converting older versions
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&ACCOUNT_POLICY_DISCRIMINATOR[..]) {
+            return Err(SolanaPamError::MalformedAccountPolicy.into());
+        }
+        let input = &mut &input[..];
+        match input.get(8) {
+            Some(1) => AccountPolicyOld::<AccessEntryV3>::deserialize(input).map(Self::from),
+            Some(2) => AccountPolicyOld::<AccessEntryV4>::deserialize(input).map(Self::from),
+            Some(3) => AccountPolicyOld::<AccessEntryV5>::deserialize(input).map(Self::from),
+            Some(&ACCOUNT_POLICY_VERSION) => Self::deserialize(input),
+            _ => return Err(SolanaPamError::MalformedAccountPolicy.into()),
+        }
+        .map_err(|_| SolanaPamError::MalformedAccountPolicy.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::AccountPolicyFull)
+    }
+
+    /// Add an entry, replacing the key's entry that allows or denies as it
+    /// does, if there is one
+    pub fn add(&mut self, add: AccessEntry) {
+        add_entry(&mut self.entries, add)
+    }
+
+    pub fn remove(&mut self, remove: &Pubkey) {
+        self.entries.retain(|e| e.pubkey != *remove);
+    }
+
+    /// Add each key without time bounds, leaving keys already listed as they are
+    pub fn add_many(&mut self, keys: &[Pubkey]) {
+        for key in keys {
+            if self.get(key).is_none() {
+                self.entries.push(AccessEntry::new(*key));
+            }
+        }
+    }
+
+    pub fn remove_many(&mut self, keys: &[Pubkey]) {
+        self.entries.retain(|e| !keys.contains(&e.pubkey));
+    }
+
+    /// List exactly `keys`, in order and once each
+    ///
+    /// Keys already listed keep their entries; the others are added without
+    /// time bounds.
+    pub fn replace(&mut self, keys: &[Pubkey]) {
+        self.entries = replace_entries(&self.entries, keys);
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> Option<&AccessEntry> {
+        self.entries.iter().find(|e| e.pubkey == *pubkey)
+    }
+
+    /// Drop entries that will never grant access again
+    pub fn remove_expired(&mut self, now: i64) {
+        self.entries.retain(|e| !e.is_expired(now));
+    }
+}
+
+/// Most admins a multisig can have, one per bit of a proposal's approvals
+pub const MAX_MULTISIG_ADMINS: usize = 16;
+
+/// Check that `threshold` of `admins` is a usable approval rule
+fn validate_multisig(admins: &[Pubkey], threshold: u8) -> ProgramResult {
+    let unique = admins
+        .iter()
+        .enumerate()
+        .all(|(i, admin)| !admins[..i].contains(admin));
+    let valid = unique
+        && admins.len() <= MAX_MULTISIG_ADMINS
+        && threshold >= 1
+        && threshold as usize <= admins.len();
+    if valid {
+        Ok(())
+    } else {
+        Err(SolanaPamError::InvalidMultisig.into())
+    }
+}
+
+/// First bytes of every multisig account
+pub const MULTISIG_DISCRIMINATOR: [u8; 8] = *b"pam_msig";
+pub const MULTISIG_VERSION: u8 = 1;
+/// Size of a multisig account, enough for the most admins
+pub const MULTISIG_SIZE: usize = 8 + 1 + 32 + 4 + MAX_MULTISIG_ADMINS * 32 + 1 + 4 + 8;
+
+/// The admins governing an access list and how many must approve a change
+///
+/// Lives at the address from `find_multisig_address` for the list.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct Multisig {
+    discriminator: [u8; 8],
+    version: u8,
+    pub access_list: Pubkey,
+    pub admins: Vec<Pubkey>,
+    pub threshold: u8,
+    /// Bumped whenever the admins or threshold change, so approvals given
+    /// under the old rule can not be used under the new one
+    pub generation: u32,
+    /// Number of proposals made so far, the index of the next one
+    pub proposal_count: u64,
+}
+
+impl Multisig {
+    pub fn new(
+        access_list: Pubkey,
+        admins: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<Self, ProgramError> {
+        validate_multisig(&admins, threshold)?;
+        Ok(Multisig {
+            discriminator: MULTISIG_DISCRIMINATOR,
+            version: MULTISIG_VERSION,
+            access_list,
+            admins,
+            threshold,
+            generation: 0,
+            proposal_count: 0,
+        })
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&MULTISIG_DISCRIMINATOR[..])
+            || input.get(8) != Some(&MULTISIG_VERSION)
+        {
+            return Err(SolanaPamError::MalformedMultisig.into());
+        }
+        Self::deserialize(&mut &input[..]).map_err(|_| SolanaPamError::MalformedMultisig.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::InvalidMultisig)
+    }
+
+    /// Position of `admin` among the admins, its bit in proposal approvals
+    pub fn admin_index(&self, admin: &Pubkey) -> Option<usize> {
+        self.admins.iter().position(|a| a == admin)
+    }
+
+    /// Replace the admins and threshold, invalidating open proposals
+    pub fn set_admins(&mut self, admins: Vec<Pubkey>, threshold: u8) -> ProgramResult {
+        validate_multisig(&admins, threshold)?;
+        self.admins = admins;
+        self.threshold = threshold;
+        self.generation = self.generation.wrapping_add(1);
+        Ok(())
+    }
+}
+
+/// A change to a multisig governed access list, or to the multisig itself
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub enum ProposalAction {
+    /// Add the entry to the list, or replace the one for its key
+    AddEntry(AccessEntry),
+    /// Remove the key from the list
+    RemoveEntry(Pubkey),
+    /// Replace the multisig's admins and threshold
+    SetMultisig { admins: Vec<Pubkey>, threshold: u8 },
+}
+
+impl ProposalAction {
+    pub fn validate(&self) -> ProgramResult {
+        match self {
+            ProposalAction::AddEntry(entry) => entry.validate(),
+            ProposalAction::RemoveEntry(_) => Ok(()),
+            ProposalAction::SetMultisig { admins, threshold } => {
+                validate_multisig(admins, *threshold)
+            }
+        }
+    }
+}
+
+/// First bytes of every proposal account
+pub const PROPOSAL_DISCRIMINATOR: [u8; 8] = *b"pam_prop";
+/// Layout version written by this program; version 1 entries had no deny flag,
+/// version 2 entries no role and version 3 entries no kind
+pub const PROPOSAL_VERSION: u8 = 4;
+/// Size of a proposal account, enough for the largest action
+pub const PROPOSAL_SIZE: usize =
+    8 + 1 + 32 + 8 + 4 + 2 + 1 + (1 + 4 + MAX_MULTISIG_ADMINS * 32 + 1);
+
+/// A change waiting for a multisig's approval
+///
+/// Lives at the address from `find_proposal_address` for the multisig and
+/// the proposal's index.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct Proposal {
+    discriminator: [u8; 8],
+    version: u8,
+    pub multisig: Pubkey,
+    pub index: u64,
+    /// The multisig generation the proposal was made under
+    pub generation: u32,
+    /// Bit `i` is set once the multisig's admin `i` has approved
+    pub approvals: u16,
+    pub executed: bool,
+    pub action: ProposalAction,
+}
+
+/// Versions 1 to 3 of the layout, read so pending proposals survive the
+/// upgrade
+#[derive(BorshDeserialize)]
+struct ProposalOld<E> {
+    _discriminator: [u8; 8],
+    _version: u8,
+    multisig: Pubkey,
+    index: u64,
+    generation: u32,
+    approvals: u16,
+    executed: bool,
+    action: ProposalActionOld<E>,
+}
+
+#[derive(BorshDeserialize)]
+enum ProposalActionOld<E> {
+    AddEntry(E),
+    RemoveEntry(Pubkey),
+    SetMultisig { admins: Vec<Pubkey>, threshold: u8 },
+}
+
+impl<E: Into<AccessEntry>> From<ProposalOld<E>> for Proposal {
+    fn from(old: ProposalOld<E>) -> Self {
+        let action = match old.action {
+            ProposalActionOld::AddEntry(entry) => ProposalAction::AddEntry(entry.into()),
+            ProposalActionOld::RemoveEntry(pubkey) => ProposalAction::RemoveEntry(pubkey),
+            ProposalActionOld::SetMultisig { admins, threshold } => {
+                ProposalAction::SetMultisig { admins, threshold }
+            }
+        };
+        Proposal {
+            approvals: old.approvals,
+            executed: old.executed,
+            ..Proposal::new(old.multisig, old.index, old.generation, action)
+        }
+    }
+}
+
+impl Proposal {
+    pub fn new(multisig: Pubkey, index: u64, generation: u32, action: ProposalAction) -> Self {
+        Proposal {
+            discriminator: PROPOSAL_DISCRIMINATOR,
+            version: PROPOSAL_VERSION,
+            multisig,
+            index,
+            generation,
+            approvals: 0,
+            executed: false,
+            action,
+        }
+    }
+
+    /// Decode a proposal, converting older versions
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&PROPOSAL_DISCRIMINATOR[..]) {
+            return Err(SolanaPamError::MalformedProposal.into());
+        }
+        let input = &mut &input[..];
+        match input.get(8) {
+            Some(1) => ProposalOld::<AccessEntryV3>::deserialize(input).map(Self::from),
+            Some(2) => ProposalOld::<AccessEntryV4>::deserialize(input).map(Self::from),
+            Some(3) => ProposalOld::<AccessEntryV5>::deserialize(input).map(Self::from),
+            Some(&PROPOSAL_VERSION) => Self::deserialize(input),
+            _ => return Err(SolanaPamError::MalformedProposal.into()),
+        }
+        .map_err(|_| SolanaPamError::MalformedProposal.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::MalformedProposal)
+    }
+
+    /// Record the approval of the admin at `admin_index`, once
+    pub fn approve(&mut self, admin_index: usize) {
+        self.approvals |= 1 << admin_index;
+    }
+
+    pub fn approval_count(&self) -> u32 {
+        self.approvals.count_ones()
+    }
+
+    pub fn is_approved_by(&self, admin_index: usize) -> bool {
+        self.approvals & (1 << admin_index) != 0
+    }
+}
+
+/// A delegate may add keys that are not yet listed, or drop their deny entries
+pub const DELEGATE_ADD: u8 = 1;
+/// A delegate may remove keys, or deny keys that have no deny entry yet
+pub const DELEGATE_REMOVE: u8 = 1 << 1;
+/// A delegate may change when the entries of keys already listed grant access
+///
+/// Changing anything else about a listed key's entry, such as its scope or
+/// whether it denies, takes both `DELEGATE_ADD` and `DELEGATE_REMOVE`.
+pub const DELEGATE_MANAGE_EXPIRY: u8 = 1 << 2;
+/// A delegate may give keys a role other than `Role::Login`, or change a role
+///
+/// Making a key a `Role::Admin`, or stopping it being one, takes every right.
+pub const DELEGATE_MANAGE_ROLES: u8 = 1 << 3;
+pub const DELEGATE_ALL_RIGHTS: u8 =
+    DELEGATE_ADD | DELEGATE_REMOVE | DELEGATE_MANAGE_EXPIRY | DELEGATE_MANAGE_ROLES;
+
+/// First bytes of every delegate account
+pub const DELEGATE_DISCRIMINATOR: [u8; 8] = *b"pam_dlgt";
+pub const DELEGATE_VERSION: u8 = 1;
+pub const DELEGATE_SIZE: usize = 8 + 1 + 32 + 32 + 1;
+
+/// A key the owner of an access list lets change it, within `rights`
+///
+/// Lives at the address from `find_delegate_address` for the list and the
+/// delegate's key.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct Delegate {
+    discriminator: [u8; 8],
+    version: u8,
+    pub access_list: Pubkey,
+    pub delegate: Pubkey,
+    /// `DELEGATE_*` bits
+    pub rights: u8,
+}
+
+impl Delegate {
+    pub fn new(access_list: Pubkey, delegate: Pubkey, rights: u8) -> Result<Self, ProgramError> {
+        let mut record = Delegate {
+            discriminator: DELEGATE_DISCRIMINATOR,
+            version: DELEGATE_VERSION,
+            access_list,
+            delegate,
+            rights: 0,
+        };
+        record.set_rights(rights)?;
+        Ok(record)
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&DELEGATE_DISCRIMINATOR[..])
+            || input.get(8) != Some(&DELEGATE_VERSION)
+        {
+            return Err(SolanaPamError::MalformedDelegate.into());
+        }
+        Self::deserialize(&mut &input[..]).map_err(|_| SolanaPamError::MalformedDelegate.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::MalformedDelegate)
+    }
+
+    /// Replace the rights, which must be some of the `DELEGATE_*` bits
+    pub fn set_rights(&mut self, rights: u8) -> ProgramResult {
+        if rights == 0 || rights & !DELEGATE_ALL_RIGHTS != 0 {
+            return Err(SolanaPamError::InvalidDelegateRights.into());
+        }
+        self.rights = rights;
+        Ok(())
+    }
+
+    /// Fail unless the delegate holds every right in `rights`
+    pub fn check_rights(&self, rights: u8) -> ProgramResult {
+        if self.rights & rights == rights {
+            Ok(())
+        } else {
+            Err(SolanaPamError::MissingDelegateRight.into())
+        }
+    }
+}
+
+/// First bytes of every revocation account
+pub const REVOCATION_DISCRIMINATOR: [u8; 8] = *b"pam_rvkd";
+pub const REVOCATION_VERSION: u8 = 1;
+pub const REVOCATION_SIZE: usize = 8 + 1 + 32 + 32 + 8;
+
+/// A key locked out of every host, whatever its grants say
+///
+/// Lives at the address from `find_revocation_address` for the key, so one
+/// lookup tells whether a key is revoked.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct Revocation {
+    discriminator: [u8; 8],
+    version: u8,
+    pub pubkey: Pubkey,
+    /// The program authority, or the key itself
+    pub revoked_by: Pubkey,
+    /// Cluster time of the revocation
+    pub revoked_at: i64,
+}
+
+impl Revocation {
+    pub fn new(pubkey: Pubkey, revoked_by: Pubkey, revoked_at: i64) -> Self {
+        Revocation {
+            discriminator: REVOCATION_DISCRIMINATOR,
+            version: REVOCATION_VERSION,
+            pubkey,
+            revoked_by,
+            revoked_at,
+        }
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&REVOCATION_DISCRIMINATOR[..])
+            || input.get(8) != Some(&REVOCATION_VERSION)
+        {
+            return Err(SolanaPamError::MalformedRevocation.into());
+        }
+        Self::deserialize(&mut &input[..]).map_err(|_| SolanaPamError::MalformedRevocation.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::MalformedRevocation)
+    }
+}
+
+/// Longest group name, in bytes
+pub const MAX_GROUP_NAME_LEN: usize = 32;
+/// Most admins a group can have
+pub const MAX_GROUP_ADMINS: usize = 8;
+/// Most levels of groups `expand_groups` follows, counting the group an entry
+/// names as the first
+pub const MAX_GROUP_DEPTH: usize = 4;
+
+/// One member of a group
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub enum GroupMember {
+    Key(Pubkey),
+    /// Every member of this group account
+    Group(Pubkey),
+}
+
+/// First bytes of every group account
+pub const GROUP_DISCRIMINATOR: [u8; 8] = *b"pam_grup";
+pub const GROUP_VERSION: u8 = 1;
+/// Bytes before the first member: discriminator, version, the longest name,
+/// the most admins and the member count
+pub const GROUP_HEADER_SIZE: usize = 8 + 1 + 4 + MAX_GROUP_NAME_LEN + 4 + MAX_GROUP_ADMINS * 32 + 4;
+/// Size of a group account, as allocated by `CreateGroup`
+pub const GROUP_SIZE: usize = 2048;
+/// Number of members that fit in a group account
+pub const GROUP_CAPACITY: usize = (GROUP_SIZE - GROUP_HEADER_SIZE) / 33;
+
+/// A team of keys, and of other groups, that access entries can name at once
+///
+/// Lives at the address from `find_group_address` for its name.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct Group {
+    discriminator: [u8; 8],
+    version: u8,
+    pub name: String,
+    /// The signers allowed to change the members and admins
+    pub admins: Vec<Pubkey>,
+    pub members: Vec<GroupMember>,
+}
+
+/// Check `admins` is a usable, duplicate free set of group admins
+fn validate_group_admins(admins: &[Pubkey]) -> ProgramResult {
+    let unique = admins
+        .iter()
+        .enumerate()
+        .all(|(i, admin)| !admins[..i].contains(admin));
+    if unique && !admins.is_empty() && admins.len() <= MAX_GROUP_ADMINS {
+        Ok(())
+    } else {
+        Err(SolanaPamError::InvalidGroup.into())
+    }
+}
+
+impl Group {
+    pub fn new(name: String, admins: Vec<Pubkey>) -> Result<Self, ProgramError> {
+        if name.is_empty() || name.len() > MAX_GROUP_NAME_LEN {
+            return Err(SolanaPamError::InvalidGroup.into());
+        }
+        validate_group_admins(&admins)?;
+        Ok(Group {
+            discriminator: GROUP_DISCRIMINATOR,
+            version: GROUP_VERSION,
+            name,
+            admins,
+            members: Vec::new(),
+        })
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&GROUP_DISCRIMINATOR[..]) || input.get(8) != Some(&GROUP_VERSION)
+        {
+            return Err(SolanaPamError::MalformedGroup.into());
+        }
+        Self::deserialize(&mut &input[..]).map_err(|_| SolanaPamError::MalformedGroup.into())
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::GroupFull)
+    }
+
+    pub fn is_admin(&self, pubkey: &Pubkey) -> bool {
+        self.admins.contains(pubkey)
+    }
+
+    pub fn set_admins(&mut self, admins: Vec<Pubkey>) -> ProgramResult {
+        validate_group_admins(&admins)?;
+        self.admins = admins;
+        Ok(())
+    }
+
+    /// Add a member, once
+    pub fn add(&mut self, member: GroupMember) {
+        if !self.members.contains(&member) {
+            self.members.push(member);
+        }
+    }
+
+    pub fn remove(&mut self, member: &GroupMember) {
+        self.members.retain(|m| m != member);
+    }
+}
+
+/// Why the members of a group could not be worked out
+#[derive(Debug, PartialEq)]
+pub enum GroupError<E> {
+    /// A group could not be read
+    Fetch(E),
+    /// The group is nested in itself
+    Cycle(Pubkey),
+    /// The group is nested more than `MAX_GROUP_DEPTH` levels down
+    TooDeep(Pubkey),
+}
+
+/// Find the keys in each of `groups`, directly or through nested groups
+///
+/// `fetch` reads a group account, `None` for one that does not exist, which
+/// has no members. Each group is read once however often it is nested. A
+/// group nested in itself or too deep fails the whole expansion, so what is
+/// granted never depends on the order members were added in.
+pub fn expand_groups<E, F>(
+    groups: &[Pubkey],
+    mut fetch: F,
+) -> Result<Vec<(Pubkey, Vec<Pubkey>)>, GroupError<E>>
+where
+    F: FnMut(&Pubkey) -> Result<Option<Group>, E>,
+{
+    let mut expanded = Vec::new();
+    let mut path = Vec::new();
+    for group in groups {
+        expand_group(group, &mut fetch, &mut path, &mut expanded)?;
+    }
+    Ok(expanded
+        .into_iter()
+        .filter(|e| groups.contains(&e.group))
+        .map(|e| (e.group, e.keys))
+        .collect())
+}
+
+/// A group whose members have been worked out
+struct ExpandedGroup {
+    group: Pubkey,
+    keys: Vec<Pubkey>,
+    /// Levels of groups from this one down to its most deeply nested group
+    height: usize,
+}
+
+/// Expand `group`, nested below the groups in `path`, and every group below
+/// it into `expanded`, returning its index there
+fn expand_group<E, F>(
+    group: &Pubkey,
+    fetch: &mut F,
+    path: &mut Vec<Pubkey>,
+    expanded: &mut Vec<ExpandedGroup>,
+) -> Result<usize, GroupError<E>>
+where
+    F: FnMut(&Pubkey) -> Result<Option<Group>, E>,
+{
+    if path.contains(group) {
+        return Err(GroupError::Cycle(*group));
+    }
+    // A group already expanded is checked against the depth it is found at now
+    if let Some(index) = expanded.iter().position(|e| e.group == *group) {
+        if path.len() + expanded[index].height > MAX_GROUP_DEPTH {
+            return Err(GroupError::TooDeep(*group));
+        }
+        return Ok(index);
+    }
+    if path.len() == MAX_GROUP_DEPTH {
+        return Err(GroupError::TooDeep(*group));
+    }
+    let members = fetch(group)
+        .map_err(GroupError::Fetch)?
+        .map_or_else(Vec::new, |g| g.members);
+    path.push(*group);
+    let (mut keys, mut height) = (Vec::new(), 1);
+    for member in &members {
+        let found = match member {
+            GroupMember::Key(key) => std::slice::from_ref(key),
+            GroupMember::Group(nested) => {
+                let index = expand_group(nested, fetch, path, expanded)?;
+                height = height.max(expanded[index].height + 1);
+                &expanded[index].keys[..]
+            }
+        };
+        for key in found {
+            if !keys.contains(key) {
+                keys.push(*key);
+            }
+        }
+    }
+    path.pop();
+    expanded.push(ExpandedGroup {
+        group: *group,
+        keys,
+        height,
+    });
+    Ok(expanded.len() - 1)
+}
+
+/// First bytes of every login log account
+pub const LOGIN_LOG_DISCRIMINATOR: [u8; 8] = *b"pam_logn";
+pub const LOGIN_LOG_VERSION: u8 = 1;
+/// Largest encoding of a login record, with the longest service name
+pub const LOGIN_RECORD_MAX_SIZE: usize = 32 + 4 + MAX_SERVICE_NAME_LEN + 32 + 8;
+/// Bytes before the first record: discriminator, version, host, the count of
+/// logins ever recorded and the record count
+pub const LOGIN_LOG_HEADER_SIZE: usize = 8 + 1 + 32 + 8 + 4;
+/// Size of a login log account, as allocated by the first `RecordLogin`
+pub const LOGIN_LOG_SIZE: usize = 4096;
+/// Number of records a login log keeps before overwriting the oldest
+pub const LOGIN_LOG_CAPACITY: usize =
+    (LOGIN_LOG_SIZE - LOGIN_LOG_HEADER_SIZE) / LOGIN_RECORD_MAX_SIZE;
+
+/// One login to a host, as attested by the host
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct LoginRecord {
+    /// The key that authenticated
+    pub user: Pubkey,
+    /// The PAM service handling the login, empty when the host did not say
+    pub service: String,
+    /// SHA-256 of the remote host the login came from, zero when there was
+    /// none, so the log does not publish client addresses
+    pub remote_host_hash: [u8; 32],
+    /// Cluster time the record was written, which the host cannot choose
+    pub timestamp: i64,
+}
+
+impl LoginRecord {
+    pub fn new(
+        user: Pubkey,
+        service: String,
+        remote_host_hash: [u8; 32],
+        timestamp: i64,
+    ) -> Result<Self, ProgramError> {
+        if service.len() > MAX_SERVICE_NAME_LEN {
+            return Err(SolanaPamError::InvalidLoginRecord.into());
+        }
+        Ok(LoginRecord {
+            user,
+            service,
+            remote_host_hash,
+            timestamp,
+        })
+    }
+}
+
+/// The most recent logins to one host, kept as a ring buffer
+///
+/// Lives at the address from `find_login_log_address` for the host key, and
+/// only the host key can append to it, so the history does not depend on the
+/// host's own logs. `total` counts every login ever recorded, so a gap shows
+/// how many were overwritten.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, PartialEq)]
+pub struct LoginLog {
+    discriminator: [u8; 8],
+    version: u8,
+    pub host: Pubkey,
+    pub total: u64,
+    /// Slot `total % LOGIN_LOG_CAPACITY` is written next
+    records: Vec<LoginRecord>,
+}
+
+impl LoginLog {
+    pub fn new(host: Pubkey) -> Self {
+        LoginLog {
+            discriminator: LOGIN_LOG_DISCRIMINATOR,
+            version: LOGIN_LOG_VERSION,
+            host,
+            total: 0,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.get(..8) != Some(&LOGIN_LOG_DISCRIMINATOR[..])
+            || input.get(8) != Some(&LOGIN_LOG_VERSION)
+        {
+            return Err(SolanaPamError::MalformedLoginLog.into());
+        }
+        let log = Self::deserialize(&mut &input[..])
+            .map_err(|_| ProgramError::from(SolanaPamError::MalformedLoginLog))?;
+        if log.records.len() > LOGIN_LOG_CAPACITY {
+            return Err(SolanaPamError::MalformedLoginLog.into());
+        }
+        Ok(log)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Writing into a Vec cannot fail
+        self.try_to_vec().unwrap()
+    }
+
+    pub fn write(&self, output: &mut [u8]) -> ProgramResult {
+        write_padded(&self.pack(), output, SolanaPamError::MalformedLoginLog)
+    }
+
+    /// Append `record`, overwriting the oldest once the log is full
+    pub fn record(&mut self, record: LoginRecord) {
+        if self.records.len() < LOGIN_LOG_CAPACITY {
+            self.records.push(record);
+        } else {
+            let slot = (self.total % LOGIN_LOG_CAPACITY as u64) as usize;
+            self.records[slot] = record;
+        }
+        self.total = self.total.saturating_add(1);
+    }
+
+    /// The records kept, oldest first
+    pub fn records(&self) -> impl Iterator<Item = &LoginRecord> {
+        let oldest = if self.records.len() < LOGIN_LOG_CAPACITY {
+            0
+        } else {
+            (self.total % LOGIN_LOG_CAPACITY as u64) as usize
+        };
+        self.records[oldest..]
+            .iter()
+            .chain(self.records[..oldest].iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_account(list: &AccessList) -> Vec<u8> {
+        let mut data = vec![0xff; ACCESS_LIST_SIZE];
+        list.write(&mut data).unwrap();
+        data
+    }
+
+    fn keys(list: &AccessList) -> Vec<Pubkey> {
+        list.entries.iter().map(|e| e.pubkey).collect()
+    }
+
+    #[test]
+    fn test_access_list_round_trip() {
+        let mut list = AccessList::new(Pubkey::new_unique());
+        let data = full_account(&list);
+        assert_eq!(AccessList::unpack(&data), Ok(list.clone()));
+
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        list.add(AccessEntry::new(first));
+        list.add(AccessEntry::new(second));
+        list.add(AccessEntry::new(first));
+        let data = full_account(&list);
+        assert_eq!(&data[..8], b"pam_acl\0");
+        assert_eq!(data[8], ACCESS_LIST_VERSION);
+        // No multisig, then the entry count
+        assert_eq!(&data[41..46], &[0, 2, 0, 0, 0]);
+        assert!(data[ACCESS_LIST_HEADER_SIZE + 2 * 38..]
+            .iter()
+            .all(|b| *b == 0));
+        let unpacked = AccessList::unpack(&data).unwrap();
+        assert_eq!(keys(&unpacked), vec![first, second]);
+
+        list.remove(&first);
+        assert_eq!(
+            keys(&AccessList::unpack(&full_account(&list)).unwrap()),
+            vec![second]
+        );
+    }
+
+    #[test]
+    fn test_access_list_capacity() {
+        let mut list = AccessList::new(Pubkey::new_unique());
+        list.multisig = Some(Pubkey::new_unique());
+        let largest = || AccessEntry {
+            pubkey: Pubkey::new_unique(),
+            not_before: Some(0),
+            expires_at: Some(1),
+            hosts: HostScope::Hosts(vec![Pubkey::new_unique(); MAX_SCOPE_HOSTS]),
+            deny: true,
+            role: Role::Admin,
+            kind: EntryKind::Token { min_amount: 1 },
+        };
+        for _ in 0..ACCESS_LIST_CAPACITY {
+            list.add(largest());
+        }
+        let mut data = vec![0; ACCESS_LIST_SIZE];
+        assert_eq!(list.write(&mut data), Ok(()));
+        assert_eq!(
+            list.pack().len(),
+            ACCESS_LIST_HEADER_SIZE + ACCESS_LIST_CAPACITY * ACCESS_ENTRY_MAX_SIZE
+        );
+        list.add(largest());
+        assert_eq!(
+            list.write(&mut data),
+            Err(SolanaPamError::AccessListFull.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_rejects_malformed_accounts() {
+        let malformed = Err(SolanaPamError::MalformedAccessList.into());
+        // A zeroed account has no header, so its padding is never read as keys
+        assert_eq!(AccessList::unpack(&[0; ACCESS_LIST_SIZE]), malformed);
+        assert_eq!(AccessList::unpack(&[]), malformed);
+
+        let mut list = AccessList::new(Pubkey::new_unique());
+        list.add(AccessEntry::new(Pubkey::new_unique()));
+        let packed = list.pack();
+        assert_eq!(AccessList::unpack(&packed[..packed.len() - 1]), malformed);
+        // An entry count larger than the account
+        let mut lying = full_account(&list);
+        lying[42..46].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(AccessList::unpack(&lying), malformed);
+        let mut future = full_account(&list);
+        future[8] = ACCESS_LIST_VERSION + 1;
+        assert_eq!(AccessList::unpack(&future), malformed);
+    }
+
+    #[test]
+    fn test_unpack_version_1() {
+        let (owner, key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut data = vec![0; ACCESS_LIST_SIZE];
+        data[..8].copy_from_slice(&ACCESS_LIST_DISCRIMINATOR);
+        data[8] = 1;
+        data[9..41].copy_from_slice(owner.as_ref());
+        data[41..45].copy_from_slice(&1u32.to_le_bytes());
+        data[45..77].copy_from_slice(key.as_ref());
+
+        let list = AccessList::unpack(&data).unwrap();
+        assert_eq!(list.owner, owner);
+        assert_eq!(list.entries, vec![AccessEntry::new(key)]);
+        assert_eq!(full_account(&list)[8], ACCESS_LIST_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_legacy_account() {
+        let owner = Pubkey::new_unique();
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut legacy = vec![0; ACCESS_LIST_SIZE];
+        legacy[..32].copy_from_slice(first.as_ref());
+        legacy[96..128].copy_from_slice(second.as_ref());
+
+        let list = AccessList::migrate(&legacy, owner).unwrap();
+        assert_eq!(list.owner, owner);
+        assert_eq!(keys(&list), vec![first, second]);
+        let data = full_account(&list);
+        assert_eq!(AccessList::unpack(&data), Ok(list.clone()));
+        // Migrating an account that already has a header keeps its owner
+        assert_eq!(AccessList::migrate(&data, Pubkey::new_unique()), Ok(list));
+
+        assert_eq!(
+            AccessList::migrate(&legacy[..33], owner),
+            Err(SolanaPamError::MalformedAccessList.into())
+        );
+    }
+
+    #[test]
+    fn test_entry_time_bounds() {
+        let entry = AccessEntry {
+            not_before: Some(100),
+            expires_at: Some(200),
+            ..AccessEntry::new(Pubkey::new_unique())
+        };
+        assert!(entry.is_pending(99));
+        assert!(entry.is_active(100));
+        assert!(entry.is_active(199));
+        assert!(entry.is_expired(200));
+        assert!(!entry.is_active(200));
+        assert!(AccessEntry::new(entry.pubkey).is_active(i64::MAX));
+        assert_eq!(entry.validate(), Ok(()));
+        assert_eq!(
+            AccessEntry {
+                expires_at: Some(100),
+                ..entry.clone()
+            }
+            .validate(),
+            Err(SolanaPamError::InvalidGrantWindow.into())
+        );
+
+        let mut list = AccessList::new(Pubkey::new_unique());
+        let permanent = AccessEntry::new(Pubkey::new_unique());
+        list.add(entry.clone());
+        list.add(permanent.clone());
+        list.remove_expired(150);
+        assert_eq!(list.entries, vec![entry.clone(), permanent.clone()]);
+        list.remove_expired(200);
+        assert_eq!(list.entries, vec![permanent.clone()]);
+        // Adding a listed key again changes its bounds
+        list.add(AccessEntry {
+            expires_at: Some(300),
+            ..permanent.clone()
+        });
+        assert_eq!(list.get(&permanent.pubkey).unwrap().expires_at, Some(300));
+        assert_eq!(list.entries.len(), 1);
+
+        assert!(!entry.same_grant(&permanent));
+        assert!(entry.same_grant(&AccessEntry {
+            not_before: None,
+            role: Role::Admin,
+            ..entry.clone()
+        }));
+        assert!(!entry.same_grant(&AccessEntry {
+            deny: true,
+            ..entry.clone()
+        }));
+        assert!(!entry.same_grant(&AccessEntry {
+            kind: EntryKind::Group,
+            ..entry.clone()
+        }));
+    }
+
+    #[test]
+    fn test_unpack_version_2() {
+        let (owner, key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut data = vec![0; ACCESS_LIST_SIZE];
+        data[..8].copy_from_slice(&ACCESS_LIST_DISCRIMINATOR);
+        data[8] = 2;
+        data[9..41].copy_from_slice(owner.as_ref());
+        data[41..45].copy_from_slice(&1u32.to_le_bytes());
+        data[45..77].copy_from_slice(key.as_ref());
+        // not_before unset, expires_at set
+        data[77] = 0;
+        data[78] = 1;
+        data[79..87].copy_from_slice(&500i64.to_le_bytes());
+
+        let list = AccessList::unpack(&data).unwrap();
+        assert_eq!(
+            list.entries,
+            vec![AccessEntry {
+                expires_at: Some(500),
+                ..AccessEntry::new(key)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_host_scope_limits() {
+        let scoped = |hosts| AccessEntry {
+            hosts,
+            ..AccessEntry::new(Pubkey::new_unique())
+        };
+        let invalid = Err(SolanaPamError::InvalidHostScope.into());
+        assert_eq!(scoped(HostScope::Hosts(vec![])).validate(), invalid);
+        assert_eq!(
+            scoped(HostScope::Hosts(vec![
+                Pubkey::new_unique();
+                MAX_SCOPE_HOSTS + 1
+            ]))
+            .validate(),
+            invalid
+        );
+        assert_eq!(scoped(HostScope::Tag(String::new())).validate(), invalid);
+        assert_eq!(
+            scoped(HostScope::Tag("x".repeat(MAX_HOST_NAME_LEN + 1))).validate(),
+            invalid
+        );
+        assert_eq!(
+            scoped(HostScope::Tag("prod".to_string())).validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_host_account_round_trip() {
+        let tags = vec!["x".repeat(MAX_HOST_NAME_LEN); MAX_HOST_TAGS];
+        let host =
+            HostAccount::new(Pubkey::new_unique(), "y".repeat(MAX_HOST_NAME_LEN), tags).unwrap();
+        let mut data = vec![0xff; HOST_ACCOUNT_SIZE];
+        host.write(&mut data).unwrap();
+        assert_eq!(HostAccount::unpack(&data), Ok(host.clone()));
+        assert!(host.has_tag(&"x".repeat(MAX_HOST_NAME_LEN)));
+        assert!(!host.has_tag("prod"));
+
+        assert_eq!(
+            HostAccount::unpack(&[0; HOST_ACCOUNT_SIZE]),
+            Err(SolanaPamError::MalformedHostAccount.into())
+        );
+        assert_eq!(
+            HostAccount::new(Pubkey::new_unique(), String::new(), vec![]),
+            Err(SolanaPamError::InvalidHostName.into())
+        );
+        assert_eq!(
+            HostAccount::new(
+                Pubkey::new_unique(),
+                "web-1".to_string(),
+                vec!["prod".to_string(); MAX_HOST_TAGS + 1]
+            ),
+            Err(SolanaPamError::InvalidHostName.into())
+        );
+    }
+
+    #[test]
+    fn test_username_account_round_trip() {
+        let longest = "u".repeat(MAX_USERNAME_LEN);
+        let account = UsernameAccount::new(Pubkey::new_unique(), longest).unwrap();
+        let mut data = vec![0xff; USERNAME_ACCOUNT_SIZE];
+        account.write(&mut data).unwrap();
+        assert_eq!(UsernameAccount::unpack(&data), Ok(account));
+        assert_eq!(
+            UsernameAccount::unpack(&[0; USERNAME_ACCOUNT_SIZE]),
+            Err(SolanaPamError::MalformedUsernameAccount.into())
+        );
+
+        for name in &["alice", "_svc", "build-01", "a"] {
+            assert!(is_valid_username(name), "{}", name);
+        }
+        let too_long = "u".repeat(MAX_USERNAME_LEN + 1);
+        for name in &[
+            "", "Alice", "1user", "-x", "al ice", "root:x", "ålice", &too_long,
+        ] {
+            assert!(!is_valid_username(name), "{}", name);
+            assert_eq!(
+                UsernameAccount::new(Pubkey::new_unique(), name.to_string()),
+                Err(SolanaPamError::InvalidUsername.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_account_policy() {
+        let admin = Pubkey::new_unique();
+        let mut policy = AccountPolicy::new(admin, "u".repeat(MAX_USERNAME_LEN)).unwrap();
+        for _ in 0..ACCOUNT_POLICY_CAPACITY {
+            policy.add(AccessEntry {
+                not_before: Some(i64::MIN),
+                expires_at: Some(i64::MAX),
+                hosts: HostScope::Hosts(vec![Pubkey::new_unique(); MAX_SCOPE_HOSTS]),
+                ..AccessEntry::new(Pubkey::new_unique())
+            });
+        }
+        let mut data = vec![0xff; ACCOUNT_POLICY_SIZE];
+        policy.write(&mut data).unwrap();
+        assert_eq!(AccountPolicy::unpack(&data), Ok(policy.clone()));
+
+        let first = policy.entries[0].pubkey;
+        policy.add(AccessEntry::new(first));
+        assert_eq!(policy.entries.len(), ACCOUNT_POLICY_CAPACITY);
+        assert_eq!(policy.get(&first), Some(&AccessEntry::new(first)));
+        policy.remove(&first);
+        assert_eq!(policy.get(&first), None);
+        policy.remove_expired(i64::MAX);
+        assert!(policy.entries.is_empty());
+
+        assert_eq!(
+            AccountPolicy::unpack(&[0; ACCOUNT_POLICY_SIZE]),
+            Err(SolanaPamError::MalformedAccountPolicy.into())
+        );
+        assert_eq!(
+            AccountPolicy::new(admin, "Deploy".to_string()),
+            Err(SolanaPamError::InvalidUsername.into())
+        );
+    }
+
+    #[test]
+    fn test_multisig_rules() {
+        let admins: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let invalid = Err(SolanaPamError::InvalidMultisig.into());
+        let list = Pubkey::new_unique();
+        assert_eq!(Multisig::new(list, admins.clone(), 0), invalid);
+        assert_eq!(Multisig::new(list, admins.clone(), 4), invalid);
+        assert_eq!(Multisig::new(list, vec![], 0), invalid);
+        assert_eq!(Multisig::new(list, vec![admins[0], admins[0]], 1), invalid);
+        let too_many: Vec<Pubkey> = (0..=MAX_MULTISIG_ADMINS)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        assert_eq!(Multisig::new(list, too_many, 1), invalid);
+
+        let most: Vec<Pubkey> = (0..MAX_MULTISIG_ADMINS)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        let mut multisig = Multisig::new(list, most, MAX_MULTISIG_ADMINS as u8).unwrap();
+        let mut data = vec![0xff; MULTISIG_SIZE];
+        multisig.write(&mut data).unwrap();
+        assert_eq!(Multisig::unpack(&data), Ok(multisig.clone()));
+        assert_eq!(multisig.admin_index(&multisig.admins[5]), Some(5));
+        assert_eq!(multisig.admin_index(&admins[0]), None);
+
+        assert_eq!(
+            multisig.set_admins(admins.clone(), 4),
+            Err(SolanaPamError::InvalidMultisig.into())
+        );
+        assert_eq!(multisig.generation, 0);
+        multisig.set_admins(admins.clone(), 3).unwrap();
+        assert_eq!(multisig.generation, 1);
+        assert_eq!(multisig.admins, admins);
+    }
+
+    #[test]
+    fn test_proposal_approvals() {
+        // The largest action
+        let action = ProposalAction::SetMultisig {
+            admins: (0..MAX_MULTISIG_ADMINS)
+                .map(|_| Pubkey::new_unique())
+                .collect(),
+            threshold: 1,
+        };
+        assert_eq!(action.validate(), Ok(()));
+        let mut proposal = Proposal::new(Pubkey::new_unique(), u64::MAX, 7, action);
+        proposal.approve(0);
+        proposal.approve(0);
+        proposal.approve(MAX_MULTISIG_ADMINS - 1);
+        assert_eq!(proposal.approval_count(), 2);
+        assert!(proposal.is_approved_by(MAX_MULTISIG_ADMINS - 1));
+        assert!(!proposal.is_approved_by(1));
+        let mut data = vec![0xff; PROPOSAL_SIZE];
+        proposal.write(&mut data).unwrap();
+        assert_eq!(Proposal::unpack(&data), Ok(proposal));
+        assert_eq!(
+            Proposal::unpack(&[0; PROPOSAL_SIZE]),
+            Err(SolanaPamError::MalformedProposal.into())
+        );
+    }
+
+    #[test]
+    fn test_unpack_version_3() {
+        let owner = Pubkey::new_unique();
+        let entry = AccessEntry {
+            hosts: HostScope::Tag("prod".to_string()),
+            ..AccessEntry::new(Pubkey::new_unique())
+        };
+        let mut data = vec![0; ACCESS_LIST_SIZE];
+        data[..8].copy_from_slice(&ACCESS_LIST_DISCRIMINATOR);
+        data[8] = 3;
+        data[9..41].copy_from_slice(owner.as_ref());
+        data[41..45].copy_from_slice(&1u32.to_le_bytes());
+        // Entries gained the deny flag in version 5, the role in version 6
+        // and the kind in version 7, their last three bytes
+        let packed = entry.try_to_vec().unwrap();
+        let packed = &packed[..packed.len() - 3];
+        data[45..45 + packed.len()].copy_from_slice(packed);
+
+        let list = AccessList::unpack(&data).unwrap();
+        assert_eq!(list.owner, owner);
+        assert_eq!(list.multisig, None);
+        assert_eq!(list.entries, vec![entry]);
+    }
+
+    #[test]
+    fn test_delegate_rights() {
+        let (list, key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let invalid = Err(SolanaPamError::InvalidDelegateRights.into());
+        assert_eq!(Delegate::new(list, key, 0), invalid);
+        assert_eq!(Delegate::new(list, key, DELEGATE_ALL_RIGHTS + 1), invalid);
+
+        let mut delegate = Delegate::new(list, key, DELEGATE_ADD).unwrap();
+        let missing = Err(SolanaPamError::MissingDelegateRight.into());
+        assert_eq!(delegate.check_rights(DELEGATE_ADD), Ok(()));
+        assert_eq!(delegate.check_rights(DELEGATE_REMOVE), missing);
+        assert_eq!(
+            delegate.check_rights(DELEGATE_ADD | DELEGATE_REMOVE),
+            missing
+        );
+        delegate.set_rights(DELEGATE_ALL_RIGHTS).unwrap();
+        assert_eq!(
+            delegate.check_rights(DELEGATE_REMOVE | DELEGATE_MANAGE_EXPIRY),
+            Ok(())
+        );
+
+        let mut data = vec![0xff; DELEGATE_SIZE];
+        delegate.write(&mut data).unwrap();
+        assert_eq!(Delegate::unpack(&data), Ok(delegate));
+        assert_eq!(
+            Delegate::unpack(&[0; DELEGATE_SIZE]),
+            Err(SolanaPamError::MalformedDelegate.into())
+        );
+    }
+
+    #[test]
+    fn test_bulk_changes() {
+        let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let bounded = AccessEntry {
+            expires_at: Some(100),
+            ..AccessEntry::new(keys[0])
+        };
+        let mut list = AccessList::new(Pubkey::new_unique());
+        list.add(bounded.clone());
+
+        // Repeats in the input and keys already listed are added once
+        list.add_many(&[keys[0], keys[1], keys[1], keys[2]]);
+        assert_eq!(
+            list.entries,
+            vec![
+                bounded.clone(),
+                AccessEntry::new(keys[1]),
+                AccessEntry::new(keys[2])
+            ]
+        );
+
+        list.remove_many(&[keys[1], keys[3]]);
+        assert_eq!(
+            list.entries,
+            vec![bounded.clone(), AccessEntry::new(keys[2])]
+        );
+
+        list.replace(&[keys[3], keys[0], keys[3]]);
+        assert_eq!(list.entries, vec![AccessEntry::new(keys[3]), bounded]);
+        list.replace(&[]);
+        assert!(list.entries.is_empty());
+
+        assert_eq!(validate_bulk_keys(&vec![keys[0]; MAX_BULK_KEYS]), Ok(()));
+        assert_eq!(
+            validate_bulk_keys(&vec![keys[0]; MAX_BULK_KEYS + 1]),
+            Err(SolanaPamError::TooManyKeys.into())
+        );
+    }
+
+    #[test]
+    fn test_bulk_instruction_fits_in_transaction() {
+        // The tightest bulk transaction: fee payer and delegate signatures,
+        // five accounts and the program id, a blockhash and the instruction
+        let signatures = 1 + 2 * 64;
+        let message = 3 + 1 + 6 * 32 + 32;
+        let instruction = 1 + 1 + 1 + 5 + 2 + (2 + 4 + MAX_BULK_KEYS * 32);
+        assert!(signatures + message + instruction <= 1232);
+    }
+
+    #[test]
+    fn test_closed_access_list() {
+        let owner = Pubkey::new_unique();
+        let mut list = AccessList::new(owner);
+        list.add(AccessEntry::new(Pubkey::new_unique()));
+        let mut data = vec![0; ACCESS_LIST_SIZE];
+        list.write(&mut data).unwrap();
+
+        AccessList::close(&mut data).unwrap();
+        assert!(AccessList::is_closed(&data));
+        assert!(data[8..].iter().all(|b| *b == 0));
+        let closed = Err(SolanaPamError::AccessListClosed.into());
+        assert_eq!(AccessList::unpack(&data), closed);
+        // Not an empty headerless list either
+        assert_eq!(AccessList::migrate(&data, owner), closed);
+    }
+
+    #[test]
+    fn test_revocation_round_trip() {
+        let revocation = Revocation::new(Pubkey::new_unique(), Pubkey::new_unique(), 1_000);
+        let mut data = vec![0; REVOCATION_SIZE];
+        revocation.write(&mut data).unwrap();
+        assert_eq!(revocation.pack().len(), REVOCATION_SIZE);
+        assert_eq!(Revocation::unpack(&data), Ok(revocation));
+        assert_eq!(
+            Revocation::unpack(&data[..8]),
+            Err(SolanaPamError::MalformedRevocation.into())
+        );
+    }
+
+    /// Check `entry`, less its last `dropped` bytes, is read back from an
+    /// access list, account policy and proposal of the given layout versions
+    fn check_old_entry_layout(entry: AccessEntry, dropped: usize, versions: (u8, u8, u8)) {
+        let packed = entry.try_to_vec().unwrap();
+        let old_entry = &packed[..packed.len() - dropped];
+        let (owner, multisig) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut data = ACCESS_LIST_DISCRIMINATOR.to_vec();
+        data.push(versions.0);
+        data.extend_from_slice(owner.as_ref());
+        data.push(1);
+        data.extend_from_slice(multisig.as_ref());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(old_entry);
+        data.resize(ACCESS_LIST_SIZE, 0);
+        let list = AccessList::unpack(&data).unwrap();
+        assert_eq!(list.multisig, Some(multisig));
+        assert_eq!(list.entries, vec![entry.clone()]);
+
+        let mut data = ACCOUNT_POLICY_DISCRIMINATOR.to_vec();
+        data.push(versions.1);
+        data.extend_from_slice(owner.as_ref());
+        data.extend_from_slice(&"deploy".to_string().try_to_vec().unwrap());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(old_entry);
+        data.resize(ACCOUNT_POLICY_SIZE, 0);
+        let policy = AccountPolicy::unpack(&data).unwrap();
+        assert_eq!(policy.account, "deploy");
+        assert_eq!(policy.entries, vec![entry.clone()]);
+        let mut rewritten = vec![0; ACCOUNT_POLICY_SIZE];
+        policy.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten[8], ACCOUNT_POLICY_VERSION);
+
+        let mut data = PROPOSAL_DISCRIMINATOR.to_vec();
+        data.push(versions.2);
+        data.extend_from_slice(multisig.as_ref());
+        data.extend_from_slice(&7u64.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&0b11u16.to_le_bytes());
+        data.push(0);
+        data.push(0);
+        data.extend_from_slice(old_entry);
+        data.resize(PROPOSAL_SIZE, 0);
+        let proposal = Proposal::unpack(&data).unwrap();
+        assert_eq!(
+            (proposal.index, proposal.generation, proposal.approvals),
+            (7, 2, 0b11)
+        );
+        assert_eq!(proposal.action, ProposalAction::AddEntry(entry));
+    }
+
+    #[test]
+    fn test_unpack_before_deny_entries() {
+        let entry = AccessEntry {
+            hosts: HostScope::Service("sshd".to_string()),
+            ..AccessEntry::new(Pubkey::new_unique())
+        };
+        check_old_entry_layout(entry, 3, (4, 1, 1));
+    }
+
+    #[test]
+    fn test_unpack_before_roles() {
+        let entry = AccessEntry {
+            expires_at: Some(2_000),
+            deny: true,
+            ..AccessEntry::new(Pubkey::new_unique())
+        };
+        check_old_entry_layout(entry, 2, (5, 2, 2));
+    }
+
+    #[test]
+    fn test_unpack_before_groups() {
+        let entry = AccessEntry {
+            role: Role::Sudo,
+            ..AccessEntry::new(Pubkey::new_unique())
+        };
+        check_old_entry_layout(entry, 1, (6, 3, 3));
+    }
+
+    #[test]
+    fn test_deny_wins() {
+        let (key, web) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let tags = vec!["prod".to_string()];
+        let login = Login {
+            pubkey: key,
+            now: 1_000,
+            host: Some(web),
+            host_tags: &tags,
+            service: Some("sshd"),
+            ..Login::default()
+        };
+        let allow = AccessEntry::new(key);
+        let deny = |hosts| AccessEntry {
+            hosts,
+            deny: true,
+            ..AccessEntry::new(key)
+        };
+
+        assert_eq!(decide(&[], &login), Decision::Unlisted);
+        assert_eq!(decide(Some(&allow), &login), Decision::Allow(Role::Login));
+        // A deny from any list beats an allow from another, in either order
+        for hosts in &[
+            HostScope::Any,
+            HostScope::Hosts(vec![web]),
+            HostScope::Tag("prod".to_string()),
+            HostScope::Service("sshd".to_string()),
+        ] {
+            let lists = [vec![allow.clone()], vec![deny(hosts.clone())]];
+            assert_eq!(decide(lists.iter().flatten(), &login), Decision::Deny);
+            assert_eq!(decide(lists.iter().rev().flatten(), &login), Decision::Deny);
+        }
+
+        // Denies that do not cover the login leave the allow standing
+        let other = Pubkey::new_unique();
+        for entry in &[
+            deny(HostScope::Hosts(vec![Pubkey::new_unique()])),
+            deny(HostScope::Tag("staging".to_string())),
+            deny(HostScope::Service("sudo".to_string())),
+            AccessEntry {
+                expires_at: Some(500),
+                ..deny(HostScope::Any)
+            },
+            AccessEntry {
+                deny: true,
+                ..AccessEntry::new(other)
+            },
+        ] {
+            assert_eq!(
+                decide(&[entry.clone(), allow.clone()], &login),
+                Decision::Allow(Role::Login)
+            );
+            assert_eq!(decide(Some(entry), &login), Decision::Unlisted);
+        }
+
+        // Within the skew, a deny about to start already applies
+        let pending = AccessEntry {
+            not_before: Some(1_030),
+            ..deny(HostScope::Any)
+        };
+        assert_eq!(
+            decide(&[pending.clone(), allow.clone()], &login),
+            Decision::Allow(Role::Login)
+        );
+        let lenient = Login { skew: 60, ..login };
+        assert_eq!(decide(&[pending, allow.clone()], &lenient), Decision::Deny);
+
+        // One list holds a key's allow next to a deny scoped to some hosts,
+        // and adding either again replaces only its own kind
+        let mut list = AccessList::new(Pubkey::new_unique());
+        list.add(allow.clone());
+        list.add(deny(HostScope::Hosts(vec![web])));
+        assert_eq!(
+            list.entries,
+            vec![allow.clone(), deny(HostScope::Hosts(vec![web]))]
+        );
+        assert_eq!(decide(&list.entries, &login), Decision::Deny);
+        list.add(deny(HostScope::Service("sudo".to_string())));
+        assert_eq!(
+            list.entries,
+            vec![allow.clone(), deny(HostScope::Service("sudo".to_string()))]
+        );
+        assert_eq!(decide(&list.entries, &login), Decision::Allow(Role::Login));
+        list.replace(&[other, key]);
+        assert_eq!(list.entries.len(), 3);
+        list.remove(&key);
+        assert_eq!(list.entries, vec![AccessEntry::new(other)]);
+
+        // A deny in one list beats the allow a policy gives the same key
+        let mut policy = AccountPolicy::new(Pubkey::new_unique(), "deploy".to_string()).unwrap();
+        policy.add(allow.clone());
+        let mut own = AccessList::new(key);
+        own.add(deny(HostScope::Any));
+        assert_eq!(
+            decide(&policy.entries, &login),
+            Decision::Allow(Role::Login)
+        );
+        assert_eq!(
+            decide(policy.entries.iter().chain(&own.entries), &login),
+            Decision::Deny
+        );
+    }
+
+    #[test]
+    fn test_roles() {
+        let (key, admin) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let login = Login {
+            pubkey: key,
+            now: 1_000,
+            service: Some("sudo"),
+            ..Login::default()
+        };
+        let with_role = |role, hosts| AccessEntry {
+            hosts,
+            role,
+            ..AccessEntry::new(key)
+        };
+        let sudo = with_role(Role::Sudo, HostScope::Service("sudo".to_string()));
+        let login_only = with_role(Role::Login, HostScope::Any);
+
+        // The highest role that applies wins, wherever it came from
+        for entries in &[
+            [sudo.clone(), login_only.clone()],
+            [login_only.clone(), sudo.clone()],
+        ] {
+            assert_eq!(decide(entries, &login), Decision::Allow(Role::Sudo));
+        }
+        let sshd = Login {
+            service: Some("sshd"),
+            ..login
+        };
+        assert_eq!(
+            decide(&[sudo, login_only], &sshd),
+            Decision::Allow(Role::Login)
+        );
+
+        let mut list = AccessList::new(Pubkey::new_unique());
+        list.add(AccessEntry::new(admin));
+        assert!(!list.is_admin(&admin, 1_000));
+        list.set_role(&admin, Role::Admin).unwrap();
+        assert!(list.is_admin(&admin, 1_000));
+        assert_eq!(
+            list.set_role(&key, Role::Sudo),
+            Err(SolanaPamError::EntryNotFound.into())
+        );
+        // Expired or deny entries give no admin rights
+        list.add(AccessEntry {
+            expires_at: Some(500),
+            role: Role::Admin,
+            ..AccessEntry::new(admin)
+        });
+        assert!(!list.is_admin(&admin, 1_000));
+        list.add(AccessEntry {
+            deny: true,
+            role: Role::Admin,
+            ..AccessEntry::new(admin)
+        });
+        assert!(!list.is_admin(&admin, 1_000));
+        // Nor does an admin entry while a deny for the key is in force
+        list.add(AccessEntry {
+            role: Role::Admin,
+            ..AccessEntry::new(admin)
+        });
+        assert!(!list.is_admin(&admin, 1_000));
+        list.set_role(&admin, Role::Login).unwrap();
+        assert_eq!(list.find(&admin, false).unwrap().role, Role::Login);
+        assert_eq!(list.find(&admin, true).unwrap().role, Role::Admin);
+    }
+
+    #[test]
+    fn test_group_round_trip() {
+        let admin = Pubkey::new_unique();
+        assert_eq!(
+            Group::new(String::new(), vec![admin]),
+            Err(SolanaPamError::InvalidGroup.into())
+        );
+        assert_eq!(
+            Group::new("ops".to_string(), vec![]),
+            Err(SolanaPamError::InvalidGroup.into())
+        );
+        let mut group = Group::new("ops".to_string(), vec![admin]).unwrap();
+        assert_eq!(
+            group.set_admins(vec![admin, admin]),
+            Err(SolanaPamError::InvalidGroup.into())
+        );
+        let key = GroupMember::Key(Pubkey::new_unique());
+        group.add(key);
+        group.add(key);
+        group.add(GroupMember::Group(Pubkey::new_unique()));
+        assert_eq!(group.members.len(), 2);
+        group.remove(&key);
+        assert_eq!(group.members.len(), 1);
+
+        let mut data = vec![0xff; GROUP_SIZE];
+        group.write(&mut data).unwrap();
+        assert_eq!(Group::unpack(&data), Ok(group.clone()));
+        assert_eq!(
+            Group::unpack(&[0; GROUP_SIZE]),
+            Err(SolanaPamError::MalformedGroup.into())
+        );
+
+        // The capacity holds with the longest name and the most admins
+        let admins = (0..MAX_GROUP_ADMINS)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+        let mut group = Group::new("g".repeat(MAX_GROUP_NAME_LEN), admins).unwrap();
+        for _ in 0..GROUP_CAPACITY {
+            group.add(GroupMember::Key(Pubkey::new_unique()));
+        }
+        assert_eq!(group.write(&mut data), Ok(()));
+        group.add(GroupMember::Key(Pubkey::new_unique()));
+        assert_eq!(
+            group.write(&mut data),
+            Err(SolanaPamError::GroupFull.into())
+        );
+    }
+
+    /// Groups keyed by address, each with the given members
+    fn group_set(groups: &[(Pubkey, Vec<GroupMember>)]) -> Vec<(Pubkey, Group)> {
+        groups
+            .iter()
+            .map(|(address, members)| {
+                let mut group = Group::new("g".to_string(), vec![*address]).unwrap();
+                group.members = members.clone();
+                (*address, group)
+            })
+            .collect()
+    }
+
+    fn expand(
+        set: &[(Pubkey, Group)],
+        groups: &[Pubkey],
+    ) -> Result<Vec<(Pubkey, Vec<Pubkey>)>, GroupError<()>> {
+        expand_groups(groups, |address| {
+            Ok(set
+                .iter()
+                .find(|(a, _)| a == address)
+                .map(|(_, g)| g.clone()))
+        })
+    }
+
+    #[test]
+    fn test_expand_groups() {
+        let g: Vec<Pubkey> = (0..6).map(|_| Pubkey::new_unique()).collect();
+        let (alice, bob, carol) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let (key, group) = (GroupMember::Key, GroupMember::Group);
+        // g0 holds alice and g1, g1 holds bob and g2, g2 holds carol and a
+        // group that does not exist
+        let set = group_set(&[
+            (g[0], vec![key(alice), group(g[1])]),
+            (g[1], vec![key(bob), group(g[2])]),
+            (g[2], vec![key(carol), key(bob), group(g[5])]),
+        ]);
+        assert_eq!(
+            expand(&set, &[g[2], g[0]]).unwrap(),
+            vec![(g[2], vec![carol, bob]), (g[0], vec![alice, bob, carol])]
+        );
+        assert_eq!(expand(&set, &[g[4]]).unwrap(), vec![(g[4], vec![])]);
+
+        // A cycle fails wherever the walk starts
+        let cyclic = group_set(&[
+            (g[0], vec![key(alice), group(g[1])]),
+            (g[1], vec![group(g[2])]),
+            (g[2], vec![group(g[0])]),
+        ]);
+        for start in &g[..3] {
+            assert!(matches!(
+                expand(&cyclic, &[*start]),
+                Err(GroupError::Cycle(_))
+            ));
+        }
+        let selfish = group_set(&[(g[0], vec![group(g[0])])]);
+        assert_eq!(expand(&selfish, &[g[0]]), Err(GroupError::Cycle(g[0])));
+
+        // g0 to g3 are as deep as groups go; g4 above them is one too many,
+        // however g0 to g3 were expanded before
+        let deep = group_set(&[
+            (g[4], vec![group(g[0])]),
+            (g[0], vec![group(g[1])]),
+            (g[1], vec![group(g[2])]),
+            (g[2], vec![group(g[3])]),
+            (g[3], vec![key(alice)]),
+        ]);
+        assert_eq!(expand(&deep, &[g[0]]).unwrap(), vec![(g[0], vec![alice])]);
+        assert!(matches!(
+            expand(&deep, &[g[4]]),
+            Err(GroupError::TooDeep(_))
+        ));
+        assert!(matches!(
+            expand(&deep, &[g[0], g[4]]),
+            Err(GroupError::TooDeep(_))
+        ));
+
+        assert_eq!(
+            expand_groups(&[g[0]], |_| Err("unreachable")),
+            Err(GroupError::Fetch("unreachable"))
+        );
+    }
+
+    #[test]
+    fn test_group_entries() {
+        let (key, ops) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let groups = [ops];
+        let member = Login {
+            pubkey: key,
+            groups: &groups,
+            ..Login::default()
+        };
+        let outsider = Login {
+            pubkey: key,
+            ..Login::default()
+        };
+        let entry = AccessEntry::group(ops);
+        assert_eq!(decide(Some(&entry), &member), Decision::Allow(Role::Login));
+        assert_eq!(decide(Some(&entry), &outsider), Decision::Unlisted);
+        // A group never matches as a key, nor a key as a group
+        assert_eq!(
+            decide(Some(&AccessEntry::new(ops)), &member),
+            Decision::Unlisted
+        );
+        let denied = AccessEntry {
+            deny: true,
+            ..AccessEntry::group(ops)
+        };
+        assert_eq!(
+            decide(&[AccessEntry::new(key), denied], &member),
+            Decision::Deny
+        );
+
+        // Group entries give no rights over the list
+        let mut list = AccessList::new(Pubkey::new_unique());
+        list.add(AccessEntry {
+            role: Role::Admin,
+            ..AccessEntry::group(ops)
+        });
+        assert!(!list.is_admin(&ops, 0));
+    }
+
+    #[test]
+    fn test_token_entries() {
+        let (key, badge, other) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let holding = |tokens| Login {
+            pubkey: key,
+            tokens,
+            ..Login::default()
+        };
+        let (enough, short) = ([(badge, 3)], [(badge, 2), (other, 10)]);
+        let entry = AccessEntry::token(badge, 3);
+        assert_eq!(
+            decide(Some(&entry), &holding(&enough)),
+            Decision::Allow(Role::Login)
+        );
+        assert_eq!(decide(Some(&entry), &holding(&short)), Decision::Unlisted);
+        assert_eq!(decide(Some(&entry), &holding(&[])), Decision::Unlisted);
+        // The mint never matches as a key
+        assert_eq!(
+            decide(
+                Some(&entry),
+                &Login {
+                    pubkey: badge,
+                    ..Login::default()
+                }
+            ),
+            Decision::Unlisted
+        );
+
+        assert_eq!(entry.validate(), Ok(()));
+        assert_eq!(
+            AccessEntry::token(badge, 0).validate(),
+            Err(SolanaPamError::InvalidTokenGate.into())
+        );
+        // The kind is appended to the entry, so existing entries keep their bytes
+        let encoded = entry.try_to_vec().unwrap();
+        assert_eq!(encoded[encoded.len() - 9..], [2, 3, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_login_log() {
+        let host = Pubkey::new_unique();
+        let record = |timestamp| {
+            LoginRecord::new(
+                Pubkey::new_unique(),
+                "s".repeat(MAX_SERVICE_NAME_LEN),
+                [0xab; 32],
+                timestamp,
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            LoginRecord::new(host, "s".repeat(MAX_SERVICE_NAME_LEN + 1), [0; 32], 0),
+            Err(SolanaPamError::InvalidLoginRecord.into())
+        );
+
+        let mut log = LoginLog::new(host);
+        let mut data = vec![0xff; LOGIN_LOG_SIZE];
+        for timestamp in 0..3 {
+            log.record(record(timestamp));
+        }
+        log.write(&mut data).unwrap();
+        assert_eq!(LoginLog::unpack(&data), Ok(log.clone()));
+        let times = |log: &LoginLog| log.records().map(|r| r.timestamp).collect::<Vec<_>>();
+        assert_eq!(times(&log), vec![0, 1, 2]);
+
+        // A full log fits its account and wraps around, oldest first
+        let capacity = LOGIN_LOG_CAPACITY as i64;
+        for timestamp in 3..capacity + 2 {
+            log.record(record(timestamp));
+        }
+        assert_eq!(log.write(&mut data), Ok(()));
+        assert_eq!(log.total, capacity as u64 + 2);
+        assert_eq!(times(&log), (2..capacity + 2).collect::<Vec<_>>());
+        assert_eq!(LoginLog::unpack(&data), Ok(log));
+
+        assert_eq!(
+            LoginLog::unpack(&[0; LOGIN_LOG_SIZE]),
+            Err(SolanaPamError::MalformedLoginLog.into())
+        );
+    }
+}
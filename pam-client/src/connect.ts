@@ -26,11 +26,14 @@ export const configs = {
 };
 
 export async function initAccount(account: Keypair) {
+  const space = 1024 * 100;
+  // The program refuses state that could be purged for rent
+  const lamports = await connection.getMinimumBalanceForRentExemption(space);
   const tx = SystemProgram.createAccount({
     fromPubkey: userAccount.publicKey,
     newAccountPubkey: account.publicKey,
-    lamports: 10000000,
-    space: 1024 * 100,
+    lamports,
+    space,
     programId: progId,
   });
   try {
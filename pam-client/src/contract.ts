@@ -1,6 +1,5 @@
 import {
   Connection,
-  Keypair,
   PublicKey,
   sendAndConfirmTransaction,
   Signer,
@@ -9,14 +8,312 @@ import {
   TransactionInstruction,
 } from "@solana/web3.js";
 
+// Must match INSTRUCTION_VERSION in solana-pam-shared
+const INSTRUCTION_VERSION = 6;
+
+// Variant order of ProgInstruction, which is its Borsh tag
 enum Instruction {
   init = 0,
   initAccessList = 1,
   addToAccessList = 2,
-  removeToAccessList = 2,
+  removeToAccessList = 3,
+  migrateAccessList = 4,
+  setAuthority = 5,
+  registerHost = 6,
+  claimUsername = 7,
+  releaseUsername = 8,
+  initAccountPolicy = 9,
+  addToAccountPolicy = 10,
+  removeFromAccountPolicy = 11,
+  createMultisig = 12,
+  propose = 13,
+  approveProposal = 14,
+  executeProposal = 15,
+  setDelegate = 16,
+  revokeDelegate = 17,
+  addManyToAccessList = 18,
+  removeManyFromAccessList = 19,
+  replaceAccessList = 20,
+  closeAccessList = 21,
+  revokeKey = 22,
+  setEntryRole = 23,
+  createGroup = 24,
+  addGroupMember = 25,
+  removeGroupMember = 26,
+  setGroupAdmins = 27,
+  recordLogin = 28,
+  recordSession = 29,
+}
+
+// Most keys one bulk instruction carries, MAX_BULK_KEYS in the program
+export const MAX_BULK_KEYS = 24;
+
+// Rights a delegate can hold over an access list, DELEGATE_* in the program
+export const DelegateRights = {
+  add: 1,
+  remove: 2,
+  manageExpiry: 4,
+  manageRoles: 8,
+};
+
+// What a granted key may do, in the variant order of Role in the program
+export enum Role {
+  login = 0,
+  sudo = 1,
+  admin = 2,
 }
+
+// Same bytes as ProgInstruction::pack: version, tag, then the fields
 const instrData = (instr: Instruction, data: number[] = []): Buffer => {
-  return Buffer.from([instr, ...data]);
+  return Buffer.from([INSTRUCTION_VERSION, instr, ...data]);
+};
+
+// Borsh Option<i64>: a 0 tag, or a 1 tag then the value little endian.
+// Unix timestamps are well inside the range a number holds exactly.
+const optionI64 = (value?: number): number[] => {
+  if (value === undefined) {
+    return [0];
+  }
+  const bytes = Buffer.alloc(8);
+  bytes.writeUInt32LE(value % 2 ** 32 >>> 0, 0);
+  bytes.writeInt32LE(Math.floor(value / 2 ** 32), 4);
+  return [1, ...bytes];
+};
+
+// Borsh u32 length prefix
+const u32 = (value: number): number[] => {
+  const bytes = Buffer.alloc(4);
+  bytes.writeUInt32LE(value, 0);
+  return [...bytes];
+};
+
+// Borsh String: the utf-8 length, then the bytes
+const borshString = (value: string): number[] => {
+  const bytes = Buffer.from(value, "utf8");
+  return [...u32(bytes.length), ...bytes];
+};
+
+// The machines an entry applies to, as HostScope: every host, the listed
+// host accounts, hosts whose account carries a tag, or one PAM service
+export type HostScope =
+  | { any: true }
+  | { hosts: PublicKey[] }
+  | { tag: string }
+  | { service: string };
+
+const hostScope = (scope: HostScope = { any: true }): number[] => {
+  if ("hosts" in scope) {
+    return [
+      1,
+      ...u32(scope.hosts.length),
+      ...scope.hosts.flatMap((host) => [...host.toBytes()]),
+    ];
+  }
+  if ("tag" in scope) {
+    return [2, ...borshString(scope.tag)];
+  }
+  if ("service" in scope) {
+    return [3, ...borshString(scope.service)];
+  }
+  return [0];
+};
+
+// Borsh u64 little endian, for proposal indexes and token amounts
+const u64 = (value: number): number[] => {
+  const bytes = Buffer.alloc(8);
+  bytes.writeUInt32LE(value % 2 ** 32 >>> 0, 0);
+  bytes.writeUInt32LE(Math.floor(value / 2 ** 32), 4);
+  return [...bytes];
+};
+
+// What an entry's pubkey names, as EntryKind: the key itself, a group
+// account, or a token mint whose holders of at least minAmount are granted
+export type EntryKind = "key" | "group" | { minAmount: number };
+
+const entryKind = (kind: EntryKind = "key"): number[] => {
+  if (kind === "key") {
+    return [0];
+  }
+  if (kind === "group") {
+    return [1];
+  }
+  return [2, ...u64(kind.minAmount)];
+};
+
+const pubkeys = (keys: PublicKey[]): number[] => [
+  ...u32(keys.length),
+  ...keys.flatMap((key) => [...key.toBytes()]),
+];
+
+// A member of a group, as GroupMember: a key or a nested group account
+export type GroupMember = { key: PublicKey } | { group: PublicKey };
+
+const groupMember = (member: GroupMember): number[] => {
+  if ("group" in member) {
+    return [1, ...member.group.toBytes()];
+  }
+  return [0, ...member.key.toBytes()];
+};
+
+// A change to a multisig governed list, as ProposalAction
+export type ProposalAction =
+  | {
+      add: PublicKey;
+      notBefore?: number;
+      expiresAt?: number;
+      hosts?: HostScope;
+      deny?: boolean;
+      role?: Role;
+      kind?: EntryKind;
+    }
+  | { remove: PublicKey }
+  | { admins: PublicKey[]; threshold: number };
+
+const proposalAction = (action: ProposalAction): number[] => {
+  if ("add" in action) {
+    return [
+      0,
+      ...action.add.toBytes(),
+      ...optionI64(action.notBefore),
+      ...optionI64(action.expiresAt),
+      ...hostScope(action.hosts),
+      action.deny ? 1 : 0,
+      action.role ?? Role.login,
+      ...entryKind(action.kind),
+    ];
+  }
+  if ("remove" in action) {
+    return [1, ...action.remove.toBytes()];
+  }
+  return [2, ...pubkeys(action.admins), action.threshold];
+};
+
+export const findAccessListAddress = async (
+  programId: PublicKey,
+  user: PublicKey
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("access_list"), user.toBuffer()],
+    programId
+  );
+  return address;
+};
+
+export const findHostAddress = async (
+  programId: PublicKey,
+  host: PublicKey
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("host"), host.toBuffer()],
+    programId
+  );
+  return address;
+};
+
+export const findUsernameAddress = async (
+  programId: PublicKey,
+  username: string
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("username"), Buffer.from(username, "utf8")],
+    programId
+  );
+  return address;
+};
+
+export const findAccountPolicyAddress = async (
+  programId: PublicKey,
+  account: string
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("account_policy"), Buffer.from(account, "utf8")],
+    programId
+  );
+  return address;
+};
+
+export const findMultisigAddress = async (
+  programId: PublicKey,
+  accessList: PublicKey
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("multisig"), accessList.toBuffer()],
+    programId
+  );
+  return address;
+};
+
+export const findProposalAddress = async (
+  programId: PublicKey,
+  multisig: PublicKey,
+  index: number
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("proposal"), multisig.toBuffer(), Buffer.from(u64(index))],
+    programId
+  );
+  return address;
+};
+
+export const findGroupAddress = async (
+  programId: PublicKey,
+  name: string
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("group"), Buffer.from(name, "utf8")],
+    programId
+  );
+  return address;
+};
+
+export const findLoginLogAddress = async (
+  programId: PublicKey,
+  host: PublicKey
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("login_log"), host.toBuffer()],
+    programId
+  );
+  return address;
+};
+
+export const findRevocationAddress = async (
+  programId: PublicKey,
+  pubkey: PublicKey
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("revoked"), pubkey.toBuffer()],
+    programId
+  );
+  return address;
+};
+
+// The upgradeable BPF loader, which owns the program's loader data account
+const BPF_LOADER_UPGRADEABLE_ID = new PublicKey(
+  "BPFLoaderUpgradeab1e11111111111111111111111"
+);
+
+// Holds the program's upgrade authority, which Init must be signed by
+export const findLoaderDataAddress = async (
+  programId: PublicKey
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [programId.toBuffer()],
+    BPF_LOADER_UPGRADEABLE_ID
+  );
+  return address;
+};
+
+export const findDelegateAddress = async (
+  programId: PublicKey,
+  accessList: PublicKey,
+  delegate: PublicKey
+): Promise<PublicKey> => {
+  const [address] = await PublicKey.findProgramAddress(
+    [Buffer.from("delegate"), accessList.toBuffer(), delegate.toBuffer()],
+    programId
+  );
+  return address;
 };
 
 export const getContract = (
@@ -24,10 +321,85 @@ export const getContract = (
   programId: PublicKey,
   signerAccount: Signer
 ) => {
+  // The delegate and revocation accounts passed after the list by every
+  // change to it; the delegate account is only read when signer is one
+  const signerAccounts = async (signer: PublicKey, accessList: PublicKey) => [
+    {
+      pubkey: await findDelegateAddress(programId, accessList, signer),
+      isSigner: false,
+      isWritable: false,
+    },
+    {
+      pubkey: await findRevocationAddress(programId, signer),
+      isSigner: false,
+      isWritable: false,
+    },
+  ];
+
+  // One of the bulk instructions on user's own list
+  const bulkChange = async (
+    progDataAccount: PublicKey,
+    user: PublicKey,
+    instr: Instruction,
+    keys: PublicKey[]
+  ) => {
+    if (keys.length > MAX_BULK_KEYS) {
+      throw new Error(`At most ${MAX_BULK_KEYS} keys per instruction`);
+    }
+    const accessList = await findAccessListAddress(programId, user);
+    return new TransactionInstruction({
+      keys: [
+        { pubkey: progDataAccount, isSigner: false, isWritable: false },
+        { pubkey: user, isSigner: true, isWritable: false },
+        { pubkey: accessList, isSigner: false, isWritable: true },
+        ...(await signerAccounts(user, accessList)),
+      ],
+      programId,
+      data: instrData(instr, pubkeys(keys)),
+    });
+  };
+
+  // One of the member or admin changes to group name, signed by an admin
+  const groupChange = async (
+    progDataAccount: PublicKey,
+    admin: PublicKey,
+    name: string,
+    instr: Instruction,
+    data: number[]
+  ) => {
+    return new TransactionInstruction({
+      keys: [
+        { pubkey: progDataAccount, isSigner: false, isWritable: false },
+        { pubkey: admin, isSigner: true, isWritable: false },
+        {
+          pubkey: await findGroupAddress(programId, name),
+          isSigner: false,
+          isWritable: true,
+        },
+        {
+          pubkey: await findRevocationAddress(programId, admin),
+          isSigner: false,
+          isWritable: false,
+        },
+      ],
+      programId,
+      data: instrData(instr, [...borshString(name), ...data]),
+    });
+  };
+
   return {
-    initProgDataTx: (progDataAccount: PublicKey) => {
+    initProgDataTx: async (
+      progDataAccount: PublicKey,
+      authority: PublicKey
+    ) => {
       const keys = [
-        { pubkey: progDataAccount, isSigner: true, isWritable: true },
+        { pubkey: progDataAccount, isSigner: false, isWritable: true },
+        { pubkey: authority, isSigner: true, isWritable: false },
+        {
+          pubkey: await findLoaderDataAddress(programId),
+          isSigner: false,
+          isWritable: false,
+        },
       ];
       return new TransactionInstruction({
         keys,
@@ -35,11 +407,27 @@ export const getContract = (
         data: instrData(Instruction.init),
       });
     },
-    removeFromAccessList: (
+    setAuthorityTx: (
       progDataAccount: PublicKey,
-      accessListPk: PublicKey,
+      authority: PublicKey,
+      newAuthority: PublicKey
+    ) => {
+      const keys = [
+        { pubkey: progDataAccount, isSigner: false, isWritable: true },
+        { pubkey: authority, isSigner: true, isWritable: false },
+      ];
+      return new TransactionInstruction({
+        keys,
+        programId,
+        data: instrData(Instruction.setAuthority, [...newAuthority.toBytes()]),
+      });
+    },
+    removeFromAccessList: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
       remove: PublicKey
     ) => {
+      const accessList = await findAccessListAddress(programId, user);
       return new TransactionInstruction({
         keys: [
           {
@@ -48,19 +436,91 @@ export const getContract = (
             isWritable: false,
           },
           {
-            pubkey: accessListPk,
+            pubkey: user,
             isSigner: true,
-            isWritable: true,
+            isWritable: false,
           },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          ...(await signerAccounts(user, accessList)),
         ],
         programId,
         data: instrData(Instruction.removeToAccessList, [...remove.toBytes()]),
       });
     },
-    addToAccessListTx: (
+    // notBefore and expiresAt are unix timestamps in seconds bounding the
+    // grant, hosts limits it to some machines, and kind makes add a group
+    // account or token mint whose members or holders are granted instead
+    addToAccessListTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      add: PublicKey,
+      notBefore?: number,
+      expiresAt?: number,
+      hosts?: HostScope,
+      deny = false,
+      role = Role.login,
+      kind: EntryKind = "key"
+    ) => {
+      const accessList = await findAccessListAddress(programId, user);
+      return new TransactionInstruction({
+        keys: [
+          {
+            pubkey: progDataAccount,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: user,
+            isSigner: true,
+            isWritable: false,
+          },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          ...(await signerAccounts(user, accessList)),
+        ],
+        programId,
+        data: instrData(Instruction.addToAccessList, [
+          ...add.toBytes(),
+          ...optionI64(notBefore),
+          ...optionI64(expiresAt),
+          ...hostScope(hosts),
+          deny ? 1 : 0,
+          role,
+          ...entryKind(kind),
+        ]),
+      });
+    },
+    initAccessListTx: async (progDataAccount: PublicKey, user: PublicKey) => {
+      return new TransactionInstruction({
+        keys: [
+          {
+            pubkey: progDataAccount,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: user,
+            isSigner: true,
+            isWritable: true,
+          },
+          {
+            pubkey: await findAccessListAddress(programId, user),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.initAccessList),
+      });
+    },
+    migrateAccessListTx: (
       progDataAccount: PublicKey,
-      accessListPk: PublicKey,
-      add: PublicKey
+      user: PublicKey,
+      accessList: PublicKey
     ) => {
       return new TransactionInstruction({
         keys: [
@@ -70,52 +530,736 @@ export const getContract = (
             isWritable: false,
           },
           {
-            pubkey: accessListPk,
+            pubkey: user,
             isSigner: true,
+            isWritable: false,
+          },
+          {
+            pubkey: accessList,
+            isSigner: false,
             isWritable: true,
           },
         ],
         programId,
-        data: instrData(Instruction.addToAccessList, [...add.toBytes()]),
+        data: instrData(Instruction.migrateAccessList),
       });
     },
-    initAccessListTx: (
+    // Both the authority and the host key sign
+    registerHostTx: async (
       progDataAccount: PublicKey,
-      newAccessListAccount: PublicKey
+      authority: PublicKey,
+      host: PublicKey,
+      label: string,
+      tags: string[] = []
     ) => {
       return new TransactionInstruction({
         keys: [
           {
             pubkey: progDataAccount,
             isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: authority,
+            isSigner: true,
             isWritable: true,
           },
           {
-            pubkey: newAccessListAccount,
+            pubkey: host,
             isSigner: true,
+            isWritable: false,
+          },
+          {
+            pubkey: await findHostAddress(programId, host),
+            isSigner: false,
             isWritable: true,
           },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
         ],
         programId,
-        data: instrData(Instruction.initAccessList),
+        data: instrData(Instruction.registerHost, [
+          ...borshString(label),
+          ...u32(tags.length),
+          ...tags.flatMap(borshString),
+        ]),
+      });
+    },
+    claimUsernameTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      username: string
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          {
+            pubkey: progDataAccount,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: user,
+            isSigner: true,
+            isWritable: true,
+          },
+          {
+            pubkey: await findUsernameAddress(programId, username),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.claimUsername, borshString(username)),
+      });
+    },
+    releaseUsernameTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      username: string
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          {
+            pubkey: progDataAccount,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: user,
+            isSigner: true,
+            isWritable: true,
+          },
+          {
+            pubkey: await findUsernameAddress(programId, username),
+            isSigner: false,
+            isWritable: true,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.releaseUsername, borshString(username)),
       });
     },
-    // TODO: change to init access list account
-    // add functionality to add Pubkey user
-    // add functionality to remove Pubkey user
-    createAccessListAccountTx: (pks: PublicKey[]) => {
-      const pkBytes = pks.map((pk) => [...pk.toBytes()]);
-      const data = new Uint8Array(pkBytes.flat());
-      const newAccount = Keypair.generate();
-      const tx = SystemProgram.createAccount({
-        fromPubkey: signerAccount.publicKey,
-        newAccountPubkey: newAccount.publicKey,
-        lamports: 10000000,
-        space: 1024,
+    // The authority creates the policy for a unix account, then only admin
+    // changes it
+    initAccountPolicyTx: async (
+      progDataAccount: PublicKey,
+      authority: PublicKey,
+      account: string,
+      admin: PublicKey
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          {
+            pubkey: progDataAccount,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: authority,
+            isSigner: true,
+            isWritable: true,
+          },
+          {
+            pubkey: await findAccountPolicyAddress(programId, account),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
         programId,
+        data: instrData(Instruction.initAccountPolicy, [
+          ...borshString(account),
+          ...admin.toBytes(),
+        ]),
       });
-      return { newAccount, tx };
     },
+    addToAccountPolicyTx: async (
+      progDataAccount: PublicKey,
+      admin: PublicKey,
+      account: string,
+      add: PublicKey,
+      notBefore?: number,
+      expiresAt?: number,
+      hosts?: HostScope,
+      deny = false,
+      role = Role.login,
+      kind: EntryKind = "key"
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          {
+            pubkey: progDataAccount,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: admin,
+            isSigner: true,
+            isWritable: false,
+          },
+          {
+            pubkey: await findAccountPolicyAddress(programId, account),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, admin),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.addToAccountPolicy, [
+          ...borshString(account),
+          ...add.toBytes(),
+          ...optionI64(notBefore),
+          ...optionI64(expiresAt),
+          ...hostScope(hosts),
+          deny ? 1 : 0,
+          role,
+          ...entryKind(kind),
+        ]),
+      });
+    },
+    removeFromAccountPolicyTx: async (
+      progDataAccount: PublicKey,
+      admin: PublicKey,
+      account: string,
+      remove: PublicKey
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          {
+            pubkey: progDataAccount,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: admin,
+            isSigner: true,
+            isWritable: false,
+          },
+          {
+            pubkey: await findAccountPolicyAddress(programId, account),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, admin),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.removeFromAccountPolicy, [
+          ...borshString(account),
+          ...remove.toBytes(),
+        ]),
+      });
+    },
+    // After this the list only changes through proposals approved by
+    // threshold of admins
+    createMultisigTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      admins: PublicKey[],
+      threshold: number
+    ) => {
+      const accessList = await findAccessListAddress(programId, user);
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: user, isSigner: true, isWritable: true },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          {
+            pubkey: await findMultisigAddress(programId, accessList),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, user),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.createMultisig, [
+          ...pubkeys(admins),
+          threshold,
+        ]),
+      });
+    },
+    // index must be the multisig's current proposal count
+    proposeTx: async (
+      progDataAccount: PublicKey,
+      proposer: PublicKey,
+      multisig: PublicKey,
+      index: number,
+      action: ProposalAction
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: proposer, isSigner: true, isWritable: true },
+          { pubkey: multisig, isSigner: false, isWritable: true },
+          {
+            pubkey: await findProposalAddress(programId, multisig, index),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, proposer),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.propose, proposalAction(action)),
+      });
+    },
+    approveProposalTx: async (
+      progDataAccount: PublicKey,
+      admin: PublicKey,
+      multisig: PublicKey,
+      index: number
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: admin, isSigner: true, isWritable: false },
+          { pubkey: multisig, isSigner: false, isWritable: false },
+          {
+            pubkey: await findProposalAddress(programId, multisig, index),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, admin),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.approveProposal),
+      });
+    },
+    // admins are the multisig's admins in its order, whose revocations are
+    // checked so approvals from revoked admins do not count
+    executeProposalTx: async (
+      progDataAccount: PublicKey,
+      admin: PublicKey,
+      multisig: PublicKey,
+      index: number,
+      accessList: PublicKey,
+      admins: PublicKey[]
+    ) => {
+      const revocations = await Promise.all(
+        admins.map(async (pubkey) => ({
+          pubkey: await findRevocationAddress(programId, pubkey),
+          isSigner: false,
+          isWritable: false,
+        }))
+      );
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: admin, isSigner: true, isWritable: false },
+          { pubkey: multisig, isSigner: false, isWritable: true },
+          {
+            pubkey: await findProposalAddress(programId, multisig, index),
+            isSigner: false,
+            isWritable: true,
+          },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          ...revocations,
+        ],
+        programId,
+        data: instrData(Instruction.executeProposal),
+      });
+    },
+    addManyToAccessListTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      keys: PublicKey[]
+    ) => bulkChange(progDataAccount, user, Instruction.addManyToAccessList, keys),
+    removeManyFromAccessListTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      keys: PublicKey[]
+    ) =>
+      bulkChange(
+        progDataAccount,
+        user,
+        Instruction.removeManyFromAccessList,
+        keys
+      ),
+    replaceAccessListTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      keys: PublicKey[]
+    ) => bulkChange(progDataAccount, user, Instruction.replaceAccessList, keys),
+    closeAccessListTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      recipient: PublicKey
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: user, isSigner: true, isWritable: false },
+          {
+            pubkey: await findAccessListAddress(programId, user),
+            isSigner: false,
+            isWritable: true,
+          },
+          { pubkey: recipient, isSigner: false, isWritable: true },
+        ],
+        programId,
+        data: instrData(Instruction.closeAccessList),
+      });
+    },
+    // For a list the program state's map still records, which it drops
+    closeLegacyAccessListTx: (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      accessList: PublicKey,
+      recipient: PublicKey
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: true },
+          { pubkey: user, isSigner: true, isWritable: false },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          { pubkey: recipient, isSigner: false, isWritable: true },
+        ],
+        programId,
+        data: instrData(Instruction.closeAccessList),
+      });
+    },
+    addAsDelegateTx: async (
+      progDataAccount: PublicKey,
+      delegate: PublicKey,
+      owner: PublicKey,
+      add: PublicKey,
+      notBefore?: number,
+      expiresAt?: number,
+      hosts?: HostScope,
+      deny = false,
+      role = Role.login,
+      kind: EntryKind = "key"
+    ) => {
+      const accessList = await findAccessListAddress(programId, owner);
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: delegate, isSigner: true, isWritable: false },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          {
+            pubkey: await findDelegateAddress(programId, accessList, delegate),
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, delegate),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.addToAccessList, [
+          ...add.toBytes(),
+          ...optionI64(notBefore),
+          ...optionI64(expiresAt),
+          ...hostScope(hosts),
+          deny ? 1 : 0,
+          role,
+          ...entryKind(kind),
+        ]),
+      });
+    },
+    removeAsDelegateTx: async (
+      progDataAccount: PublicKey,
+      delegate: PublicKey,
+      owner: PublicKey,
+      remove: PublicKey
+    ) => {
+      const accessList = await findAccessListAddress(programId, owner);
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: delegate, isSigner: true, isWritable: false },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          {
+            pubkey: await findDelegateAddress(programId, accessList, delegate),
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, delegate),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.removeToAccessList, [...remove.toBytes()]),
+      });
+    },
+    // Change pubkey's role on owner's list, signed by the owner, a delegate
+    // with the manageRoles right or a key with an admin entry on the list
+    setEntryRoleTx: async (
+      progDataAccount: PublicKey,
+      signer: PublicKey,
+      owner: PublicKey,
+      pubkey: PublicKey,
+      role: Role
+    ) => {
+      const accessList = await findAccessListAddress(programId, owner);
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: signer, isSigner: true, isWritable: false },
+          { pubkey: accessList, isSigner: false, isWritable: true },
+          ...(await signerAccounts(signer, accessList)),
+        ],
+        programId,
+        data: instrData(Instruction.setEntryRole, [...pubkey.toBytes(), role]),
+      });
+    },
+    setDelegateTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      delegate: PublicKey,
+      rights: number
+    ) => {
+      const accessList = await findAccessListAddress(programId, user);
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: user, isSigner: true, isWritable: true },
+          { pubkey: accessList, isSigner: false, isWritable: false },
+          {
+            pubkey: await findDelegateAddress(programId, accessList, delegate),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: await findRevocationAddress(programId, user),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.setDelegate, [
+          ...delegate.toBytes(),
+          rights,
+        ]),
+      });
+    },
+    revokeDelegateTx: async (
+      progDataAccount: PublicKey,
+      user: PublicKey,
+      delegate: PublicKey
+    ) => {
+      const accessList = await findAccessListAddress(programId, user);
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: user, isSigner: true, isWritable: true },
+          { pubkey: accessList, isSigner: false, isWritable: false },
+          {
+            pubkey: await findDelegateAddress(programId, accessList, delegate),
+            isSigner: false,
+            isWritable: true,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.revokeDelegate, [...delegate.toBytes()]),
+      });
+    },
+    // Revoke pubkey everywhere, signed by the program authority or the key itself
+    revokeKeyTx: async (
+      progDataAccount: PublicKey,
+      payer: PublicKey,
+      revoker: PublicKey,
+      pubkey: PublicKey
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: payer, isSigner: true, isWritable: true },
+          { pubkey: revoker, isSigner: true, isWritable: false },
+          {
+            pubkey: await findRevocationAddress(programId, pubkey),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.revokeKey, [...pubkey.toBytes()]),
+      });
+    },
+    // Create group name with creator as its only admin
+    createGroupTx: async (
+      progDataAccount: PublicKey,
+      creator: PublicKey,
+      name: string
+    ) => {
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: creator, isSigner: true, isWritable: true },
+          {
+            pubkey: await findGroupAddress(programId, name),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.createGroup, borshString(name)),
+      });
+    },
+    addGroupMemberTx: async (
+      progDataAccount: PublicKey,
+      admin: PublicKey,
+      name: string,
+      member: GroupMember
+    ) =>
+      groupChange(
+        progDataAccount,
+        admin,
+        name,
+        Instruction.addGroupMember,
+        groupMember(member)
+      ),
+    removeGroupMemberTx: async (
+      progDataAccount: PublicKey,
+      admin: PublicKey,
+      name: string,
+      member: GroupMember
+    ) =>
+      groupChange(
+        progDataAccount,
+        admin,
+        name,
+        Instruction.removeGroupMember,
+        groupMember(member)
+      ),
+    setGroupAdminsTx: async (
+      progDataAccount: PublicKey,
+      admin: PublicKey,
+      name: string,
+      admins: PublicKey[]
+    ) =>
+      groupChange(
+        progDataAccount,
+        admin,
+        name,
+        Instruction.setGroupAdmins,
+        pubkeys(admins)
+      ),
+    // Append a login to host's login log, signed by the host key.
+    // remoteHostHash is the SHA-256 of the remote host, or 32 zero bytes.
+    recordLoginTx: async (
+      progDataAccount: PublicKey,
+      host: PublicKey,
+      user: PublicKey,
+      service: string,
+      remoteHostHash: Uint8Array
+    ) => {
+      if (remoteHostHash.length !== 32) {
+        throw new Error("remoteHostHash must be 32 bytes");
+      }
+      return new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: host, isSigner: true, isWritable: true },
+          {
+            pubkey: await findHostAddress(programId, host),
+            isSigner: false,
+            isWritable: false,
+          },
+          {
+            pubkey: await findLoginLogAddress(programId, host),
+            isSigner: false,
+            isWritable: true,
+          },
+          {
+            pubkey: SystemProgram.programId,
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.recordLogin, [
+          ...user.toBytes(),
+          ...borshString(service),
+          ...remoteHostHash,
+        ]),
+      });
+    },
+    // Attest that a session of user on host started, or ended when
+    // openedAt, the unix time it started, is given. Signed by the host key.
+    recordSessionTx: async (
+      progDataAccount: PublicKey,
+      host: PublicKey,
+      user: PublicKey,
+      service: string,
+      openedAt?: number
+    ) =>
+      new TransactionInstruction({
+        keys: [
+          { pubkey: progDataAccount, isSigner: false, isWritable: false },
+          { pubkey: host, isSigner: true, isWritable: false },
+          {
+            pubkey: await findHostAddress(programId, host),
+            isSigner: false,
+            isWritable: false,
+          },
+        ],
+        programId,
+        data: instrData(Instruction.recordSession, [
+          ...user.toBytes(),
+          ...borshString(service),
+          ...optionI64(openedAt),
+        ]),
+      }),
     sendTxs: async (
       instructions: TransactionInstruction[],
       signers: Signer[] = []
@@ -1,17 +1,17 @@
-import { Keypair } from "@solana/web3.js";
-import { configs, connection, initAccount, initAccounts } from "./connect";
+import { configs, connection, initAccounts } from "./connect";
 import { getContract } from "./contract";
 async function main() {
   await initAccounts();
   const contract = getContract(connection, configs.progId, configs.userAccount);
-  const txInit = contract.initProgDataTx(configs.data_account.publicKey);
-  const accessList = Keypair.generate();
-  await initAccount(accessList);
-  const initAccessListTx = contract.initAccessListTx(
+  const txInit = await contract.initProgDataTx(
     configs.data_account.publicKey,
-    accessList.publicKey
+    configs.userAccount.publicKey
   );
-  const ret = await contract.sendTxs([txInit], [configs.data_account]);
+  const initAccessListTx = await contract.initAccessListTx(
+    configs.data_account.publicKey,
+    configs.userAccount.publicKey
+  );
+  const ret = await contract.sendTxs([txInit]);
   console.log(ret);
 }
 
@@ -0,0 +1,192 @@
+//! Login and session attestations: after a successful login the host signs a
+//! `RecordLogin` transaction, appending the login to its on-chain login log,
+//! and it signs a `RecordSession` transaction when a session opens or closes
+use crate::solana::{LookupError, TransactionSender};
+use ed25519_dalek::{Keypair, Signer};
+use solana_pam_shared::client;
+use solana_program::{
+    hash::{hash, Hash},
+    instruction::Instruction,
+    message::Message,
+    pubkey::Pubkey,
+};
+
+/// SHA-256 of the remote host a login came from, or zero without one, so the
+/// log can be matched against known addresses without publishing them
+pub fn remote_host_hash(rhost: Option<&str>) -> [u8; 32] {
+    match rhost {
+        Some(rhost) if !rhost.is_empty() => hash(rhost.as_bytes()).to_bytes(),
+        _ => [0; 32],
+    }
+}
+
+/// What a host attests about one login
+pub struct LoginAttestation<'a> {
+    pub program_id: &'a Pubkey,
+    pub program_data: &'a Pubkey,
+    /// The key that authenticated
+    pub user: &'a Pubkey,
+    pub service: Option<&'a str>,
+    /// The remote host the login came from, as PAM_RHOST
+    pub rhost: Option<&'a str>,
+}
+
+/// What a host attests about a session opening, or with `opened_at`, closing
+pub struct SessionAttestation<'a> {
+    pub program_id: &'a Pubkey,
+    pub program_data: &'a Pubkey,
+    /// The key that authenticated for the session
+    pub user: &'a Pubkey,
+    pub service: Option<&'a str>,
+    /// When the session opened, by the host's clock, once it has closed
+    pub opened_at: Option<i64>,
+}
+
+fn host_key(host: &Keypair) -> Pubkey {
+    Pubkey::new_from_array(host.public.to_bytes())
+}
+
+/// Build the `RecordLogin` transaction for `login`, signed and paid for by
+/// `host`, in wire format
+pub fn login_transaction(login: &LoginAttestation, host: &Keypair, blockhash: Hash) -> Vec<u8> {
+    let instruction = client::record_login(
+        login.program_id,
+        login.program_data,
+        &host_key(host),
+        login.user,
+        login.service.unwrap_or_default().to_string(),
+        remote_host_hash(login.rhost),
+    );
+    host_transaction(instruction, host, blockhash)
+}
+
+/// Build the `RecordSession` transaction for `session`, signed and paid for
+/// by `host`, in wire format
+pub fn session_transaction(
+    session: &SessionAttestation,
+    host: &Keypair,
+    blockhash: Hash,
+) -> Vec<u8> {
+    let instruction = client::record_session(
+        session.program_id,
+        session.program_data,
+        &host_key(host),
+        session.user,
+        session.service.unwrap_or_default().to_string(),
+        session.opened_at,
+    );
+    host_transaction(instruction, host, blockhash)
+}
+
+/// Put `instruction` in a transaction signed and paid for by `host`
+fn host_transaction(instruction: Instruction, host: &Keypair, blockhash: Hash) -> Vec<u8> {
+    let host_key = host_key(host);
+    let mut message = Message::new(&[instruction], Some(&host_key));
+    message.recent_blockhash = blockhash;
+    let message = message.serialize();
+    // The signature count as a compact-u16, the signature, then the message
+    let mut transaction = vec![1];
+    transaction.extend_from_slice(&host.sign(&message).to_bytes());
+    transaction.extend_from_slice(&message);
+    transaction
+}
+
+/// Send the attestation for `login`, returning the transaction signature
+pub fn record_login<T: TransactionSender + ?Sized>(
+    sender: &T,
+    login: &LoginAttestation,
+    host: &Keypair,
+) -> Result<String, LookupError> {
+    let blockhash = sender.latest_blockhash()?;
+    sender.send_transaction(&login_transaction(login, host, blockhash))
+}
+
+/// Send the attestation for `session`, returning the transaction signature
+pub fn record_session<T: TransactionSender + ?Sized>(
+    sender: &T,
+    session: &SessionAttestation,
+    host: &Keypair,
+) -> Result<String, LookupError> {
+    let blockhash = sender.latest_blockhash()?;
+    sender.send_transaction(&session_transaction(session, host, blockhash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{PublicKey, SecretKey, Signature, Verifier};
+    use std::{cell::RefCell, convert::TryFrom};
+
+    #[derive(Default)]
+    struct Recorder {
+        sent: RefCell<Vec<Vec<u8>>>,
+    }
+
+    impl TransactionSender for Recorder {
+        fn latest_blockhash(&self) -> Result<Hash, LookupError> {
+            Ok(Hash::new_from_array([5; 32]))
+        }
+
+        fn send_transaction(&self, transaction: &[u8]) -> Result<String, LookupError> {
+            self.sent.borrow_mut().push(transaction.to_vec());
+            Ok("signature".to_string())
+        }
+    }
+
+    #[test]
+    fn test_remote_host_hash() {
+        assert_eq!(remote_host_hash(None), [0; 32]);
+        assert_eq!(remote_host_hash(Some("")), [0; 32]);
+        assert_eq!(
+            remote_host_hash(Some("10.0.0.1")),
+            hash(b"10.0.0.1").to_bytes()
+        );
+        assert_ne!(
+            remote_host_hash(Some("10.0.0.1")),
+            remote_host_hash(Some("10.0.0.2"))
+        );
+    }
+
+    #[test]
+    fn test_record_login() {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        let host = Keypair { secret, public };
+        let host_key = Pubkey::new_from_array(public.to_bytes());
+        let (program_id, program_data, user) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let login = LoginAttestation {
+            program_id: &program_id,
+            program_data: &program_data,
+            user: &user,
+            service: Some("sshd"),
+            rhost: Some("10.0.0.1"),
+        };
+        let recorder = Recorder::default();
+
+        assert_eq!(record_login(&recorder, &login, &host).unwrap(), "signature");
+        let sent = recorder.sent.into_inner();
+        assert_eq!(sent.len(), 1);
+        let (count, rest) = sent[0].split_first().unwrap();
+        assert_eq!(*count, 1);
+        let (signature, message) = rest.split_at(64);
+        let signature = Signature::try_from(signature).unwrap();
+        assert!(public.verify(message, &signature).is_ok());
+
+        // The host signs and pays for the instruction the shared client builds
+        let instruction = client::record_login(
+            &program_id,
+            &program_data,
+            &host_key,
+            &user,
+            "sshd".to_string(),
+            hash(b"10.0.0.1").to_bytes(),
+        );
+        let mut expected = Message::new(&[instruction], Some(&host_key));
+        expected.recent_blockhash = Hash::new_from_array([5; 32]);
+        assert_eq!(message, &expected.serialize()[..]);
+    }
+}
@@ -0,0 +1,1093 @@
+//! Challenge/response helpers for the authenticate phase
+use crate::solana::{self, AccountSource, LookupError};
+use ed25519_dalek::{PublicKey, Signature};
+use rand::{rngs::OsRng, RngCore};
+use solana_pam_shared::{
+    instructions::find_host_address,
+    state::{
+        decide, expand_groups, AccessEntry, AccountPolicy, Decision, EntryKind, GroupError,
+        HostScope, Login, Role,
+    },
+};
+use solana_program::pubkey::Pubkey;
+use std::{
+    convert::TryFrom,
+    ffi::CString,
+    fmt, fs, io, mem, ptr,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug)]
+pub enum AuthError {
+    UnknownUser(String),
+    Conversation,
+    /// A shared account was not authenticated by this module, so there is no
+    /// key to check
+    NotAuthenticated(String),
+    BadSignature,
+    Config(String),
+    Lookup(LookupError),
+    /// The username registry, a shared account's policy or the groups it
+    /// names could not be read, before any signature was checked
+    Resolve(LookupError),
+    NotInAccessList(Pubkey),
+    /// The key's grant has not started yet
+    NotYetValid(Pubkey),
+    /// The key's grant has run out
+    Expired(Pubkey),
+    /// The key's grant does not cover this host or service
+    WrongHost(Pubkey),
+    /// A deny entry for the key covers this login
+    Denied(Pubkey),
+    /// The key is in the revocation registry
+    Revoked(Pubkey),
+    /// The revocation registry could not be read, which never fails open
+    RevocationUnknown(LookupError),
+    /// The key's grant is for a lower role than this service requires
+    InsufficientRole {
+        pubkey: Pubkey,
+        role: Role,
+    },
+    /// A group named by an entry is nested in itself
+    GroupCycle(Pubkey),
+    /// A group named by an entry nests groups too deep
+    GroupTooDeep(Pubkey),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::UnknownUser(user) => write!(f, "no pubkey mapped to user {}", user),
+            AuthError::Conversation => write!(f, "could not read a response from the user"),
+            AuthError::NotAuthenticated(user) => {
+                write!(f, "no key authenticated as {} through this module", user)
+            }
+            AuthError::BadSignature => write!(f, "signature does not verify"),
+            AuthError::Config(e) => write!(f, "bad configuration: {}", e),
+            AuthError::Lookup(e) => write!(f, "{}", e),
+            AuthError::Resolve(e) => write!(f, "could not resolve the user: {}", e),
+            AuthError::NotInAccessList(pk) => write!(f, "{} is not in the access list", pk),
+            AuthError::NotYetValid(pk) => write!(f, "the grant for {} is not valid yet", pk),
+            AuthError::Expired(pk) => write!(f, "the grant for {} has expired", pk),
+            AuthError::WrongHost(pk) => {
+                write!(f, "the grant for {} is not for this host or service", pk)
+            }
+            AuthError::Denied(pk) => write!(f, "{} is denied access", pk),
+            AuthError::Revoked(pk) => write!(f, "{} has been revoked", pk),
+            AuthError::RevocationUnknown(e) => {
+                write!(f, "could not check whether the key is revoked: {}", e)
+            }
+            AuthError::InsufficientRole { pubkey, role } => write!(
+                f,
+                "{} only has the {} role, which this service does not accept",
+                pubkey,
+                role.as_str()
+            ),
+            AuthError::GroupCycle(group) => write!(f, "group {} is nested in itself", group),
+            AuthError::GroupTooDeep(group) => write!(f, "group {} is nested too deep", group),
+        }
+    }
+}
+
+impl From<LookupError> for AuthError {
+    fn from(e: LookupError) -> Self {
+        AuthError::Lookup(e)
+    }
+}
+
+/// Build a fresh challenge for `user` around a random 32 byte nonce
+pub fn new_challenge(user: &str) -> String {
+    let mut nonce = [0u8; 32];
+    OsRng.fill_bytes(&mut nonce);
+    format!("solana-pam:{}:{}", user, bs58::encode(nonce).into_string())
+}
+
+/// Check a base58 encoded ed25519 signature of `challenge` made by `pubkey`
+pub fn verify_signature(pubkey: &Pubkey, challenge: &str, signature: &str) -> bool {
+    let signature = match bs58::decode(signature.trim()).into_vec() {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    let signature = match Signature::try_from(signature.as_slice()) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let public_key = match PublicKey::from_bytes(pubkey.as_ref()) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    public_key
+        .verify_strict(challenge.as_bytes(), &signature)
+        .is_ok()
+}
+
+/// Find the pubkey mapped to `user` in the contents of a user map file
+pub fn parse_user_map(contents: &str, user: &str) -> Option<Pubkey> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some(name), Some(pk)) if name == user => Pubkey::from_str(pk).ok(),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Find `user` in the user map file at `path`, which need not exist
+pub fn lookup_user_override(path: &str, user: &str) -> Result<Option<Pubkey>, AuthError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(parse_user_map(&contents, user)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(AuthError::Config(e.to_string())),
+    }
+}
+
+/// Whose keys may log in as a unix user
+#[derive(Debug, PartialEq)]
+pub enum Principal {
+    /// A personal account with a single key, checked against its own access list
+    Key(Pubkey),
+    /// An account whose policy lists the keys that may log in as it
+    Shared(AccountPolicy),
+}
+
+impl Principal {
+    /// Keys whose signature is accepted for the user, including the members
+    /// of groups named in a policy
+    pub fn keys<S: AccountSource + ?Sized>(
+        &self,
+        source: &S,
+        program_id: &Pubkey,
+    ) -> Result<Vec<Pubkey>, AuthError> {
+        let policy = match self {
+            Principal::Key(pubkey) => return Ok(vec![*pubkey]),
+            Principal::Shared(policy) => policy,
+        };
+        let entries: Vec<&AccessEntry> = policy.entries.iter().collect();
+        let mut keys: Vec<Pubkey> = Vec::new();
+        let members = expand_group_entries(source, program_id, &entries)?
+            .into_iter()
+            .flat_map(|(_, members)| members);
+        let direct = entries
+            .iter()
+            .filter(|e| e.kind == EntryKind::Key)
+            .map(|e| e.pubkey);
+        for pubkey in direct.chain(members) {
+            if !keys.contains(&pubkey) {
+                keys.push(pubkey);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Whether keys beyond those `keys` can list may log in, such as holders
+    /// of a token named in a policy, so the signer has to say who it is
+    pub fn admits_unlisted_keys(&self) -> bool {
+        match self {
+            Principal::Key(_) => false,
+            Principal::Shared(policy) => policy
+                .entries
+                .iter()
+                .any(|e| !e.deny && matches!(e.kind, EntryKind::Token { .. })),
+        }
+    }
+}
+
+/// Read the groups named by `entries` and find the keys in each, through any
+/// nested groups
+fn expand_group_entries<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    entries: &[&AccessEntry],
+) -> Result<Vec<(Pubkey, Vec<Pubkey>)>, AuthError> {
+    let mut groups = Vec::new();
+    for e in entries.iter().filter(|e| e.kind == EntryKind::Group) {
+        if !groups.contains(&e.pubkey) {
+            groups.push(e.pubkey);
+        }
+    }
+    if groups.is_empty() {
+        return Ok(Vec::new());
+    }
+    expand_groups(&groups, |group| {
+        solana::fetch_group(source, program_id, group)
+    })
+    .map_err(|e| match e {
+        GroupError::Fetch(e) => AuthError::Lookup(e),
+        GroupError::Cycle(group) => AuthError::GroupCycle(group),
+        GroupError::TooDeep(group) => AuthError::GroupTooDeep(group),
+    })
+}
+
+/// Local accounts below this UID are system accounts
+const MIN_REGISTRY_UID: libc::uid_t = 1000;
+
+/// Whether `user` is a local system account such as root. Anyone can claim a
+/// name in the username registry, so it never answers for these.
+fn is_system_user(user: &str) -> bool {
+    let name = match CString::new(user) {
+        Ok(name) => name,
+        Err(_) => return true,
+    };
+    let mut entry: libc::passwd = unsafe { mem::zeroed() };
+    let mut found = ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    // A lookup that fails is treated as a system account, to be safe
+    status != 0 || (!found.is_null() && entry.pw_uid < MIN_REGISTRY_UID)
+}
+
+/// Find out who may log in as `user`: the key for it in the local user map if
+/// it has one, otherwise the keys in its account policy, otherwise, when
+/// `use_registry` is set and `user` is not a system account, the key that
+/// claimed the name in the program's username registry
+pub fn resolve_user<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    user_map: &str,
+    use_registry: bool,
+    user: &str,
+) -> Result<Principal, AuthError> {
+    if let Some(pubkey) = lookup_user_override(user_map, user)? {
+        return Ok(Principal::Key(pubkey));
+    }
+    if let Some(policy) =
+        solana::fetch_account_policy(source, program_id, user).map_err(AuthError::Resolve)?
+    {
+        return Ok(Principal::Shared(policy));
+    }
+    let owner = if use_registry && !is_system_user(user) {
+        solana::fetch_username_owner(source, program_id, user).map_err(AuthError::Resolve)?
+    } else {
+        None
+    };
+    owner
+        .map(Principal::Key)
+        .ok_or_else(|| AuthError::UnknownUser(user.to_string()))
+}
+
+/// Find which of `keys` made `signature` of `challenge`
+pub fn find_signer<'a>(keys: &'a [Pubkey], challenge: &str, signature: &str) -> Option<&'a Pubkey> {
+    keys.iter()
+        .find(|pubkey| verify_signature(pubkey, challenge, signature))
+}
+
+/// Find who signed `challenge` from the user's `response`: the base58
+/// signature made by one of `keys`, or a key and its signature separated by
+/// whitespace
+///
+/// A named key must be one of `keys` unless `any_key` is set, for principals
+/// that admit keys they cannot list.
+pub fn find_response_signer(
+    keys: &[Pubkey],
+    any_key: bool,
+    challenge: &str,
+    response: &str,
+) -> Option<Pubkey> {
+    let mut fields = response.split_whitespace();
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(signature), None, None) => find_signer(keys, challenge, signature).copied(),
+        (Some(pubkey), Some(signature), None) => {
+            let pubkey = Pubkey::from_str(pubkey).ok()?;
+            if (any_key || keys.contains(&pubkey))
+                && verify_signature(&pubkey, challenge, signature)
+            {
+                Some(pubkey)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Seconds since the unix epoch by this host's clock
+pub fn local_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Where and when an access check is made
+pub struct AccessContext<'a> {
+    pub program_id: &'a Pubkey,
+    /// Program state account, to find lists created before they moved to
+    /// program-derived addresses
+    pub program_data: Option<&'a Pubkey>,
+    /// This host's registered key, if it has one
+    pub host: Option<&'a Pubkey>,
+    pub now: i64,
+    /// Leeway in seconds at both ends of a time-bounded grant
+    pub skew: i64,
+    /// The PAM service handling the login, for entries scoped to one
+    pub service: Option<&'a str>,
+    /// The lowest role the service accepts
+    pub min_role: Role,
+}
+
+/// Decide whether `pubkey` may log in from its entries in every list
+/// consulted, returning its role
+///
+/// A deny entry that covers the login wins over any grant. When nothing grants
+/// access, the error says why the key's allow entry does not.
+fn decide_access<S: AccountSource + ?Sized>(
+    source: &S,
+    ctx: &AccessContext,
+    entries: &[&AccessEntry],
+    pubkey: &Pubkey,
+) -> Result<Role, AuthError> {
+    let host = ctx
+        .host
+        .map(|host| find_host_address(ctx.program_id, host).0);
+    // Only fetch this host's account when an entry is scoped to a tag
+    let host_tags = match ctx.host {
+        Some(host) if entries.iter().any(|e| matches!(e.hosts, HostScope::Tag(_))) => {
+            solana::fetch_host(source, ctx.program_id, host)?
+                .map(|account| account.tags)
+                .unwrap_or_default()
+        }
+        _ => Vec::new(),
+    };
+    let groups: Vec<Pubkey> = expand_group_entries(source, ctx.program_id, entries)?
+        .into_iter()
+        .filter(|(_, members)| members.contains(pubkey))
+        .map(|(group, _)| group)
+        .collect();
+    let mut mints = Vec::new();
+    for e in entries {
+        if matches!(e.kind, EntryKind::Token { .. }) && !mints.contains(&e.pubkey) {
+            mints.push(e.pubkey);
+        }
+    }
+    // Balances are read on every login, so a moved token takes effect at once
+    let tokens = mints
+        .into_iter()
+        .map(|mint| Ok((mint, solana::fetch_token_balance(source, pubkey, &mint)?)))
+        .collect::<Result<Vec<_>, LookupError>>()?;
+    let login = Login {
+        pubkey: *pubkey,
+        now: ctx.now,
+        skew: ctx.skew,
+        host,
+        host_tags: &host_tags,
+        service: ctx.service,
+        groups: &groups,
+        tokens: &tokens,
+    };
+    match decide(entries.iter().copied(), &login) {
+        Decision::Allow(role) if role >= ctx.min_role => Ok(role),
+        Decision::Allow(role) => Err(AuthError::InsufficientRole {
+            pubkey: *pubkey,
+            role,
+        }),
+        Decision::Deny => Err(AuthError::Denied(*pubkey)),
+        Decision::Unlisted => {
+            let allow = entries.iter().find(|e| e.is_for(&login) && !e.deny);
+            Err(match allow {
+                None => AuthError::NotInAccessList(*pubkey),
+                Some(e) if e.is_pending(ctx.now.saturating_add(ctx.skew)) => {
+                    AuthError::NotYetValid(*pubkey)
+                }
+                Some(e) if e.is_expired(ctx.now.saturating_sub(ctx.skew)) => {
+                    AuthError::Expired(*pubkey)
+                }
+                Some(_) => AuthError::WrongHost(*pubkey),
+            })
+        }
+    }
+}
+
+/// Check that `pubkey` has a grant in its own on-chain access list that is
+/// valid at `ctx.now`, give or take `ctx.skew` seconds, on this host, and
+/// return the role it grants
+pub fn check_access<S: AccountSource + ?Sized>(
+    source: &S,
+    ctx: &AccessContext,
+    pubkey: &Pubkey,
+) -> Result<Role, AuthError> {
+    let access_list = solana::fetch_access_list(source, ctx.program_id, ctx.program_data, pubkey)?;
+    let entries: Vec<&AccessEntry> = access_list.iter().collect();
+    decide_access(source, ctx, &entries, pubkey)
+}
+
+/// Check that `pubkey`, which signed for `principal`, may log in as it now,
+/// and return its role
+///
+/// A revoked key is turned away before any grant is looked at, as is any
+/// key when the revocation registry cannot be read, and a deny in the key's
+/// own access list wins over a shared account's grants.
+pub fn check_principal<S: AccountSource + ?Sized>(
+    source: &S,
+    ctx: &AccessContext,
+    principal: &Principal,
+    pubkey: &Pubkey,
+) -> Result<Role, AuthError> {
+    if solana::fetch_revocation(source, ctx.program_id, pubkey)
+        .map_err(AuthError::RevocationUnknown)?
+        .is_some()
+    {
+        return Err(AuthError::Revoked(*pubkey));
+    }
+    match principal {
+        Principal::Key(_) => check_access(source, ctx, pubkey),
+        Principal::Shared(policy) => {
+            let own =
+                match solana::fetch_access_list(source, ctx.program_id, ctx.program_data, pubkey) {
+                    Ok(list) => list,
+                    Err(LookupError::NoAccessList(_)) => Vec::new(),
+                    Err(e) => return Err(e.into()),
+                };
+            let entries: Vec<&AccessEntry> = policy
+                .entries
+                .iter()
+                .chain(own.iter().filter(|entry| entry.deny))
+                .collect();
+            decide_access(source, ctx, &entries, pubkey)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixture::Fixture, solana::TOKEN_ACCOUNT_SIZE};
+    use solana_pam_shared::{instructions::find_group_address, state::GroupMember};
+
+    fn context<'a>(program_id: &'a Pubkey, program_data: Option<&'a Pubkey>) -> AccessContext<'a> {
+        AccessContext {
+            program_id,
+            program_data,
+            host: None,
+            now: 0,
+            skew: 0,
+            service: None,
+            min_role: Role::Login,
+        }
+    }
+
+    #[test]
+    fn test_check_access() {
+        let (allowed, outsider, legacy, unlisted) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut fixture = Fixture::new();
+        fixture.add_list(&allowed, vec![Pubkey::new_unique(), allowed]);
+        fixture.add_list(&outsider, vec![Pubkey::new_unique()]);
+        fixture.add_legacy_list(&legacy, vec![legacy]);
+        let (source, program_id, program_data) = fixture.source();
+        let ctx = context(&program_id, None);
+
+        assert!(check_access(&source, &ctx, &allowed).is_ok());
+        assert!(matches!(
+            check_access(&source, &ctx, &outsider),
+            Err(AuthError::NotInAccessList(_))
+        ));
+        assert!(matches!(
+            check_access(
+                &source,
+                &context(&program_id, Some(&program_data)),
+                &unlisted
+            ),
+            Err(AuthError::Lookup(LookupError::NoAccessList(_)))
+        ));
+        assert!(matches!(
+            check_access(&source, &context(&Pubkey::new_unique(), None), &allowed),
+            Err(AuthError::Lookup(LookupError::NoAccessList(_)))
+        ));
+    }
+
+    #[test]
+    fn test_check_access_legacy_list() {
+        let legacy = Pubkey::new_unique();
+        let mut fixture = Fixture::new();
+        fixture.add_legacy_list(&legacy, vec![legacy]);
+        let (source, program_id, program_data) = fixture.source();
+
+        assert!(check_access(&source, &context(&program_id, Some(&program_data)), &legacy).is_ok());
+        assert!(matches!(
+            check_access(&source, &context(&program_id, None), &legacy),
+            Err(AuthError::Lookup(LookupError::NoAccessList(_)))
+        ));
+        assert!(matches!(
+            check_access(
+                &source,
+                &context(&program_id, Some(&Pubkey::new_unique())),
+                &legacy
+            ),
+            Err(AuthError::Lookup(LookupError::AccountNotFound(_)))
+        ));
+    }
+
+    #[test]
+    fn test_check_access_time_bounds() {
+        let (contractor, early) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut fixture = Fixture::new();
+        let bounded = |pubkey| AccessEntry {
+            not_before: Some(1_000),
+            expires_at: Some(2_000),
+            ..AccessEntry::new(pubkey)
+        };
+        fixture.add_entries(&contractor, vec![bounded(contractor)]);
+        fixture.add_entries(&early, vec![bounded(early)]);
+        let (source, program_id, _) = fixture.source();
+        let check = |pubkey, now, skew| {
+            let ctx = AccessContext {
+                now,
+                skew,
+                ..context(&program_id, None)
+            };
+            check_access(&source, &ctx, pubkey)
+        };
+
+        assert!(check(&contractor, 1_500, 0).is_ok());
+        assert!(matches!(
+            check(&contractor, 2_000, 0),
+            Err(AuthError::Expired(_))
+        ));
+        assert!(matches!(
+            check(&early, 999, 0),
+            Err(AuthError::NotYetValid(_))
+        ));
+        // Skew is allowed at both ends of the window
+        assert!(check(&contractor, 2_030, 60).is_ok());
+        assert!(check(&early, 950, 60).is_ok());
+        assert!(matches!(
+            check(&contractor, 2_060, 60),
+            Err(AuthError::Expired(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_access_host_scope() {
+        let (web, db, unregistered) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let (by_host, by_tag, anywhere) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut fixture = Fixture::new();
+        fixture.add_host(&web, vec!["prod".to_string()]);
+        fixture.add_host(&db, vec![]);
+        let (web_account, _) = find_host_address(&fixture.program_id, &web);
+        let scoped = |pubkey, hosts| AccessEntry {
+            hosts,
+            ..AccessEntry::new(pubkey)
+        };
+        fixture.add_entries(
+            &by_host,
+            vec![scoped(by_host, HostScope::Hosts(vec![web_account]))],
+        );
+        fixture.add_entries(
+            &by_tag,
+            vec![scoped(by_tag, HostScope::Tag("prod".to_string()))],
+        );
+        fixture.add_list(&anywhere, vec![anywhere]);
+        let (source, program_id, _) = fixture.source();
+        let check = |pubkey: &Pubkey, host: Option<&Pubkey>| {
+            let ctx = AccessContext {
+                host,
+                ..context(&program_id, None)
+            };
+            check_access(&source, &ctx, pubkey)
+        };
+
+        for pubkey in &[by_host, by_tag, anywhere] {
+            assert!(check(pubkey, Some(&web)).is_ok());
+        }
+        for host in &[Some(&db), Some(&unregistered), None] {
+            assert!(check(&anywhere, *host).is_ok());
+            for pubkey in &[by_host, by_tag] {
+                assert!(matches!(check(pubkey, *host), Err(AuthError::WrongHost(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_user() {
+        let (alice, bob, local) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut fixture = Fixture::new();
+        fixture.add_username("alice", &alice);
+        fixture.add_username("bob", &bob);
+        fixture.add_username("root", &bob);
+        let (source, program_id, _) = fixture.source();
+        let user_map =
+            std::env::temp_dir().join(format!("solana-pam-users-{}", std::process::id()));
+        fs::write(&user_map, format!("# overrides\nbob {}\n", local)).unwrap();
+        let user_map = user_map.to_str().unwrap();
+        let resolve =
+            |use_registry, user| resolve_user(&source, &program_id, user_map, use_registry, user);
+
+        assert_eq!(resolve(true, "alice").unwrap(), Principal::Key(alice));
+        // The local map wins over the registry, and is all there is without it
+        assert_eq!(resolve(true, "bob").unwrap(), Principal::Key(local));
+        assert_eq!(resolve(false, "bob").unwrap(), Principal::Key(local));
+        assert!(matches!(
+            resolve(false, "alice"),
+            Err(AuthError::UnknownUser(_))
+        ));
+        // Nobody's claim on a system account is trusted
+        assert!(matches!(
+            resolve(true, "root"),
+            Err(AuthError::UnknownUser(_))
+        ));
+        for user in &["carol", "not a valid name", &"x".repeat(64)] {
+            assert!(matches!(
+                resolve(true, user),
+                Err(AuthError::UnknownUser(_))
+            ));
+        }
+        fs::remove_file(user_map).unwrap();
+
+        // Hosts without a local map use the registry alone
+        assert_eq!(
+            resolve_user(&source, &program_id, user_map, true, "alice").unwrap(),
+            Principal::Key(alice)
+        );
+    }
+
+    #[test]
+    fn test_shared_account() {
+        let (alice, bob, carol) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let web = Pubkey::new_unique();
+        let mut fixture = Fixture::new();
+        let (web_account, _) = find_host_address(&fixture.program_id, &web);
+        fixture.add_policy(
+            "deploy",
+            vec![
+                AccessEntry::new(alice),
+                AccessEntry {
+                    expires_at: Some(1_000),
+                    ..AccessEntry::new(bob)
+                },
+                AccessEntry {
+                    hosts: HostScope::Hosts(vec![web_account]),
+                    ..AccessEntry::new(carol)
+                },
+            ],
+        );
+        // The policy wins over a personal claim on the same name
+        fixture.add_username("deploy", &Pubkey::new_unique());
+        let (source, program_id, _) = fixture.source();
+        let principal = resolve_user(&source, &program_id, "/nonexistent", true, "deploy").unwrap();
+        assert_eq!(
+            principal.keys(&source, &program_id).unwrap(),
+            vec![alice, bob, carol]
+        );
+        let ctx = AccessContext {
+            now: 2_000,
+            ..context(&program_id, None)
+        };
+        let check = |pubkey| check_principal(&source, &ctx, &principal, pubkey);
+
+        assert!(check(&alice).is_ok());
+        assert!(matches!(check(&bob), Err(AuthError::Expired(_))));
+        assert!(matches!(check(&carol), Err(AuthError::WrongHost(_))));
+        assert!(matches!(
+            check(&Pubkey::new_unique()),
+            Err(AuthError::NotInAccessList(_))
+        ));
+        let ctx = AccessContext {
+            host: Some(&web),
+            ..ctx
+        };
+        assert!(check_principal(&source, &ctx, &principal, &carol).is_ok());
+    }
+
+    #[test]
+    fn test_deny_and_service_scopes() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut fixture = Fixture::new();
+        let service = |name: &str| HostScope::Service(name.to_string());
+        fixture.add_policy(
+            "deploy",
+            vec![
+                AccessEntry {
+                    hosts: service("sudo"),
+                    deny: true,
+                    ..AccessEntry::new(alice)
+                },
+                AccessEntry {
+                    hosts: service("sshd"),
+                    ..AccessEntry::new(bob)
+                },
+            ],
+        );
+        fixture.add_entries(
+            &alice,
+            vec![AccessEntry {
+                deny: true,
+                ..AccessEntry::new(alice)
+            }],
+        );
+        let (source, program_id, _) = fixture.source();
+        let principal = resolve_user(&source, &program_id, "/nonexistent", true, "deploy").unwrap();
+        let check = |pubkey: &Pubkey, service: Option<&str>| {
+            let ctx = AccessContext {
+                service,
+                ..context(&program_id, None)
+            };
+            check_principal(&source, &ctx, &principal, pubkey)
+        };
+
+        assert!(matches!(
+            check(&alice, Some("sudo")),
+            Err(AuthError::Denied(_))
+        ));
+        // Alice's own list denies her everywhere
+        assert!(matches!(
+            check(&alice, Some("sshd")),
+            Err(AuthError::Denied(_))
+        ));
+        assert!(check(&bob, Some("sshd")).is_ok());
+        assert!(matches!(
+            check(&bob, Some("sudo")),
+            Err(AuthError::WrongHost(_))
+        ));
+        assert!(matches!(check(&bob, None), Err(AuthError::WrongHost(_))));
+        assert!(matches!(
+            check_principal(
+                &source,
+                &context(&program_id, None),
+                &Principal::Key(alice),
+                &alice
+            ),
+            Err(AuthError::Denied(_))
+        ));
+    }
+
+    #[test]
+    fn test_own_deny_wins_over_policy() {
+        let (alice, bob, carol) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut fixture = Fixture::new();
+        fixture.add_policy(
+            "deploy",
+            vec![
+                AccessEntry::new(alice),
+                AccessEntry::new(bob),
+                AccessEntry::new(carol),
+            ],
+        );
+        // Alice denies her key for sudo only, Bob's list only allows
+        fixture.add_entries(
+            &alice,
+            vec![
+                AccessEntry::new(alice),
+                AccessEntry {
+                    hosts: HostScope::Service("sudo".to_string()),
+                    deny: true,
+                    ..AccessEntry::new(alice)
+                },
+            ],
+        );
+        fixture.add_list(&bob, vec![bob]);
+        let (source, program_id, _) = fixture.source();
+        let principal = resolve_user(&source, &program_id, "/nonexistent", true, "deploy").unwrap();
+        let check = |pubkey: &Pubkey, service: Option<&str>| {
+            let ctx = AccessContext {
+                service,
+                ..context(&program_id, None)
+            };
+            check_principal(&source, &ctx, &principal, pubkey)
+        };
+
+        assert!(matches!(
+            check(&alice, Some("sudo")),
+            Err(AuthError::Denied(_))
+        ));
+        assert!(check(&alice, Some("sshd")).is_ok());
+        assert!(check(&bob, Some("sudo")).is_ok());
+        assert!(check(&carol, Some("sudo")).is_ok());
+    }
+
+    #[test]
+    fn test_revoked_key() {
+        let (stolen, teammate) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut fixture = Fixture::new();
+        fixture.add_list(&stolen, vec![stolen]);
+        fixture.add_policy(
+            "deploy",
+            vec![AccessEntry::new(stolen), AccessEntry::new(teammate)],
+        );
+        fixture.add_revocation(&stolen);
+        let (source, program_id, _) = fixture.source();
+        let ctx = context(&program_id, None);
+
+        // Grants in a personal list or a shared account make no difference
+        let own = Principal::Key(stolen);
+        assert!(matches!(
+            check_principal(&source, &ctx, &own, &stolen),
+            Err(AuthError::Revoked(_))
+        ));
+        let shared = resolve_user(&source, &program_id, "/nonexistent", true, "deploy").unwrap();
+        assert!(matches!(
+            check_principal(&source, &ctx, &shared, &stolen),
+            Err(AuthError::Revoked(_))
+        ));
+        assert!(check_principal(&source, &ctx, &shared, &teammate).is_ok());
+    }
+
+    #[test]
+    fn test_roles() {
+        let (user, admin, ops) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let with_role = |pubkey, role| AccessEntry {
+            role,
+            ..AccessEntry::new(pubkey)
+        };
+        let mut fixture = Fixture::new();
+        fixture.add_list(&user, vec![user]);
+        fixture.add_entries(&admin, vec![with_role(admin, Role::Admin)]);
+        // Sudo only through an entry scoped to the sudo service
+        fixture.add_policy(
+            "ops",
+            vec![
+                AccessEntry::new(ops),
+                AccessEntry {
+                    hosts: HostScope::Service("sudo".to_string()),
+                    ..with_role(ops, Role::Sudo)
+                },
+            ],
+        );
+        let (source, program_id, _) = fixture.source();
+        let shared = resolve_user(&source, &program_id, "/nonexistent", true, "ops").unwrap();
+        let check = |principal: &Principal, pubkey: &Pubkey, service, min_role| {
+            let ctx = AccessContext {
+                service,
+                min_role,
+                ..context(&program_id, None)
+            };
+            check_principal(&source, &ctx, principal, pubkey)
+        };
+
+        let sshd = (Some("sshd"), Role::Login);
+        let sudo = (Some("sudo"), Role::Sudo);
+        assert_eq!(
+            check(&Principal::Key(user), &user, sshd.0, sshd.1).unwrap(),
+            Role::Login
+        );
+        assert!(matches!(
+            check(&Principal::Key(user), &user, sudo.0, sudo.1),
+            Err(AuthError::InsufficientRole {
+                role: Role::Login,
+                ..
+            })
+        ));
+        assert_eq!(
+            check(&Principal::Key(admin), &admin, sshd.0, sshd.1).unwrap(),
+            Role::Admin
+        );
+        assert_eq!(
+            check(&Principal::Key(admin), &admin, sudo.0, sudo.1).unwrap(),
+            Role::Admin
+        );
+        assert_eq!(check(&shared, &ops, sshd.0, sshd.1).unwrap(), Role::Login);
+        assert_eq!(check(&shared, &ops, sudo.0, sudo.1).unwrap(), Role::Sudo);
+        assert!(matches!(
+            check(&shared, &ops, sudo.0, Role::Admin),
+            Err(AuthError::InsufficientRole {
+                role: Role::Sudo,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_group_access() {
+        let (alice, bob, carol, outsider) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut fixture = Fixture::new();
+        let oncall = fixture.add_group("oncall", vec![GroupMember::Key(carol)]);
+        let ops = fixture.add_group(
+            "ops",
+            vec![
+                GroupMember::Key(alice),
+                GroupMember::Key(bob),
+                GroupMember::Group(oncall),
+            ],
+        );
+        fixture.add_policy(
+            "deploy",
+            vec![
+                AccessEntry::group(ops),
+                AccessEntry {
+                    deny: true,
+                    ..AccessEntry::new(bob)
+                },
+            ],
+        );
+        // A group nested in itself fails closed for everyone it names
+        let (loop_a, _) = find_group_address(&fixture.program_id, "loop-a");
+        let loop_b = fixture.add_group("loop-b", vec![GroupMember::Group(loop_a)]);
+        fixture.add_group(
+            "loop-a",
+            vec![GroupMember::Key(alice), GroupMember::Group(loop_b)],
+        );
+        fixture.add_policy("looped", vec![AccessEntry::group(loop_a)]);
+        let (source, program_id, _) = fixture.source();
+        let ctx = context(&program_id, None);
+        let deploy = resolve_user(&source, &program_id, "/nonexistent", true, "deploy").unwrap();
+        let check = |pubkey| check_principal(&source, &ctx, &deploy, pubkey);
+
+        assert_eq!(
+            deploy.keys(&source, &program_id).unwrap(),
+            vec![bob, alice, carol]
+        );
+        assert_eq!(check(&alice).unwrap(), Role::Login);
+        assert_eq!(check(&carol).unwrap(), Role::Login);
+        assert!(matches!(check(&bob), Err(AuthError::Denied(_))));
+        assert!(matches!(
+            check(&outsider),
+            Err(AuthError::NotInAccessList(_))
+        ));
+
+        let looped = resolve_user(&source, &program_id, "/nonexistent", true, "looped").unwrap();
+        assert!(matches!(
+            looped.keys(&source, &program_id),
+            Err(AuthError::GroupCycle(_))
+        ));
+        assert!(matches!(
+            check_principal(&source, &ctx, &looped, &alice),
+            Err(AuthError::GroupCycle(_))
+        ));
+    }
+
+    #[test]
+    fn test_token_gated_access() {
+        let (badge, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (holder, split, frozen, short, copied, outsider) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut fixture = Fixture::new();
+        fixture.add_policy("deploy", vec![AccessEntry::token(badge, 2)]);
+        fixture.add_token_account(&holder, &badge, 2, 1);
+        // Balances add up across accounts, and frozen badges still count
+        fixture.add_token_account(&split, &badge, 1, 1);
+        fixture.add_token_account(&split, &badge, 1, 1);
+        fixture.add_token_account(&frozen, &badge, 5, 2);
+        fixture.add_token_account(&short, &badge, 1, 1);
+        fixture.add_token_account(&short, &other, 10, 1);
+        fixture.add_token_account(&outsider, &badge, 5, 0);
+        // A look-alike account not owned by the token program is ignored
+        let mut fake = vec![0; TOKEN_ACCOUNT_SIZE];
+        fake[..32].copy_from_slice(badge.as_ref());
+        fake[32..64].copy_from_slice(copied.as_ref());
+        fake[64..72].copy_from_slice(&5u64.to_le_bytes());
+        fake[108] = 1;
+        fixture.add_account(Pubkey::new_unique(), Pubkey::new_unique(), fake);
+        let (source, program_id, _) = fixture.source();
+        let ctx = context(&program_id, None);
+        let deploy = resolve_user(&source, &program_id, "/nonexistent", true, "deploy").unwrap();
+        let check = |pubkey: &Pubkey| check_principal(&source, &ctx, &deploy, pubkey);
+
+        assert!(deploy.admits_unlisted_keys());
+        assert!(deploy.keys(&source, &program_id).unwrap().is_empty());
+        for pubkey in &[holder, split, frozen] {
+            assert_eq!(check(pubkey).unwrap(), Role::Login);
+        }
+        for pubkey in &[short, copied, outsider] {
+            assert!(matches!(check(pubkey), Err(AuthError::NotInAccessList(_))));
+        }
+        assert!(!Principal::Key(holder).admits_unlisted_keys());
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+        let mut seed = [0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        let secret = SecretKey::from_bytes(&seed).unwrap();
+        let public = PublicKey::from(&secret);
+        let keypair = Keypair { secret, public };
+        let pubkey = Pubkey::new_from_array(keypair.public.to_bytes());
+        let challenge = new_challenge("alice");
+        let signature = bs58::encode(keypair.sign(challenge.as_bytes()).to_bytes()).into_string();
+
+        assert!(verify_signature(&pubkey, &challenge, &signature));
+        assert!(!verify_signature(
+            &pubkey,
+            &new_challenge("alice"),
+            &signature
+        ));
+        assert!(!verify_signature(
+            &Pubkey::new_unique(),
+            &challenge,
+            &signature
+        ));
+        assert!(!verify_signature(&pubkey, &challenge, "not base58!"));
+
+        let others = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let keys = [others[0], pubkey, others[1]];
+        assert_eq!(find_signer(&keys, &challenge, &signature), Some(&pubkey));
+        assert_eq!(find_signer(&others, &challenge, &signature), None);
+
+        // The signer may name itself, which only unlisted principals need
+        let named = format!("{} {}", pubkey, signature);
+        assert_eq!(
+            find_response_signer(&keys, false, &challenge, &signature),
+            Some(pubkey)
+        );
+        assert_eq!(
+            find_response_signer(&keys, false, &challenge, &named),
+            Some(pubkey)
+        );
+        assert_eq!(
+            find_response_signer(&others, false, &challenge, &named),
+            None
+        );
+        assert_eq!(
+            find_response_signer(&[], true, &challenge, &named),
+            Some(pubkey)
+        );
+        let impostor = format!("{} {}", others[0], signature);
+        assert_eq!(
+            find_response_signer(&others, true, &challenge, &impostor),
+            None
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Module configuration parsed from the `/etc/pam.d` line
+//!
+//! Arguments are `key=value` pairs, plus the bare `debug` flag. A
+//! `config=<path>` argument loads the same keys from a TOML file first, so
+//! anything on the pam.d line overrides the file.
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use solana_pam_shared::state::Role;
+use solana_program::pubkey::Pubkey;
+use std::{fmt, fs, str::FromStr, time::Duration};
+
+pub const DEFAULT_RPC_URL: &str = "https://api.devnet.solana.com";
+pub const DEFAULT_TIMEOUT_MS: u64 = 5000;
+pub const DEFAULT_CLOCK_SKEW_SECS: u64 = 60;
+/// Maps unix usernames to Solana pubkeys, one `username pubkey` pair per line
+pub const DEFAULT_USER_MAP_PATH: &str = "/etc/solana-pam/users";
+
+const KNOWN_KEYS: &[&str] = &[
+    "rpc_url",
+    "program_id",
+    "program_data",
+    "commitment",
+    "timeout_ms",
+    "fail_mode",
+    "time_source",
+    "clock_skew_secs",
+    "host_key",
+    "user_map",
+    "username_registry",
+    "min_role",
+    "record_logins",
+    "record_sessions",
+    "debug",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl Commitment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Commitment::Processed => "processed",
+            Commitment::Confirmed => "confirmed",
+            Commitment::Finalized => "finalized",
+        }
+    }
+}
+
+/// What to do when the cluster cannot be reached
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailMode {
+    /// Accept a user whose signature verified even though the access list could not be read,
+    /// though never a key whose revocation could not be checked
+    Open,
+    /// Deny the user
+    Closed,
+}
+
+/// Where the time that grants are checked against comes from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeSource {
+    /// The cluster's Clock sysvar
+    Cluster,
+    /// This host's clock, for hosts that keep trusted time
+    Local,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownKey(String),
+    MissingValue(String),
+    InvalidValue { key: String, value: String },
+    MissingKey(&'static str),
+    File { path: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownKey(key) => write!(f, "unknown option `{}`", key),
+            ConfigError::MissingValue(key) => write!(f, "option `{}` needs a value", key),
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "invalid value `{}` for option `{}`", value, key)
+            }
+            ConfigError::MissingKey(key) => write!(f, "option `{}` is required", key),
+            ConfigError::File { path, reason } => write!(f, "could not load {}: {}", path, reason),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub rpc_url: String,
+    pub program_id: Option<Pubkey>,
+    /// Program state account, only needed to find lists created before they
+    /// moved to program-derived addresses
+    pub program_data: Option<Pubkey>,
+    pub commitment: Commitment,
+    pub timeout: Duration,
+    pub fail_mode: FailMode,
+    pub time_source: TimeSource,
+    /// How far the clock may be off, granted in the user's favour at both
+    /// ends of a time-bounded grant
+    pub clock_skew: Duration,
+    /// This host's registered key, loaded from `host_key`. Without it only
+    /// entries scoped to any host are honored.
+    pub host: Option<Pubkey>,
+    /// Where `host` was loaded from, read again to sign attestations
+    pub host_key_path: Option<String>,
+    /// Local mappings from usernames to pubkeys, consulted before the registry
+    pub user_map: String,
+    /// Whether usernames missing from `user_map` are looked up in the
+    /// program's username registry, where names are first come first served,
+    /// so it is off unless asked for
+    pub username_registry: bool,
+    /// The lowest role accepted, when set; see `required_role`
+    pub min_role: Option<Role>,
+    /// Whether each successful login is recorded in the host's on-chain login
+    /// log, which needs `host_key` and `program_data`
+    pub record_logins: bool,
+    /// Whether sessions opening and closing are attested on-chain, which
+    /// needs the same options as `record_logins`
+    pub record_sessions: bool,
+    pub debug: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            rpc_url: DEFAULT_RPC_URL.to_string(),
+            program_id: None,
+            program_data: None,
+            commitment: Commitment::Confirmed,
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+            fail_mode: FailMode::Closed,
+            time_source: TimeSource::Cluster,
+            clock_skew: Duration::from_secs(DEFAULT_CLOCK_SKEW_SECS),
+            host: None,
+            host_key_path: None,
+            user_map: DEFAULT_USER_MAP_PATH.to_string(),
+            username_registry: false,
+            min_role: None,
+            record_logins: false,
+            record_sessions: false,
+            debug: false,
+        }
+    }
+}
+
+fn invalid(key: &str, value: &str) -> ConfigError {
+    ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// Read a keypair file in the Solana CLI's format, a JSON array of the 32
+/// byte secret key followed by the 32 byte public key
+pub fn load_host_keypair(path: &str) -> Result<Keypair, ConfigError> {
+    let file_error = |reason: &str| ConfigError::File {
+        path: path.to_string(),
+        reason: reason.to_string(),
+    };
+    let contents = fs::read_to_string(path).map_err(|e| file_error(&e.to_string()))?;
+    let bytes: Vec<u8> =
+        serde_json::from_str(&contents).map_err(|_| file_error("not a keypair file"))?;
+    if bytes.len() != 64 {
+        return Err(file_error("not a keypair file"));
+    }
+    let secret = SecretKey::from_bytes(&bytes[..32]).map_err(|_| file_error("bad secret key"))?;
+    let public = PublicKey::from(&secret);
+    if public.as_bytes()[..] != bytes[32..] {
+        return Err(file_error("public key does not match the secret key"));
+    }
+    Ok(Keypair { secret, public })
+}
+
+/// Read the public half of a keypair file
+fn load_host_key(path: &str) -> Result<Pubkey, ConfigError> {
+    let keypair = load_host_keypair(path)?;
+    Ok(Pubkey::new_from_array(keypair.public.to_bytes()))
+}
+
+fn split_arg(arg: &str) -> (&str, Option<&str>) {
+    match arg.find('=') {
+        Some(idx) => (&arg[..idx], Some(&arg[idx + 1..])),
+        None => (arg, None),
+    }
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        for arg in args {
+            if let (key @ "config", value) = split_arg(arg) {
+                let path = value.ok_or_else(|| ConfigError::MissingValue(key.to_string()))?;
+                config.load_file(path)?;
+            }
+        }
+        for arg in args {
+            match split_arg(arg) {
+                ("config", _) => {}
+                (key, value) => config.set(key, value)?,
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &str) -> Result<(), ConfigError> {
+        let file_error = |reason: String| ConfigError::File {
+            path: path.to_string(),
+            reason,
+        };
+        let contents = fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+        let table = match contents.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err(file_error("expected a table".to_string())),
+            Err(e) => return Err(file_error(e.to_string())),
+        };
+        for (key, value) in table.iter() {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Boolean(b) => b.to_string(),
+                other => return Err(invalid(key, &other.to_string())),
+            };
+            self.set(key, Some(&value))?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: Option<&str>) -> Result<(), ConfigError> {
+        if !KNOWN_KEYS.contains(&key) {
+            return Err(ConfigError::UnknownKey(key.to_string()));
+        }
+        let value = match (key, value) {
+            ("debug", None) => {
+                self.debug = true;
+                return Ok(());
+            }
+            (_, Some(value)) => value,
+            (_, None) => return Err(ConfigError::MissingValue(key.to_string())),
+        };
+
+        match key {
+            "rpc_url" if !value.is_empty() => self.rpc_url = value.to_string(),
+            "program_id" => {
+                self.program_id = Some(Pubkey::from_str(value).map_err(|_| invalid(key, value))?)
+            }
+            "program_data" => {
+                self.program_data = Some(Pubkey::from_str(value).map_err(|_| invalid(key, value))?)
+            }
+            "commitment" => {
+                self.commitment = match value {
+                    "processed" => Commitment::Processed,
+                    "confirmed" => Commitment::Confirmed,
+                    "finalized" => Commitment::Finalized,
+                    _ => return Err(invalid(key, value)),
+                }
+            }
+            "timeout_ms" => {
+                let ms: u64 = value.parse().map_err(|_| invalid(key, value))?;
+                self.timeout = Duration::from_millis(ms);
+            }
+            "fail_mode" => {
+                self.fail_mode = match value {
+                    "open" => FailMode::Open,
+                    "closed" => FailMode::Closed,
+                    _ => return Err(invalid(key, value)),
+                }
+            }
+            "time_source" => {
+                self.time_source = match value {
+                    "cluster" => TimeSource::Cluster,
+                    "local" => TimeSource::Local,
+                    _ => return Err(invalid(key, value)),
+                }
+            }
+            "clock_skew_secs" => {
+                let secs: u64 = value.parse().map_err(|_| invalid(key, value))?;
+                self.clock_skew = Duration::from_secs(secs);
+            }
+            "host_key" => {
+                self.host = Some(load_host_key(value)?);
+                self.host_key_path = Some(value.to_string());
+            }
+            "user_map" if !value.is_empty() => self.user_map = value.to_string(),
+            "username_registry" => {
+                self.username_registry = value.parse().map_err(|_| invalid(key, value))?
+            }
+            "min_role" => {
+                self.min_role = Some(match value {
+                    "login" => Role::Login,
+                    "sudo" => Role::Sudo,
+                    "admin" => Role::Admin,
+                    _ => return Err(invalid(key, value)),
+                })
+            }
+            "record_logins" => {
+                self.record_logins = value.parse().map_err(|_| invalid(key, value))?
+            }
+            "record_sessions" => {
+                self.record_sessions = value.parse().map_err(|_| invalid(key, value))?
+            }
+            "debug" => self.debug = value.parse().map_err(|_| invalid(key, value))?,
+            _ => return Err(invalid(key, value)),
+        }
+        Ok(())
+    }
+
+    /// The lowest role accepted for logins through `service`: `min_role` if
+    /// set, otherwise `Role::Sudo` for the `sudo` service and `Role::Login`
+    /// for every other
+    pub fn required_role(&self, service: Option<&str>) -> Role {
+        match (self.min_role, service) {
+            (Some(role), _) => role,
+            (None, Some("sudo")) => Role::Sudo,
+            (None, _) => Role::Login,
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.program_id.is_none() {
+            return Err(ConfigError::MissingKey("program_id"));
+        }
+        let attests = self.record_logins || self.record_sessions;
+        if attests && self.host.is_none() {
+            return Err(ConfigError::MissingKey("host_key"));
+        }
+        if attests && self.program_data.is_none() {
+            return Err(ConfigError::MissingKey("program_data"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let program_id = Pubkey::new_unique();
+        let config = Config::from_args(&args(&[
+            "rpc_url=http://localhost:8899",
+            &format!("program_id={}", program_id),
+            "commitment=finalized",
+            "timeout_ms=250",
+            "fail_mode=open",
+            "time_source=local",
+            "clock_skew_secs=5",
+            "user_map=/etc/pam-users",
+            "username_registry=true",
+            "debug",
+        ]))
+        .unwrap();
+        assert_eq!(config.rpc_url, "http://localhost:8899");
+        assert_eq!(config.program_id, Some(program_id));
+        assert_eq!(config.program_data, None);
+        assert_eq!(config.commitment, Commitment::Finalized);
+        assert_eq!(config.timeout, Duration::from_millis(250));
+        assert_eq!(config.fail_mode, FailMode::Open);
+        assert_eq!(config.time_source, TimeSource::Local);
+        assert_eq!(config.clock_skew, Duration::from_secs(5));
+        assert_eq!(config.user_map, "/etc/pam-users");
+        assert!(config.username_registry);
+        assert!(config.debug);
+    }
+
+    #[test]
+    fn test_reject_bad_args() {
+        let program_id = format!("program_id={}", Pubkey::new_unique());
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, "rpc=http://localhost:8899"])),
+            Err(ConfigError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, "fail_mode=sometimes"])),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, "timeout_ms"])),
+            Err(ConfigError::MissingValue(_))
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&["program_id=not-a-key"])),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&[])),
+            Err(ConfigError::MissingKey("program_id"))
+        ));
+    }
+
+    #[test]
+    fn test_required_role() {
+        let program_id = format!("program_id={}", Pubkey::new_unique());
+        let config = Config::from_args(&args(&[&program_id])).unwrap();
+        assert_eq!(config.required_role(Some("sudo")), Role::Sudo);
+        assert_eq!(config.required_role(Some("sshd")), Role::Login);
+        assert_eq!(config.required_role(None), Role::Login);
+
+        let config = Config::from_args(&args(&[&program_id, "min_role=admin"])).unwrap();
+        assert_eq!(config.required_role(Some("sshd")), Role::Admin);
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, "min_role=root"])),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_host_key() {
+        let secret = SecretKey::from_bytes(&[3; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        let mut keypair = secret.as_bytes().to_vec();
+        keypair.extend_from_slice(public.as_bytes());
+        let path =
+            std::env::temp_dir().join(format!("solana-pam-host-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_string(&keypair).unwrap()).unwrap();
+        let program_id = format!("program_id={}", Pubkey::new_unique());
+        let host_key = format!("host_key={}", path.display());
+
+        let config = Config::from_args(&args(&[&program_id, &host_key])).unwrap();
+        assert_eq!(config.host, Some(Pubkey::new_from_array(public.to_bytes())));
+        let loaded = load_host_keypair(config.host_key_path.as_ref().unwrap()).unwrap();
+        assert_eq!(loaded.public, public);
+
+        // Recording logins needs the host key and the program state account
+        let program_data = format!("program_data={}", Pubkey::new_unique());
+        let record = "record_logins=true";
+        assert!(
+            Config::from_args(&args(&[&program_id, &host_key, &program_data, record]))
+                .unwrap()
+                .record_logins
+        );
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, &program_data, record])),
+            Err(ConfigError::MissingKey("host_key"))
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, &host_key, record])),
+            Err(ConfigError::MissingKey("program_data"))
+        ));
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, &program_data, "record_sessions=true"])),
+            Err(ConfigError::MissingKey("host_key"))
+        ));
+
+        keypair[40] ^= 1;
+        fs::write(&path, serde_json::to_string(&keypair).unwrap()).unwrap();
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, &host_key])),
+            Err(ConfigError::File { .. })
+        ));
+        fs::write(&path, "[1, 2, 3]").unwrap();
+        assert!(matches!(
+            Config::from_args(&args(&[&program_id, &host_key])),
+            Err(ConfigError::File { .. })
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+//! Fixture-file backed [`AccountSource`] for tests
+//!
+//! A fixture file is a JSON object from base58 addresses to account objects in
+//! the shape `getAccountInfo` returns, so accounts captured from a real node
+//! can be dropped in as they are.
+use crate::handle::PamHandle;
+use crate::solana::{
+    token_program_id, Account, AccountSource, LookupError, TokenAccount, TransactionSender,
+    TOKEN_ACCOUNT_SIZE,
+};
+use borsh::BorshSerialize;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use serde_json::{json, Map, Value};
+use solana_pam_shared::instructions::{
+    find_access_list_address, find_account_policy_address, find_group_address, find_host_address,
+    find_revocation_address, find_username_address, ProgramData,
+};
+use solana_pam_shared::state::{
+    AccessEntry, AccessList, AccountPolicy, Group, GroupMember, HostAccount, Revocation,
+    UsernameAccount, ACCESS_LIST_SIZE, ACCOUNT_POLICY_SIZE, GROUP_SIZE, HOST_ACCOUNT_SIZE,
+    REVOCATION_SIZE, USERNAME_ACCOUNT_SIZE,
+};
+use solana_program::{hash::Hash, pubkey::Pubkey, sysvar};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    str::FromStr,
+};
+
+#[derive(Default)]
+pub struct FixtureSource {
+    accounts: HashMap<Pubkey, Account>,
+    /// Addresses whose reads fail as if the node could not be reached
+    unreachable: HashSet<Pubkey>,
+    /// Transactions sent through the source, in wire format
+    pub sent: RefCell<Vec<Vec<u8>>>,
+}
+
+impl FixtureSource {
+    pub fn from_json(contents: &str) -> Result<Self, LookupError> {
+        let value: Value =
+            serde_json::from_str(contents).map_err(|e| LookupError::Decode(e.to_string()))?;
+        let entries = value
+            .as_object()
+            .ok_or_else(|| LookupError::Decode("fixture is not an object".to_string()))?;
+        let mut accounts = HashMap::new();
+        for (address, account) in entries {
+            let address = Pubkey::from_str(address)
+                .map_err(|_| LookupError::Decode(format!("bad address {}", address)))?;
+            accounts.insert(address, Account::from_json(account)?);
+        }
+        Ok(FixtureSource {
+            accounts,
+            ..FixtureSource::default()
+        })
+    }
+
+    /// Make every read that includes `address` fail with an RPC error
+    pub fn make_unreachable(&mut self, address: Pubkey) {
+        self.unreachable.insert(address);
+    }
+
+    pub fn load(path: &str) -> Result<Self, LookupError> {
+        let contents = fs::read_to_string(path).map_err(|e| LookupError::Decode(e.to_string()))?;
+        Self::from_json(&contents)
+    }
+
+    /// Render accounts in the fixture file format
+    pub fn to_json(accounts: &[(Pubkey, Account)]) -> String {
+        let entries: Map<String, Value> = accounts
+            .iter()
+            .map(|(address, account)| {
+                let value = json!({
+                    "lamports": account.lamports,
+                    "owner": account.owner.to_string(),
+                    "data": [base64::encode(&account.data), "base64"],
+                });
+                (address.to_string(), value)
+            })
+            .collect();
+        Value::Object(entries).to_string()
+    }
+}
+
+impl AccountSource for FixtureSource {
+    fn get_multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, LookupError> {
+        if let Some(pk) = pubkeys.iter().find(|pk| self.unreachable.contains(pk)) {
+            return Err(LookupError::Rpc(format!("{} is unreachable", pk)));
+        }
+        Ok(pubkeys
+            .iter()
+            .map(|pk| self.accounts.get(pk).cloned())
+            .collect())
+    }
+
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Vec<Account>, LookupError> {
+        let token_program = token_program_id();
+        Ok(self
+            .accounts
+            .values()
+            .filter(|account| account.owner == token_program)
+            .filter(|account| match TokenAccount::unpack(&account.data) {
+                Ok(token) => token.owner == *owner && token.mint == *mint,
+                Err(_) => false,
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+impl TransactionSender for FixtureSource {
+    fn latest_blockhash(&self) -> Result<Hash, LookupError> {
+        Ok(Hash::new_from_array([5; 32]))
+    }
+
+    fn send_transaction(&self, transaction: &[u8]) -> Result<String, LookupError> {
+        self.sent.borrow_mut().push(transaction.to_vec());
+        Ok(format!("signature-{}", self.sent.borrow().len()))
+    }
+}
+
+/// Builds the accounts of a program deployment for a [`FixtureSource`]
+pub struct Fixture {
+    pub program_id: Pubkey,
+    pub program_data: Pubkey,
+    accounts: Vec<(Pubkey, Account)>,
+    state: ProgramData,
+}
+
+impl Default for Fixture {
+    fn default() -> Self {
+        Fixture {
+            program_id: Pubkey::new_unique(),
+            program_data: Pubkey::new_unique(),
+            accounts: Vec::new(),
+            state: ProgramData::new(),
+        }
+    }
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_list(&mut self, user: &Pubkey, keys: Vec<Pubkey>) {
+        self.add_entries(user, keys.into_iter().map(AccessEntry::new).collect());
+    }
+
+    pub fn add_entries(&mut self, user: &Pubkey, entries: Vec<AccessEntry>) {
+        let (list, _) = find_access_list_address(&self.program_id, user);
+        let mut access_list = AccessList::new(*user);
+        access_list.entries = entries;
+        let mut data = vec![0; ACCESS_LIST_SIZE];
+        access_list.write(&mut data).unwrap();
+        self.add_account(list, self.program_id, data);
+    }
+
+    pub fn add_host(&mut self, host: &Pubkey, tags: Vec<String>) {
+        let (address, _) = find_host_address(&self.program_id, host);
+        let host_account = HostAccount::new(*host, "host".to_string(), tags).unwrap();
+        let mut data = vec![0; HOST_ACCOUNT_SIZE];
+        host_account.write(&mut data).unwrap();
+        self.add_account(address, self.program_id, data);
+    }
+
+    pub fn add_username(&mut self, username: &str, owner: &Pubkey) {
+        let (address, _) = find_username_address(&self.program_id, username);
+        let record = UsernameAccount::new(*owner, username.to_string()).unwrap();
+        let mut data = vec![0; USERNAME_ACCOUNT_SIZE];
+        record.write(&mut data).unwrap();
+        self.add_account(address, self.program_id, data);
+    }
+
+    pub fn add_policy(&mut self, account: &str, entries: Vec<AccessEntry>) {
+        let (address, _) = find_account_policy_address(&self.program_id, account);
+        let mut policy = AccountPolicy::new(Pubkey::new_unique(), account.to_string()).unwrap();
+        policy.entries = entries;
+        let mut data = vec![0; ACCOUNT_POLICY_SIZE];
+        policy.write(&mut data).unwrap();
+        self.add_account(address, self.program_id, data);
+    }
+
+    pub fn add_revocation(&mut self, pubkey: &Pubkey) {
+        let (address, _) = find_revocation_address(&self.program_id, pubkey);
+        let mut data = vec![0; REVOCATION_SIZE];
+        Revocation::new(*pubkey, *pubkey, 0)
+            .write(&mut data)
+            .unwrap();
+        self.add_account(address, self.program_id, data);
+    }
+
+    /// Add group `name` with `members`, returning its address
+    pub fn add_group(&mut self, name: &str, members: Vec<GroupMember>) -> Pubkey {
+        let (address, _) = find_group_address(&self.program_id, name);
+        let mut group = Group::new(name.to_string(), vec![Pubkey::new_unique()]).unwrap();
+        group.members = members;
+        let mut data = vec![0; GROUP_SIZE];
+        group.write(&mut data).unwrap();
+        self.add_account(address, self.program_id, data);
+        address
+    }
+
+    /// Add an SPL token account holding `amount` of `mint` for `owner`
+    pub fn add_token_account(&mut self, owner: &Pubkey, mint: &Pubkey, amount: u64, state: u8) {
+        let mut data = vec![0; TOKEN_ACCOUNT_SIZE];
+        data[..32].copy_from_slice(mint.as_ref());
+        data[32..64].copy_from_slice(owner.as_ref());
+        data[64..72].copy_from_slice(&amount.to_le_bytes());
+        data[108] = state;
+        self.add_account(Pubkey::new_unique(), token_program_id(), data);
+    }
+
+    /// Add a headerless list at an arbitrary address, recorded only in the
+    /// state's map, as lists were stored before program-derived addresses
+    pub fn add_legacy_list(&mut self, user: &Pubkey, keys: Vec<Pubkey>) {
+        let list = Pubkey::new_unique();
+        self.state.update(user, &list).unwrap();
+        let mut data = vec![0; ACCESS_LIST_SIZE];
+        for (slot, key) in data.chunks_mut(32).zip(&keys) {
+            slot.copy_from_slice(key.as_ref());
+        }
+        self.add_account(list, self.program_id, data);
+    }
+
+    /// Set the cluster's unix time, as the Clock sysvar reports it
+    pub fn set_clock(&mut self, now: i64) {
+        let mut data = vec![0; 40];
+        data[32..].copy_from_slice(&now.to_le_bytes());
+        self.add_account(sysvar::clock::id(), sysvar::id(), data);
+    }
+
+    pub fn add_account(&mut self, address: Pubkey, owner: Pubkey, data: Vec<u8>) {
+        let account = Account {
+            lamports: 1,
+            owner,
+            data,
+        };
+        self.accounts.push((address, account));
+    }
+
+    pub fn source(mut self) -> (FixtureSource, Pubkey, Pubkey) {
+        let state = self.state.try_to_vec().unwrap();
+        self.add_account(self.program_data, self.program_id, state);
+        let source = FixtureSource::from_json(&FixtureSource::to_json(&self.accounts)).unwrap();
+        (source, self.program_id, self.program_data)
+    }
+}
+
+type Answer<'a> = Box<dyn Fn(&str) -> Option<String> + 'a>;
+
+/// A scripted PAM handle for driving the module's phases in tests
+///
+/// Prompts are answered by `answer` and recorded, and module data is kept on
+/// the conversation, so one conversation can run every phase of a login in
+/// turn as one PAM transaction would.
+pub struct Conversation<'a> {
+    pub user: Option<String>,
+    pub service: Option<String>,
+    pub rhost: Option<String>,
+    answer: Answer<'a>,
+    pub prompts: RefCell<Vec<String>>,
+    data: RefCell<HashMap<String, Vec<u8>>>,
+}
+
+impl<'a> Conversation<'a> {
+    /// A conversation for `user` that gives no answers
+    pub fn new(user: &str) -> Self {
+        Conversation {
+            user: Some(user.to_string()),
+            service: None,
+            rhost: None,
+            answer: Box::new(|_| None),
+            prompts: RefCell::default(),
+            data: RefCell::default(),
+        }
+    }
+
+    pub fn service(mut self, service: &str) -> Self {
+        self.service = Some(service.to_string());
+        self
+    }
+
+    pub fn answer<F: Fn(&str) -> Option<String> + 'a>(mut self, answer: F) -> Self {
+        self.answer = Box::new(answer);
+        self
+    }
+
+    /// Answer challenges by signing them with `keypair`
+    pub fn signing_with(self, keypair: &'a Keypair) -> Self {
+        self.answer(move |prompt| sign_challenge(keypair, prompt))
+    }
+}
+
+impl PamHandle for Conversation<'_> {
+    fn user(&self) -> Option<String> {
+        self.user.clone()
+    }
+
+    fn service(&self) -> Option<String> {
+        self.service.clone()
+    }
+
+    fn rhost(&self) -> Option<String> {
+        self.rhost.clone()
+    }
+
+    fn prompt(&self, prompt: &str) -> Option<String> {
+        self.prompts.borrow_mut().push(prompt.to_string());
+        (self.answer)(prompt)
+    }
+
+    fn set_data(&self, name: &str, data: Vec<u8>) -> bool {
+        self.data.borrow_mut().insert(name.to_string(), data);
+        true
+    }
+
+    fn data(&self, name: &str) -> Option<Vec<u8>> {
+        self.data.borrow().get(name).cloned()
+    }
+}
+
+/// A keypair derived from `seed`, and its public key as a `Pubkey`
+pub fn keypair(seed: u8) -> (Keypair, Pubkey) {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    let pubkey = Pubkey::new_from_array(public.to_bytes());
+    (Keypair { secret, public }, pubkey)
+}
+
+/// Sign the challenge on the second line of an authentication prompt, as a
+/// user would paste the base58 signature back
+pub fn sign_challenge(keypair: &Keypair, prompt: &str) -> Option<String> {
+    let challenge = prompt.lines().nth(1)?;
+    Some(bs58::encode(keypair.sign(challenge.as_bytes()).to_bytes()).into_string())
+}
+
+/// Write `keypair` to a keypair file named after `name` in the temp directory
+pub fn write_keypair_file(keypair: &Keypair, name: &str) -> String {
+    let path =
+        std::env::temp_dir().join(format!("solana-pam-{}-{}.json", name, std::process::id()));
+    fs::write(
+        &path,
+        serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap(),
+    )
+    .unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_file_round_trip() {
+        let address = Pubkey::new_unique();
+        let account = Account {
+            lamports: 42,
+            owner: Pubkey::new_unique(),
+            data: vec![1, 2, 3],
+        };
+        let path = std::env::temp_dir().join(format!("solana-pam-fixture-{}.json", address));
+        fs::write(&path, FixtureSource::to_json(&[(address, account.clone())])).unwrap();
+
+        let source = FixtureSource::load(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(source.get_account(&address).unwrap(), Some(account));
+        assert_eq!(source.get_account(&Pubkey::new_unique()).unwrap(), None);
+    }
+}
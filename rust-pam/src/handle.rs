@@ -0,0 +1,92 @@
+//! The parts of a PAM handle the module uses
+//!
+//! Every phase goes through the [`PamHandle`] trait rather than `pamsm::Pam`,
+//! so tests can drive a phase with a scripted conversation. Data the module
+//! keeps between phases, such as the key that authenticated, is stored on the
+//! handle and lives as long as the PAM transaction.
+use pamsm::{Pam, PamLibExt, PamMsgStyle};
+use solana_program::pubkey::Pubkey;
+use std::convert::TryInto;
+
+/// Module data naming the key that authenticated, and the user it signed for
+const AUTHENTICATED_KEY: &str = "pam_sol_authenticated_key";
+/// Module data holding the unix time the session was opened
+const SESSION_START: &str = "pam_sol_session_start";
+
+pub trait PamHandle {
+    fn user(&self) -> Option<String>;
+    fn service(&self) -> Option<String>;
+    fn rhost(&self) -> Option<String>;
+    /// Show `prompt` and read the answer, echoed as it is typed
+    fn prompt(&self, prompt: &str) -> Option<String>;
+    /// Keep `data` under `name` for later phases, returning whether it was kept
+    fn set_data(&self, name: &str, data: Vec<u8>) -> bool;
+    fn data(&self, name: &str) -> Option<Vec<u8>>;
+}
+
+impl PamHandle for Pam {
+    fn user(&self) -> Option<String> {
+        match self.get_user(None) {
+            Ok(Some(user)) => Some(user.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    fn service(&self) -> Option<String> {
+        match self.get_service() {
+            Ok(Some(service)) => Some(service.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    fn rhost(&self) -> Option<String> {
+        match self.get_rhost() {
+            Ok(Some(rhost)) => Some(rhost.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    fn prompt(&self, prompt: &str) -> Option<String> {
+        match self.conv(Some(prompt), PamMsgStyle::PROMPT_ECHO_ON) {
+            Ok(Some(answer)) => Some(answer.to_string_lossy().into_owned()),
+            _ => None,
+        }
+    }
+
+    fn set_data(&self, name: &str, data: Vec<u8>) -> bool {
+        self.send_bytes(name, data, None).is_ok()
+    }
+
+    fn data(&self, name: &str) -> Option<Vec<u8>> {
+        self.retrieve_bytes(name).ok()
+    }
+}
+
+/// Remember that `pubkey` authenticated as `user`
+pub fn set_authenticated_key<H: PamHandle + ?Sized>(pamh: &H, user: &str, pubkey: &Pubkey) -> bool {
+    let mut data = pubkey.to_bytes().to_vec();
+    data.extend_from_slice(user.as_bytes());
+    pamh.set_data(AUTHENTICATED_KEY, data)
+}
+
+/// The key that authenticated as `user` earlier in this PAM transaction
+///
+/// Another module may change the user between phases, so a key that signed
+/// for someone else is not returned.
+pub fn authenticated_key<H: PamHandle + ?Sized>(pamh: &H, user: &str) -> Option<Pubkey> {
+    let data = pamh.data(AUTHENTICATED_KEY)?;
+    if data.len() < 32 || &data[32..] != user.as_bytes() {
+        return None;
+    }
+    Some(Pubkey::new_from_array(data[..32].try_into().ok()?))
+}
+
+pub fn set_session_start<H: PamHandle + ?Sized>(pamh: &H, opened_at: i64) -> bool {
+    pamh.set_data(SESSION_START, opened_at.to_le_bytes().to_vec())
+}
+
+/// When `open_session` ran in this PAM transaction, if it did
+pub fn session_start<H: PamHandle + ?Sized>(pamh: &H) -> Option<i64> {
+    let data = pamh.data(SESSION_START)?;
+    Some(i64::from_le_bytes(data.as_slice().try_into().ok()?))
+}
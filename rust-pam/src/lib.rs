@@ -1,22 +1,546 @@
-#[macro_use]
+mod attest;
+mod auth;
+mod config;
+#[cfg(test)]
+mod fixture;
+mod handle;
+mod session;
+mod solana;
+mod syslog;
 
+use auth::{AuthError, Principal};
+use config::{Config, FailMode, TimeSource};
+use handle::PamHandle;
 use pamsm::{Pam, PamError, PamFlag, PamServiceModule};
+use solana::{AccountSource, LookupError, RpcClient, TransactionSender};
+use solana_pam_shared::state::Role;
+use solana_program::pubkey::Pubkey;
 
 struct PamTime;
 
+/// Find out who may log in as the PAM user
+fn resolve<H, S>(pamh: &H, config: &Config, source: &S) -> Result<(String, Principal), AuthError>
+where
+    H: PamHandle + ?Sized,
+    S: AccountSource + ?Sized,
+{
+    let user = pamh.user().ok_or(AuthError::Conversation)?;
+    // `validate` guarantees program_id is set
+    let principal = auth::resolve_user(
+        source,
+        &config.program_id.unwrap(),
+        &config.user_map,
+        config.username_registry,
+        &user,
+    )?;
+    Ok((user, principal))
+}
+
+/// Check that `pubkey` may log in as `principal` through this service now,
+/// returning its role
+fn check_key<H, S>(
+    pamh: &H,
+    config: &Config,
+    source: &S,
+    principal: &Principal,
+    pubkey: &Pubkey,
+) -> Result<Role, AuthError>
+where
+    H: PamHandle + ?Sized,
+    S: AccountSource + ?Sized,
+{
+    let program_id = config.program_id.unwrap();
+    let service = pamh.service();
+    let now = match config.time_source {
+        TimeSource::Cluster => solana::get_cluster_time(source)?,
+        TimeSource::Local => auth::local_time(),
+    };
+    let ctx = auth::AccessContext {
+        program_id: &program_id,
+        program_data: config.program_data.as_ref(),
+        host: config.host.as_ref(),
+        now,
+        skew: config.clock_skew.as_secs() as i64,
+        service: service.as_deref(),
+        min_role: config.required_role(service.as_deref()),
+    };
+    auth::check_principal(source, &ctx, principal, pubkey)
+}
+
+/// Ask the user to sign a fresh challenge, then check the signature and the
+/// signing key's place in the on-chain access list, or for shared accounts,
+/// in the account's policy, with a role the service accepts
+fn check_user<H, C>(pamh: &H, config: &Config, cluster: &C) -> Result<(), AuthError>
+where
+    H: PamHandle + ?Sized,
+    C: AccountSource + TransactionSender + ?Sized,
+{
+    let (user, principal) = resolve(pamh, config, cluster)?;
+    let challenge = auth::new_challenge(&user);
+    let prompt = match &principal {
+        Principal::Key(pubkey) => format!(
+            "Sign this challenge with {}:\n{}\nSignature: ",
+            pubkey, challenge
+        ),
+        Principal::Shared(_) if principal.admits_unlisted_keys() => format!(
+            "Sign this challenge with a key allowed to log in as {}:\n{}\nKey and signature: ",
+            user, challenge
+        ),
+        Principal::Shared(_) => format!(
+            "Sign this challenge with a key allowed to log in as {}:\n{}\nSignature: ",
+            user, challenge
+        ),
+    };
+    let signature = pamh.prompt(&prompt).ok_or(AuthError::Conversation)?;
+    // Nothing is verified until a key is found that signed, so failing to
+    // read a group must not fail open
+    let keys = principal
+        .keys(cluster, &config.program_id.unwrap())
+        .map_err(|e| match e {
+            AuthError::Lookup(e) => AuthError::Resolve(e),
+            e => e,
+        })?;
+    let pubkey = auth::find_response_signer(
+        &keys,
+        principal.admits_unlisted_keys(),
+        &challenge,
+        &signature,
+    )
+    .ok_or(AuthError::BadSignature)?;
+    if config.debug {
+        syslog::debug(&format!("{} signed the challenge for {}", pubkey, user));
+    }
+
+    let role = check_key(pamh, config, cluster, &principal, &pubkey)?;
+    syslog::info(&format!(
+        "{} authenticated with {} as {}",
+        user,
+        pubkey,
+        role.as_str()
+    ));
+    // Later phases check and log the same key
+    handle::set_authenticated_key(pamh, &user, &pubkey);
+    if config.record_logins {
+        record_login(pamh, config, cluster, &pubkey);
+    }
+    Ok(())
+}
+
+/// Record the login in this host's on-chain login log. A failure is logged
+/// but does not refuse a login that was already allowed.
+fn record_login<H, C>(pamh: &H, config: &Config, cluster: &C, pubkey: &Pubkey)
+where
+    H: PamHandle + ?Sized,
+    C: TransactionSender + ?Sized,
+{
+    let (service, rhost) = (pamh.service(), pamh.rhost());
+    // `validate` guarantees these are set when logins are recorded
+    let (program_id, program_data, host_key_path) = (
+        config.program_id.unwrap(),
+        config.program_data.unwrap(),
+        config.host_key_path.as_ref().unwrap(),
+    );
+    let login = attest::LoginAttestation {
+        program_id: &program_id,
+        program_data: &program_data,
+        user: pubkey,
+        service: service.as_deref(),
+        rhost: rhost.as_deref(),
+    };
+    let recorded = match config::load_host_keypair(host_key_path) {
+        Ok(host) => attest::record_login(cluster, &login, &host).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match recorded {
+        Ok(signature) if config.debug => syslog::debug(&format!(
+            "recorded the login of {} in {}",
+            pubkey, signature
+        )),
+        Ok(_) => {}
+        Err(e) => syslog::warning(&format!("could not record the login of {}: {}", pubkey, e)),
+    }
+}
+
+/// Check again that the user's key may log in, so a grant that expired or a
+/// key revoked since authentication, or a login through another module, is
+/// turned away
+///
+/// Personal accounts are checked with their own key. Shared accounts are
+/// checked with the key that authenticated through this module, and refused
+/// without one.
+fn check_account<H, S>(pamh: &H, config: &Config, source: &S) -> Result<Role, AuthError>
+where
+    H: PamHandle + ?Sized,
+    S: AccountSource + ?Sized,
+{
+    let (user, principal) = resolve(pamh, config, source)?;
+    let pubkey = match &principal {
+        Principal::Key(pubkey) => *pubkey,
+        Principal::Shared(_) => handle::authenticated_key(pamh, &user)
+            .ok_or_else(|| AuthError::NotAuthenticated(user.clone()))?,
+    };
+    let role = check_key(pamh, config, source, &principal, &pubkey)?;
+    if config.debug {
+        syslog::debug(&format!(
+            "{} may still use {} as {}",
+            pubkey,
+            user,
+            role.as_str()
+        ));
+    }
+    Ok(role)
+}
+
+fn authenticate<H, C>(pamh: &H, config: &Config, cluster: &C) -> PamError
+where
+    H: PamHandle + ?Sized,
+    C: AccountSource + TransactionSender + ?Sized,
+{
+    match check_user(pamh, config, cluster) {
+        Ok(()) => PamError::SUCCESS,
+        Err(AuthError::Lookup(LookupError::Rpc(e))) if config.fail_mode == FailMode::Open => {
+            syslog::warning(&format!("cluster unreachable, failing open: {}", e));
+            PamError::SUCCESS
+        }
+        Err(e) => {
+            syslog::err(&format!("authentication failed: {}", e));
+            PamError::AUTH_ERR
+        }
+    }
+}
+
+fn acct_mgmt<H, S>(pamh: &H, config: &Config, source: &S) -> PamError
+where
+    H: PamHandle + ?Sized,
+    S: AccountSource + ?Sized,
+{
+    let e = match check_account(pamh, config, source) {
+        Ok(_) => return PamError::SUCCESS,
+        Err(AuthError::Lookup(LookupError::Rpc(e))) if config.fail_mode == FailMode::Open => {
+            syslog::warning(&format!("cluster unreachable, failing open: {}", e));
+            return PamError::SUCCESS;
+        }
+        Err(e) => e,
+    };
+    syslog::err(&format!("account check failed: {}", e));
+    match e {
+        AuthError::Expired(_) => PamError::ACCT_EXPIRED,
+        AuthError::UnknownUser(_) => PamError::USER_UNKNOWN,
+        AuthError::NotAuthenticated(_)
+        | AuthError::NotInAccessList(_)
+        | AuthError::NotYetValid(_)
+        | AuthError::WrongHost(_)
+        | AuthError::Denied(_)
+        | AuthError::Revoked(_)
+        | AuthError::InsufficientRole { .. }
+        | AuthError::GroupCycle(_)
+        | AuthError::GroupTooDeep(_) => PamError::PERM_DENIED,
+        _ => PamError::AUTH_ERR,
+    }
+}
+
+fn load_config(args: &[String]) -> Option<Config> {
+    match Config::from_args(args) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            syslog::err(&format!("bad module arguments: {}", e));
+            None
+        }
+    }
+}
+
 impl PamServiceModule for PamTime {
     fn authenticate(pamh: Pam, _: PamFlag, args: Vec<String>) -> PamError {
-        println!("Checking with solana");
-        // let hour = 4;
-        // if hour == 4 {
-        //     // Only allow authentication when it's 4 AM
-        //     PamError::SUCCESS
-        // } else {
-        //     PamError::AUTH_ERR
-        // }
-        PamError::SUCCESS
+        match load_config(&args) {
+            Some(config) => authenticate(&pamh, &config, &RpcClient::new(&config)),
+            None => PamError::SERVICE_ERR,
+        }
+    }
+
+    fn acct_mgmt(pamh: Pam, _: PamFlag, args: Vec<String>) -> PamError {
+        match load_config(&args) {
+            Some(config) => acct_mgmt(&pamh, &config, &RpcClient::new(&config)),
+            None => PamError::SERVICE_ERR,
+        }
+    }
+
+    fn open_session(pamh: Pam, _: PamFlag, args: Vec<String>) -> PamError {
+        match load_config(&args) {
+            Some(config) => {
+                session::open_session(&pamh, &config, &RpcClient::new(&config), auth::local_time())
+            }
+            None => PamError::SERVICE_ERR,
+        }
+    }
+
+    fn close_session(pamh: Pam, _: PamFlag, args: Vec<String>) -> PamError {
+        match load_config(&args) {
+            Some(config) => {
+                session::close_session(&pamh, &config, &RpcClient::new(&config), auth::local_time())
+            }
+            None => PamError::SERVICE_ERR,
+        }
     }
 }
 
 pamsm::pam_module!(PamTime);
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{keypair, write_keypair_file, Conversation, Fixture, FixtureSource};
+    use solana_pam_shared::instructions::{
+        find_access_list_address, find_group_address, find_revocation_address,
+    };
+    use solana_pam_shared::state::{AccessEntry, GroupMember, HostScope};
+    use solana_program::hash::Hash;
+    use std::fs;
+
+    /// A deployment read at cluster time `now`: alice, bob and dave have their
+    /// own lists, bob is revoked, dave may only use sudo, and the shared
+    /// account deploy lets carol in, as does release through the ops group.
+    /// Grants run out at 2000.
+    fn deployment(now: i64, options: &[String]) -> (FixtureSource, Config) {
+        let (_, alice) = keypair(1);
+        let (_, bob) = keypair(2);
+        let (_, carol) = keypair(3);
+        let (_, dave) = keypair(4);
+        let until = |pubkey| AccessEntry {
+            expires_at: Some(2_000),
+            ..AccessEntry::new(pubkey)
+        };
+        let mut fixture = Fixture::new();
+        for (name, pubkey) in &[("alice", alice), ("bob", bob), ("dave", dave)] {
+            fixture.add_username(name, pubkey);
+        }
+        fixture.add_entries(&alice, vec![until(alice)]);
+        fixture.add_list(&bob, vec![bob]);
+        fixture.add_revocation(&bob);
+        fixture.add_entries(
+            &dave,
+            vec![AccessEntry {
+                hosts: HostScope::Service("sudo".to_string()),
+                role: Role::Sudo,
+                ..until(dave)
+            }],
+        );
+        fixture.add_policy("deploy", vec![until(carol)]);
+        let ops = fixture.add_group("ops", vec![GroupMember::Key(carol)]);
+        fixture.add_policy("release", vec![AccessEntry::group(ops)]);
+        fixture.set_clock(now);
+        let (source, program_id, program_data) = fixture.source();
+        let mut args = vec![
+            format!("program_id={}", program_id),
+            format!("program_data={}", program_data),
+            "user_map=/nonexistent/solana-pam/users".to_string(),
+            "username_registry=true".to_string(),
+        ];
+        args.extend_from_slice(options);
+        (source, Config::from_args(&args).unwrap())
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let (source, config) = deployment(1_000, &[]);
+        let ((alice_keypair, alice), (bob_keypair, _), (carol_keypair, carol)) =
+            (keypair(1), keypair(2), keypair(3));
+
+        let conversation = Conversation::new("alice")
+            .service("sshd")
+            .signing_with(&alice_keypair);
+        assert!(matches!(
+            authenticate(&conversation, &config, &source),
+            PamError::SUCCESS
+        ));
+        let prompts = conversation.prompts.borrow();
+        assert_eq!(prompts.len(), 1);
+        assert!(prompts[0].starts_with(&format!("Sign this challenge with {}:\n", alice)));
+        assert_eq!(
+            handle::authenticated_key(&conversation, "alice"),
+            Some(alice)
+        );
+
+        // Any key in a shared account's policy may answer for it
+        let conversation = Conversation::new("deploy").signing_with(&carol_keypair);
+        assert!(matches!(
+            authenticate(&conversation, &config, &source),
+            PamError::SUCCESS
+        ));
+        assert_eq!(
+            handle::authenticated_key(&conversation, "deploy"),
+            Some(carol)
+        );
+
+        for conversation in &[
+            // Signed with someone else's key
+            Conversation::new("alice").signing_with(&bob_keypair),
+            // A revoked key
+            Conversation::new("bob").signing_with(&bob_keypair),
+            // No answer at all
+            Conversation::new("alice"),
+            Conversation::new("nobody").signing_with(&alice_keypair),
+        ] {
+            assert!(matches!(
+                authenticate(conversation, &config, &source),
+                PamError::AUTH_ERR
+            ));
+            assert_eq!(
+                handle::authenticated_key(conversation, conversation.user.as_ref().unwrap()),
+                None
+            );
+        }
+        assert!(source.sent.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_authenticate_fails_open_only_once_signed() {
+        let (mut source, config) = deployment(1_000, &["fail_mode=open".to_string()]);
+        let program_id = config.program_id.unwrap();
+        let ((alice_keypair, alice), (carol_keypair, _), (stranger_keypair, _)) =
+            (keypair(1), keypair(3), keypair(5));
+        source.make_unreachable(find_access_list_address(&program_id, &alice).0);
+        source.make_unreachable(find_group_address(&program_id, "ops").0);
+
+        // Alice's signature verified before her list could not be read
+        let conversation = Conversation::new("alice").signing_with(&alice_keypair);
+        assert!(matches!(
+            authenticate(&conversation, &config, &source),
+            PamError::SUCCESS
+        ));
+        assert!(matches!(
+            authenticate(&Conversation::new("alice"), &config, &source),
+            PamError::AUTH_ERR
+        ));
+
+        // Without the group there is no telling whose signature to accept
+        for keypair in &[&carol_keypair, &stranger_keypair] {
+            let conversation = Conversation::new("release").signing_with(keypair);
+            assert!(matches!(
+                authenticate(&conversation, &config, &source),
+                PamError::AUTH_ERR
+            ));
+            assert_eq!(handle::authenticated_key(&conversation, "release"), None);
+        }
+
+        // Nor is a key whose revocation cannot be checked, at either stage
+        source.make_unreachable(find_revocation_address(&program_id, &alice).0);
+        assert!(matches!(
+            acct_mgmt(&conversation, &config, &source),
+            PamError::AUTH_ERR
+        ));
+        let conversation = Conversation::new("alice").signing_with(&alice_keypair);
+        assert!(matches!(
+            authenticate(&conversation, &config, &source),
+            PamError::AUTH_ERR
+        ));
+    }
+
+    #[test]
+    fn test_authenticate_records_login() {
+        let (host, _) = keypair(9);
+        let host_key = write_keypair_file(&host, "auth-host");
+        let (source, config) = deployment(
+            1_000,
+            &[
+                format!("host_key={}", host_key),
+                "record_logins=true".to_string(),
+            ],
+        );
+        let (alice_keypair, alice) = keypair(1);
+
+        let conversation = Conversation::new("alice")
+            .service("sshd")
+            .signing_with(&alice_keypair);
+        assert!(matches!(
+            authenticate(&conversation, &config, &source),
+            PamError::SUCCESS
+        ));
+        let login = attest::LoginAttestation {
+            program_id: config.program_id.as_ref().unwrap(),
+            program_data: config.program_data.as_ref().unwrap(),
+            user: &alice,
+            service: Some("sshd"),
+            rhost: None,
+        };
+        assert_eq!(
+            source.sent.borrow().as_slice(),
+            &[attest::login_transaction(
+                &login,
+                &host,
+                Hash::new_from_array([5; 32])
+            )]
+        );
+        fs::remove_file(&host_key).unwrap();
+    }
+
+    #[test]
+    fn test_acct_mgmt() {
+        let (source, config) = deployment(1_000, &[]);
+        let ((alice_keypair, _), (carol_keypair, _)) = (keypair(1), keypair(3));
+
+        // Personal accounts are checked with their own key, whichever module
+        // authenticated them
+        let conversation = Conversation::new("alice").service("sshd");
+        assert!(matches!(
+            acct_mgmt(&conversation, &config, &source),
+            PamError::SUCCESS
+        ));
+        assert!(matches!(
+            acct_mgmt(&Conversation::new("bob"), &config, &source),
+            PamError::PERM_DENIED
+        ));
+        let sshd = Conversation::new("dave").service("sshd");
+        assert!(matches!(
+            acct_mgmt(&sshd, &config, &source),
+            PamError::PERM_DENIED
+        ));
+        let sudo = Conversation::new("dave").service("sudo");
+        assert!(matches!(
+            acct_mgmt(&sudo, &config, &source),
+            PamError::SUCCESS
+        ));
+        assert!(matches!(
+            acct_mgmt(&Conversation::new("nobody"), &config, &source),
+            PamError::USER_UNKNOWN
+        ));
+
+        // A shared account needs the key that authenticated for it
+        assert!(matches!(
+            acct_mgmt(&Conversation::new("deploy"), &config, &source),
+            PamError::PERM_DENIED
+        ));
+        let conversation = Conversation::new("deploy").signing_with(&carol_keypair);
+        assert!(matches!(
+            authenticate(&conversation, &config, &source),
+            PamError::SUCCESS
+        ));
+        assert!(matches!(
+            acct_mgmt(&conversation, &config, &source),
+            PamError::SUCCESS
+        ));
+        // A key that signed for another user does not count
+        let mut switched = Conversation::new("alice").signing_with(&alice_keypair);
+        assert!(matches!(
+            authenticate(&switched, &config, &source),
+            PamError::SUCCESS
+        ));
+        switched.user = Some("deploy".to_string());
+        assert!(matches!(
+            acct_mgmt(&switched, &config, &source),
+            PamError::PERM_DENIED
+        ));
+
+        // Once the grants run out, the same logins are expired
+        let (source, config) = deployment(3_000, &[]);
+        assert!(matches!(
+            acct_mgmt(&Conversation::new("alice"), &config, &source),
+            PamError::ACCT_EXPIRED
+        ));
+        let conversation = Conversation::new("deploy");
+        handle::set_authenticated_key(&conversation, "deploy", &keypair(3).1);
+        assert!(matches!(
+            acct_mgmt(&conversation, &config, &source),
+            PamError::ACCT_EXPIRED
+        ));
+    }
+}
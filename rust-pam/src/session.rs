@@ -0,0 +1,286 @@
+//! The session phase: sessions are logged when they open and close, with how
+//! long they lasted, and attested on-chain with `RecordSession` when
+//! `record_sessions` is set
+//!
+//! Neither phase refuses a session. A session the module cannot log or
+//! attest is still opened, as the login was already allowed.
+use crate::attest::{self, SessionAttestation};
+use crate::config::{self, Config};
+use crate::handle::{self, PamHandle};
+use crate::solana::TransactionSender;
+use crate::syslog;
+use pamsm::PamError;
+use solana_program::pubkey::Pubkey;
+
+/// One session as the module logs and attests it
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    pub user: String,
+    /// The key that authenticated through this module, if one did
+    pub pubkey: Option<Pubkey>,
+    pub service: Option<String>,
+    /// Unix time the session opened, by the host's clock, if it is known
+    pub opened_at: Option<i64>,
+    /// Unix time the session closed, once it has
+    pub closed_at: Option<i64>,
+}
+
+impl Session {
+    fn from_handle<H: PamHandle + ?Sized>(pamh: &H) -> Option<Self> {
+        let user = pamh.user()?;
+        Some(Session {
+            pubkey: handle::authenticated_key(pamh, &user),
+            user,
+            service: pamh.service(),
+            opened_at: None,
+            closed_at: None,
+        })
+    }
+
+    /// Seconds from opening to closing, when both are known
+    pub fn duration(&self) -> Option<i64> {
+        Some(self.closed_at? - self.opened_at?)
+    }
+
+    /// The line logged for the session opening, or closing once it has
+    pub fn describe(&self) -> String {
+        let mut message = format!(
+            "session {} for {}",
+            if self.closed_at.is_some() {
+                "closed"
+            } else {
+                "opened"
+            },
+            self.user
+        );
+        if let Some(pubkey) = &self.pubkey {
+            message.push_str(&format!(" with {}", pubkey));
+        }
+        if let Some(service) = &self.service {
+            message.push_str(&format!(" through {}", service));
+        }
+        match (self.closed_at, self.duration()) {
+            (Some(_), Some(duration)) => message.push_str(&format!(" after {}s", duration)),
+            (Some(_), None) => message.push_str(", opened outside this module"),
+            (None, _) => {}
+        }
+        message
+    }
+}
+
+/// Sign and send the `RecordSession` attestation for `session`. A failure is
+/// logged but does not fail the session.
+fn attest_session<C: TransactionSender + ?Sized>(config: &Config, cluster: &C, session: &Session) {
+    let pubkey = match &session.pubkey {
+        Some(pubkey) => pubkey,
+        None => {
+            syslog::warning(&format!(
+                "not attesting the session of {}, no key authenticated through this module",
+                session.user
+            ));
+            return;
+        }
+    };
+    let opened_at = match (session.opened_at, session.closed_at) {
+        (_, None) => None,
+        (Some(opened_at), Some(_)) => Some(opened_at),
+        (None, Some(_)) => {
+            syslog::warning(&format!(
+                "not attesting the end of the session of {}, its start is unknown",
+                session.user
+            ));
+            return;
+        }
+    };
+    // `validate` guarantees these are set when sessions are recorded
+    let (program_id, program_data, host_key_path) = (
+        config.program_id.unwrap(),
+        config.program_data.unwrap(),
+        config.host_key_path.as_ref().unwrap(),
+    );
+    let attestation = SessionAttestation {
+        program_id: &program_id,
+        program_data: &program_data,
+        user: pubkey,
+        service: session.service.as_deref(),
+        opened_at,
+    };
+    let recorded = match config::load_host_keypair(host_key_path) {
+        Ok(host) => attest::record_session(cluster, &attestation, &host).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    match recorded {
+        Ok(signature) if config.debug => syslog::debug(&format!(
+            "attested the session of {} in {}",
+            session.user, signature
+        )),
+        Ok(_) => {}
+        Err(e) => syslog::warning(&format!(
+            "could not attest the session of {}: {}",
+            session.user, e
+        )),
+    }
+}
+
+/// Log the session opening at `now`, keeping the time to work out its
+/// duration when it closes
+pub fn open_session<H, C>(pamh: &H, config: &Config, cluster: &C, now: i64) -> PamError
+where
+    H: PamHandle + ?Sized,
+    C: TransactionSender + ?Sized,
+{
+    let mut session = match Session::from_handle(pamh) {
+        Some(session) => session,
+        None => {
+            syslog::err("could not get the user opening a session");
+            return PamError::SESSION_ERR;
+        }
+    };
+    session.opened_at = Some(now);
+    if !handle::set_session_start(pamh, now) {
+        syslog::warning("could not keep the session start, its duration will not be known");
+    }
+    syslog::info(&session.describe());
+    if config.record_sessions {
+        attest_session(config, cluster, &session);
+    }
+    PamError::SUCCESS
+}
+
+/// Log the session closing at `now`, with how long it lasted
+pub fn close_session<H, C>(pamh: &H, config: &Config, cluster: &C, now: i64) -> PamError
+where
+    H: PamHandle + ?Sized,
+    C: TransactionSender + ?Sized,
+{
+    let mut session = match Session::from_handle(pamh) {
+        Some(session) => session,
+        None => {
+            syslog::err("could not get the user closing a session");
+            return PamError::SESSION_ERR;
+        }
+    };
+    session.opened_at = handle::session_start(pamh);
+    session.closed_at = Some(now);
+    syslog::info(&session.describe());
+    if config.record_sessions {
+        attest_session(config, cluster, &session);
+    }
+    PamError::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        attest::session_transaction,
+        fixture::{keypair, write_keypair_file, Conversation, Fixture},
+    };
+    use solana_program::hash::Hash;
+    use std::fs;
+
+    #[test]
+    fn test_describe_session() {
+        let pubkey = Pubkey::new_unique();
+        let mut session = Session {
+            user: "alice".to_string(),
+            pubkey: Some(pubkey),
+            service: Some("sshd".to_string()),
+            opened_at: Some(1_000),
+            closed_at: None,
+        };
+        assert_eq!(
+            session.describe(),
+            format!("session opened for alice with {} through sshd", pubkey)
+        );
+        session.closed_at = Some(1_600);
+        assert_eq!(session.duration(), Some(600));
+        assert_eq!(
+            session.describe(),
+            format!(
+                "session closed for alice with {} through sshd after 600s",
+                pubkey
+            )
+        );
+
+        let session = Session {
+            user: "bob".to_string(),
+            pubkey: None,
+            service: None,
+            opened_at: None,
+            closed_at: Some(1_600),
+        };
+        assert_eq!(session.duration(), None);
+        assert_eq!(
+            session.describe(),
+            "session closed for bob, opened outside this module"
+        );
+    }
+
+    #[test]
+    fn test_session_phases() {
+        let (host, _) = keypair(11);
+        let (_, alice) = keypair(12);
+        let host_key = write_keypair_file(&host, "session-host");
+        let (source, program_id, program_data) = Fixture::new().source();
+        let config = Config::from_args(&[
+            format!("program_id={}", program_id),
+            format!("program_data={}", program_data),
+            format!("host_key={}", host_key),
+            "record_sessions=true".to_string(),
+        ])
+        .unwrap();
+        let attestation = |opened_at| {
+            let session = SessionAttestation {
+                program_id: &program_id,
+                program_data: &program_data,
+                user: &alice,
+                service: Some("sshd"),
+                opened_at,
+            };
+            session_transaction(&session, &host, Hash::new_from_array([5; 32]))
+        };
+
+        // The key that authenticated is attested at both ends, and the close
+        // carries the start so the duration can be worked out
+        let conversation = Conversation::new("alice").service("sshd");
+        handle::set_authenticated_key(&conversation, "alice", &alice);
+        assert!(matches!(
+            open_session(&conversation, &config, &source, 1_000),
+            PamError::SUCCESS
+        ));
+        assert_eq!(handle::session_start(&conversation), Some(1_000));
+        assert!(matches!(
+            close_session(&conversation, &config, &source, 1_600),
+            PamError::SUCCESS
+        ));
+        assert_eq!(
+            source.sent.borrow().as_slice(),
+            &[attestation(None), attestation(Some(1_000))]
+        );
+
+        // A session no key authenticated through this module is still opened
+        // and logged, but there is nothing to attest, and neither is there
+        // for a close with no recorded start
+        let other = Conversation::new("alice").service("sshd");
+        assert!(matches!(
+            open_session(&other, &config, &source, 2_000),
+            PamError::SUCCESS
+        ));
+        let other = Conversation::new("alice").service("sshd");
+        handle::set_authenticated_key(&other, "alice", &alice);
+        assert!(matches!(
+            close_session(&other, &config, &source, 2_000),
+            PamError::SUCCESS
+        ));
+        assert_eq!(source.sent.borrow().len(), 2);
+
+        let mut nobody = Conversation::new("nobody");
+        nobody.user = None;
+        assert!(matches!(
+            open_session(&nobody, &config, &source, 0),
+            PamError::SESSION_ERR
+        ));
+        fs::remove_file(&host_key).unwrap();
+    }
+}
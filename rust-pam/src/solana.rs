@@ -1,111 +1,554 @@
-
-use solana_program::{
-    instruction::{AccountMeta, Instruction, InstructionError},
-    pubkey::Pubkey,
+//! Reads solana-pam program accounts
+//!
+//! Lookups go through the [`AccountSource`] trait so the authorization path can
+//! run against the JSON-RPC API or against fixture files in tests.
+use crate::config::Config;
+use borsh::BorshDeserialize;
+use serde_json::{json, Value};
+use solana_pam_shared::error::describe_error_code;
+use solana_pam_shared::instructions::{
+    find_access_list_address, find_account_policy_address, find_group_address, find_host_address,
+    find_revocation_address, find_username_address, ProgramData,
 };
-use solana_program_test::*;
-use solana_sdk::{
-    signature::{Keypair, Signer},
-    transaction::{Transaction, TransactionError},
+use solana_pam_shared::state::{
+    is_valid_username, AccessList, AccountPolicy, Group, HostAccount, Revocation, UserAccessList,
+    UsernameAccount,
 };
-use spl_memo::*;
-
-async fn test_memo_signing() {
-    let memo = "🐆".as_bytes();
-    let (mut banks_client, payer, recent_blockhash) = program_test().start().await;
-
-    let keypairs = vec![Keypair::new(), Keypair::new(), Keypair::new()];
-    let pubkeys: Vec<Pubkey> = keypairs.iter().map(|keypair| keypair.pubkey()).collect();
-
-    // Test complete signing
-    let signer_key_refs: Vec<&Pubkey> = pubkeys.iter().collect();
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(memo, &signer_key_refs)], Some(&payer.pubkey()));
-    let mut signers = vec![&payer];
-    for keypair in keypairs.iter() {
-        signers.push(keypair);
-    }
-    transaction.sign(&signers, recent_blockhash);
-    banks_client.process_transaction(transaction).await.unwrap();
-
-    // Test unsigned memo
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(memo, &[])], Some(&payer.pubkey()));
-    transaction.sign(&[&payer], recent_blockhash);
-    banks_client.process_transaction(transaction).await.unwrap();
-
-    // Demonstrate success on signature provided, regardless of specific memo AccountMeta
-    let mut transaction = Transaction::new_with_payer(
-        &[Instruction {
-            program_id: id(),
-            accounts: vec![
-                AccountMeta::new_readonly(keypairs[0].pubkey(), true),
-                AccountMeta::new_readonly(keypairs[1].pubkey(), true),
-                AccountMeta::new_readonly(payer.pubkey(), false),
-            ],
-            data: memo.to_vec(),
-        }],
-        Some(&payer.pubkey()),
-    );
-    transaction.sign(&[&payer, &keypairs[0], &keypairs[1]], recent_blockhash);
-    banks_client.process_transaction(transaction).await.unwrap();
-
-    // Test missing signer(s)
-    let mut transaction = Transaction::new_with_payer(
-        &[Instruction {
-            program_id: id(),
-            accounts: vec![
-                AccountMeta::new_readonly(keypairs[0].pubkey(), true),
-                AccountMeta::new_readonly(keypairs[1].pubkey(), false),
-                AccountMeta::new_readonly(keypairs[2].pubkey(), true),
-            ],
-            data: memo.to_vec(),
-        }],
-        Some(&payer.pubkey()),
-    );
-    transaction.sign(&[&payer, &keypairs[0], &keypairs[2]], recent_blockhash);
-    assert_eq!(
-        banks_client
-            .process_transaction(transaction)
-            .await
-            .unwrap_err()
-            .unwrap(),
-        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
-    );
-
-    let mut transaction = Transaction::new_with_payer(
-        &[Instruction {
-            program_id: id(),
-            accounts: vec![
-                AccountMeta::new_readonly(keypairs[0].pubkey(), false),
-                AccountMeta::new_readonly(keypairs[1].pubkey(), false),
-                AccountMeta::new_readonly(keypairs[2].pubkey(), false),
-            ],
-            data: memo.to_vec(),
-        }],
-        Some(&payer.pubkey()),
-    );
-    transaction.sign(&[&payer], recent_blockhash);
-    assert_eq!(
-        banks_client
-            .process_transaction(transaction)
-            .await
-            .unwrap_err()
-            .unwrap(),
-        TransactionError::InstructionError(0, InstructionError::MissingRequiredSignature)
-    );
-
-    // Test invalid utf-8; demonstrate log
-    let invalid_utf8 = [0xF0, 0x9F, 0x90, 0x86, 0xF0, 0x9F, 0xFF, 0x86];
-    let mut transaction =
-        Transaction::new_with_payer(&[build_memo(&invalid_utf8, &[])], Some(&payer.pubkey()));
-    transaction.sign(&[&payer], recent_blockhash);
-    assert_eq!(
-        banks_client
-            .process_transaction(transaction)
-            .await
-            .unwrap_err()
-            .unwrap(),
-        TransactionError::InstructionError(0, InstructionError::InvalidInstructionData)
-    );
+use solana_program::{hash::Hash, pubkey::Pubkey, sysvar};
+use std::{convert::TryInto, fmt, str::FromStr};
+
+#[derive(Debug)]
+pub enum LookupError {
+    /// The RPC node could not be reached or answered with an error
+    Rpc(String),
+    AccountNotFound(Pubkey),
+    /// The account is not owned by the configured program
+    WrongOwner(Pubkey),
+    /// The account exists but its data is not in the expected layout
+    Decode(String),
+    /// The user has no access list
+    NoAccessList(Pubkey),
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LookupError::Rpc(e) => write!(f, "rpc request failed: {}", e),
+            LookupError::AccountNotFound(pk) => write!(f, "account {} not found", pk),
+            LookupError::WrongOwner(pk) => write!(f, "account {} has the wrong owner", pk),
+            LookupError::Decode(e) => write!(f, "could not decode account data: {}", e),
+            LookupError::NoAccessList(pk) => write!(f, "no access list registered for {}", pk),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Account {
+    pub lamports: u64,
+    pub owner: Pubkey,
+    pub data: Vec<u8>,
+}
+
+impl Account {
+    /// Decode an account in the shape of an RPC `value` object with base64 data
+    pub fn from_json(value: &Value) -> Result<Self, LookupError> {
+        let decode_error = |what: &str| LookupError::Decode(format!("missing {}", what));
+        let lamports = value["lamports"]
+            .as_u64()
+            .ok_or_else(|| decode_error("lamports"))?;
+        let owner = value["owner"]
+            .as_str()
+            .and_then(|o| Pubkey::from_str(o).ok())
+            .ok_or_else(|| decode_error("owner"))?;
+        let data = match (value["data"][0].as_str(), value["data"][1].as_str()) {
+            (Some(data), Some("base64")) => {
+                base64::decode(data).map_err(|e| LookupError::Decode(e.to_string()))?
+            }
+            _ => return Err(decode_error("base64 data")),
+        };
+        Ok(Account {
+            lamports,
+            owner,
+            data,
+        })
+    }
+}
+
+/// The SPL token program, owner of the token accounts token entries are
+/// checked against
+pub const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+pub fn token_program_id() -> Pubkey {
+    Pubkey::from_str(TOKEN_PROGRAM_ID).unwrap()
+}
+
+/// Size of an SPL token account
+pub const TOKEN_ACCOUNT_SIZE: usize = 165;
+
+/// The fields of an SPL token account that token entries depend on
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenAccount {
+    pub mint: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    /// Frozen accounts still hold their tokens, as non-transferable badges do
+    pub initialized: bool,
+}
+
+impl TokenAccount {
+    /// Read the mint, owner, amount and state of a token account
+    pub fn unpack(data: &[u8]) -> Result<Self, LookupError> {
+        if data.len() != TOKEN_ACCOUNT_SIZE {
+            return Err(LookupError::Decode(format!(
+                "token account is {} bytes",
+                data.len()
+            )));
+        }
+        Ok(TokenAccount {
+            mint: Pubkey::new_from_array(data[..32].try_into().unwrap()),
+            owner: Pubkey::new_from_array(data[32..64].try_into().unwrap()),
+            amount: u64::from_le_bytes(data[64..72].try_into().unwrap()),
+            // After the mint, owner, amount and optional delegate
+            initialized: data[108] != 0,
+        })
+    }
+}
+
+/// Somewhere to read accounts from
+pub trait AccountSource {
+    /// Fetch several accounts at once, `None` for each one that does not exist
+    fn get_multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, LookupError>;
+
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, LookupError> {
+        Ok(self
+            .get_multiple_accounts(std::slice::from_ref(pubkey))?
+            .pop()
+            .flatten())
+    }
+
+    /// Fetch the accounts holding `mint` for `owner`
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Vec<Account>, LookupError>;
+}
+
+/// Somewhere to submit signed transactions
+pub trait TransactionSender {
+    /// A blockhash recent enough for a new transaction
+    fn latest_blockhash(&self) -> Result<Hash, LookupError>;
+
+    /// Submit a transaction in wire format, returning its base58 signature
+    fn send_transaction(&self, transaction: &[u8]) -> Result<String, LookupError>;
+}
+
+/// [`AccountSource`] speaking Solana JSON-RPC over blocking HTTP
+pub struct RpcClient {
+    agent: ureq::Agent,
+    url: String,
+    commitment: &'static str,
+}
+
+impl RpcClient {
+    pub fn new(config: &Config) -> Self {
+        RpcClient {
+            agent: ureq::AgentBuilder::new().timeout(config.timeout).build(),
+            url: config.rpc_url.clone(),
+            commitment: config.commitment.as_str(),
+        }
+    }
+
+    /// Make a JSON-RPC request, returning the `value` of its result
+    fn call(&self, method: &str, params: Value) -> Result<Value, LookupError> {
+        Ok(self.call_result(method, params)?["value"].take())
+    }
+
+    fn call_result(&self, method: &str, params: Value) -> Result<Value, LookupError> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let mut response: Value = self
+            .agent
+            .post(&self.url)
+            .send_json(request)
+            .map_err(|e| LookupError::Rpc(e.to_string()))?
+            .into_json()
+            .map_err(|e| LookupError::Rpc(e.to_string()))?;
+        if let Some(err) = response.get("error") {
+            return Err(LookupError::Rpc(describe_rpc_error(err)));
+        }
+        Ok(response["result"].take())
+    }
+
+    fn options(&self) -> Value {
+        json!({ "encoding": "base64", "commitment": self.commitment })
+    }
+}
+
+/// Describe a JSON-RPC error, decoding a failed instruction's program error code
+pub fn describe_rpc_error(err: &Value) -> String {
+    let instruction_error = &err["data"]["err"]["InstructionError"];
+    if let Some(code) = instruction_error[1]["Custom"].as_u64() {
+        return format!(
+            "instruction {} failed: {}",
+            instruction_error[0],
+            describe_error_code(code as u32)
+        );
+    }
+    match err["message"].as_str() {
+        Some(message) => message.to_string(),
+        None => err.to_string(),
+    }
+}
+
+fn account_or_none(value: &Value) -> Result<Option<Account>, LookupError> {
+    if value.is_null() {
+        Ok(None)
+    } else {
+        Account::from_json(value).map(Some)
+    }
+}
+
+impl AccountSource for RpcClient {
+    fn get_multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, LookupError> {
+        let keys: Vec<String> = pubkeys.iter().map(|pk| pk.to_string()).collect();
+        let value = self.call("getMultipleAccounts", json!([keys, self.options()]))?;
+        let values = value
+            .as_array()
+            .filter(|values| values.len() == pubkeys.len())
+            .ok_or_else(|| {
+                LookupError::Rpc("malformed getMultipleAccounts response".to_string())
+            })?;
+        values.iter().map(account_or_none).collect()
+    }
+
+    fn get_account(&self, pubkey: &Pubkey) -> Result<Option<Account>, LookupError> {
+        let value = self.call(
+            "getAccountInfo",
+            json!([pubkey.to_string(), self.options()]),
+        )?;
+        account_or_none(&value)
+    }
+
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        mint: &Pubkey,
+    ) -> Result<Vec<Account>, LookupError> {
+        let value = self.call(
+            "getTokenAccountsByOwner",
+            json!([
+                owner.to_string(),
+                { "mint": mint.to_string() },
+                self.options()
+            ]),
+        )?;
+        let values = value.as_array().ok_or_else(|| {
+            LookupError::Rpc("malformed getTokenAccountsByOwner response".to_string())
+        })?;
+        values
+            .iter()
+            .map(|value| Account::from_json(&value["account"]))
+            .collect()
+    }
+}
+
+impl TransactionSender for RpcClient {
+    fn latest_blockhash(&self) -> Result<Hash, LookupError> {
+        let value = self.call(
+            "getLatestBlockhash",
+            json!([{ "commitment": self.commitment }]),
+        )?;
+        value["blockhash"]
+            .as_str()
+            .and_then(|hash| Hash::from_str(hash).ok())
+            .ok_or_else(|| LookupError::Rpc("malformed getLatestBlockhash response".to_string()))
+    }
+
+    fn send_transaction(&self, transaction: &[u8]) -> Result<String, LookupError> {
+        let options = json!({ "encoding": "base64", "preflightCommitment": self.commitment });
+        let value = self.call_result(
+            "sendTransaction",
+            json!([base64::encode(transaction), options]),
+        )?;
+        value
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| LookupError::Rpc("malformed sendTransaction response".to_string()))
+    }
+}
+
+/// Fetch an account that must exist and be owned by `program_id`
+pub fn get_program_account<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    address: &Pubkey,
+) -> Result<Account, LookupError> {
+    let account = source
+        .get_account(address)?
+        .ok_or(LookupError::AccountNotFound(*address))?;
+    if account.owner != *program_id {
+        return Err(LookupError::WrongOwner(*address));
+    }
+    Ok(account)
+}
+
+/// Look up `user`'s access list at its program-derived address
+///
+/// Lists created before access lists moved to program-derived addresses are
+/// only recorded in the program state's map, so when `program_data` is given
+/// it is consulted as a fallback. Lists still in the headerless format are
+/// read as `AccessList::migrate` would convert them.
+pub fn fetch_access_list<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    program_data: Option<&Pubkey>,
+    user: &Pubkey,
+) -> Result<UserAccessList, LookupError> {
+    let (list_address, _) = find_access_list_address(program_id, user);
+    let list_account = get_program_account(source, program_id, &list_address);
+    let list_account = match (list_account, program_data) {
+        (Err(LookupError::AccountNotFound(_)), Some(program_data)) => {
+            let list_address = legacy_access_list_address(source, program_id, program_data, user)?;
+            get_program_account(source, program_id, &list_address)?
+        }
+        (Err(LookupError::AccountNotFound(_)), None) => {
+            return Err(LookupError::NoAccessList(*user))
+        }
+        (result, _) => result?,
+    };
+    let access_list = AccessList::migrate(&list_account.data, *user)
+        .map_err(|e| LookupError::Decode(e.to_string()))?;
+    if access_list.owner != *user {
+        return Err(LookupError::Decode(format!(
+            "access list belongs to {}",
+            access_list.owner
+        )));
+    }
+    Ok(access_list.entries)
+}
+
+/// Look up the account registered for host key `host`, if there is one
+pub fn fetch_host<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    host: &Pubkey,
+) -> Result<Option<HostAccount>, LookupError> {
+    let (host_address, _) = find_host_address(program_id, host);
+    let host_account = match get_program_account(source, program_id, &host_address) {
+        Ok(account) => account,
+        Err(LookupError::AccountNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let host_account =
+        HostAccount::unpack(&host_account.data).map_err(|e| LookupError::Decode(e.to_string()))?;
+    if host_account.host != *host {
+        return Err(LookupError::Decode(format!(
+            "host account belongs to {}",
+            host_account.host
+        )));
+    }
+    Ok(Some(host_account))
+}
+
+/// Look up the revocation of `pubkey`, if it has been revoked
+pub fn fetch_revocation<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    pubkey: &Pubkey,
+) -> Result<Option<Revocation>, LookupError> {
+    let (address, _) = find_revocation_address(program_id, pubkey);
+    let account = match get_program_account(source, program_id, &address) {
+        Ok(account) => account,
+        Err(LookupError::AccountNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let revocation =
+        Revocation::unpack(&account.data).map_err(|e| LookupError::Decode(e.to_string()))?;
+    if revocation.pubkey != *pubkey {
+        return Err(LookupError::Decode(format!(
+            "revocation account records {}",
+            revocation.pubkey
+        )));
+    }
+    Ok(Some(revocation))
+}
+
+/// Look up the key that claimed `username` in the program's registry
+pub fn fetch_username_owner<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    username: &str,
+) -> Result<Option<Pubkey>, LookupError> {
+    // Names that could never be claimed, some too long to derive an address from
+    if !is_valid_username(username) {
+        return Ok(None);
+    }
+    let (address, _) = find_username_address(program_id, username);
+    let account = match get_program_account(source, program_id, &address) {
+        Ok(account) => account,
+        Err(LookupError::AccountNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let record =
+        UsernameAccount::unpack(&account.data).map_err(|e| LookupError::Decode(e.to_string()))?;
+    if record.username != username {
+        return Err(LookupError::Decode(format!(
+            "username account records {}",
+            record.username
+        )));
+    }
+    Ok(Some(record.owner))
+}
+
+/// Look up the policy for unix account `account`, if it has one
+pub fn fetch_account_policy<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    account: &str,
+) -> Result<Option<AccountPolicy>, LookupError> {
+    if !is_valid_username(account) {
+        return Ok(None);
+    }
+    let (address, _) = find_account_policy_address(program_id, account);
+    let policy_account = match get_program_account(source, program_id, &address) {
+        Ok(policy_account) => policy_account,
+        Err(LookupError::AccountNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let policy = AccountPolicy::unpack(&policy_account.data)
+        .map_err(|e| LookupError::Decode(e.to_string()))?;
+    if policy.account != account {
+        return Err(LookupError::Decode(format!(
+            "account policy is for {}",
+            policy.account
+        )));
+    }
+    Ok(Some(policy))
+}
+
+/// Read the group account at `address`, if there is one
+pub fn fetch_group<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    address: &Pubkey,
+) -> Result<Option<Group>, LookupError> {
+    let account = match get_program_account(source, program_id, address) {
+        Ok(account) => account,
+        Err(LookupError::AccountNotFound(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let group = Group::unpack(&account.data).map_err(|e| LookupError::Decode(e.to_string()))?;
+    if find_group_address(program_id, &group.name).0 != *address {
+        return Err(LookupError::Decode(format!(
+            "group account is for {}",
+            group.name
+        )));
+    }
+    Ok(Some(group))
+}
+
+/// Total amount of `mint` held by `owner` across its token accounts
+pub fn fetch_token_balance<S: AccountSource + ?Sized>(
+    source: &S,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Result<u64, LookupError> {
+    let token_program = token_program_id();
+    let mut balance: u64 = 0;
+    for account in source.get_token_accounts_by_owner(owner, mint)? {
+        if account.owner != token_program {
+            continue;
+        }
+        let token = TokenAccount::unpack(&account.data)?;
+        if token.initialized && token.mint == *mint && token.owner == *owner {
+            balance = balance.saturating_add(token.amount);
+        }
+    }
+    Ok(balance)
+}
+
+/// Read the cluster's unix time from the Clock sysvar
+pub fn get_cluster_time<S: AccountSource + ?Sized>(source: &S) -> Result<i64, LookupError> {
+    let clock = sysvar::clock::id();
+    let account = source
+        .get_account(&clock)?
+        .ok_or(LookupError::AccountNotFound(clock))?;
+    // Clock is bincode encoded: slot, epoch_start_timestamp, epoch,
+    // leader_schedule_epoch, then unix_timestamp, all 8 bytes wide
+    let mut timestamp = [0u8; 8];
+    match account.data.get(32..40) {
+        Some(bytes) => timestamp.copy_from_slice(bytes),
+        None => return Err(LookupError::Decode("clock sysvar is too short".to_string())),
+    }
+    Ok(i64::from_le_bytes(timestamp))
+}
+
+fn legacy_access_list_address<S: AccountSource + ?Sized>(
+    source: &S,
+    program_id: &Pubkey,
+    program_data: &Pubkey,
+    user: &Pubkey,
+) -> Result<Pubkey, LookupError> {
+    let state_account = get_program_account(source, program_id, program_data)?;
+    // The state account is larger than the serialized map, so trailing bytes are expected
+    let state = ProgramData::deserialize(&mut state_account.data.as_slice())
+        .map_err(|e| LookupError::Decode(e.to_string()))?;
+    state
+        .user_access_map
+        .get(&user.to_bytes())
+        .map(|k| Pubkey::new_from_array(*k))
+        .ok_or(LookupError::NoAccessList(*user))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::FixtureSource;
+
+    #[test]
+    fn test_describe_rpc_error() {
+        let err = json!({
+            "code": -32002,
+            "message": "Transaction simulation failed: Error processing Instruction 0: custom program error: 0x70",
+            "data": { "err": { "InstructionError": [0, { "Custom": 112 }] } },
+        });
+        assert_eq!(
+            describe_rpc_error(&err),
+            "instruction 0 failed: Signer does not own the access list (error 112)"
+        );
+
+        let err = json!({ "code": -32602, "message": "Invalid param: WrongSize" });
+        assert_eq!(describe_rpc_error(&err), "Invalid param: WrongSize");
+    }
+
+    #[test]
+    fn test_get_cluster_time() {
+        let mut data = vec![0; 40];
+        data[32..].copy_from_slice(&1_620_000_000i64.to_le_bytes());
+        let clock = Account {
+            lamports: 1,
+            owner: sysvar::id(),
+            data,
+        };
+        let fixture = FixtureSource::to_json(&[(sysvar::clock::id(), clock)]);
+        let source = FixtureSource::from_json(&fixture).unwrap();
+        assert_eq!(get_cluster_time(&source).unwrap(), 1_620_000_000);
+
+        let source = FixtureSource::default();
+        assert!(matches!(
+            get_cluster_time(&source),
+            Err(LookupError::AccountNotFound(_))
+        ));
+    }
 }
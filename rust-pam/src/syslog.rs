@@ -0,0 +1,32 @@
+//! Minimal syslog output under the `authpriv` facility
+use std::{ffi::CString, os::raw::c_int};
+
+fn log(priority: c_int, message: &str) {
+    let message = match CString::new(format!("pam_sol: {}", message)) {
+        Ok(message) => message,
+        Err(_) => return,
+    };
+    unsafe {
+        libc::syslog(
+            libc::LOG_AUTHPRIV | priority,
+            b"%s\0".as_ptr() as *const libc::c_char,
+            message.as_ptr(),
+        );
+    }
+}
+
+pub fn err(message: &str) {
+    log(libc::LOG_ERR, message)
+}
+
+pub fn warning(message: &str) {
+    log(libc::LOG_WARNING, message)
+}
+
+pub fn info(message: &str) {
+    log(libc::LOG_INFO, message)
+}
+
+pub fn debug(message: &str) {
+    log(libc::LOG_DEBUG, message)
+}